@@ -1,4 +1,8 @@
 fn main() {
+    // cc emits rerun-if-env-changed lines, which turns off cargo's default
+    // rerun-on-any-change behavior, so the C sources have to be listed here.
+    println!("cargo:rerun-if-changed=src/vm.h");
+    println!("cargo:rerun-if-changed=src/vm.c");
     cc::Build::new()
         .file("src/vm.h")
         .file("src/vm.c")
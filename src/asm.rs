@@ -0,0 +1,297 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::str::FromStr;
+
+use crate::header::*;
+
+/// A function's label, visibility, and declaration and body ops, with where each op is in the text.
+type Func = (Label, Visibility, Vec<(Op1, SourceLoc)>, Vec<(Op1, SourceLoc)>);
+
+enum Section {
+    Decl,
+    Body,
+    Done,
+}
+
+/// Assemble a program from text, producing the same structures as `parse::go`.
+/// The text is a list of functions in label order, each introduced by a `func L:` line.
+/// Then come its forward declaration's ops, ending in `lced`, `export a b`, or `import a b`,
+/// and then, unless it's imported, its body, ending in a terminator like `halt` or `call`.
+/// There's one op per line, written the way `pretty` prints it, and `;` starts a comment.
+/// A `bytes n...` line appends bytes to the data section.
+/// The debug info gives the line and column of every op, with a file id of 0.
+pub fn go(src: &str) -> Result<ParsedProgram, Error> {
+    let mut data_section = vec![];
+    // each function's declaration and body ops, terminators included
+    let mut funcs: Vec<Func> = vec![];
+    let mut section = Section::Done;
+    for (i, line) in src.lines().enumerate() {
+        let code = line.split(';').next().unwrap_or("");
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let Some(mnemonic) = words.first() else {
+            continue;
+        };
+        let loc = SourceLoc {
+            file_id: 0,
+            line: i as u32 + 1,
+            col: (code.len() - code.trim_start().len()) as u32 + 1,
+        };
+        let located = |e: Error| Error::Located(loc, Box::new(e));
+        if let [_, label] = words[..] {
+            if *mnemonic == "func" && label.ends_with(':') {
+                if let (Some((label, ..)), Section::Decl | Section::Body) = (funcs.last(), &section) {
+                    return Err(located(Error::SyntaxErrorUnterminatedFunc(*label)));
+                }
+                let label = label[..label.len() - 1]
+                    .parse()
+                    .map_err(|_| located(Error::SyntaxErrorBadOperand(words.join(" "))))?;
+                if label != funcs.len() as Label {
+                    return Err(located(Error::SyntaxErrorLabelOutOfOrder(funcs.len() as Label, label)));
+                }
+                funcs.push((label, Visibility::Local, vec![], vec![]));
+                section = Section::Decl;
+                continue;
+            }
+        }
+        if *mnemonic == "bytes" {
+            for word in &words[1..] {
+                let byte = word.parse().map_err(|_| located(Error::SyntaxErrorBadOperand(words.join(" "))))?;
+                data_section.push(byte);
+            }
+            continue;
+        }
+        let op = op(&words).map_err(located)?;
+        let Some((_, visibility, decl, body)) = funcs.last_mut() else {
+            return Err(located(Error::SyntaxErrorOpOutsideFunction));
+        };
+        match section {
+            Section::Decl => {
+                decl.push((op, loc));
+                match op {
+                    Op1::Lced => section = Section::Body,
+                    Op1::Export(a, b) => {
+                        *visibility = Visibility::Export(a, b);
+                        section = Section::Body;
+                    }
+                    Op1::Import(a, b) => {
+                        *visibility = Visibility::Import(a, b);
+                        section = Section::Done;
+                    }
+                    _ => {}
+                }
+            }
+            Section::Body => {
+                body.push((op, loc));
+                if matches!(
+                    op,
+                    Op1::Call | Op1::CallNZ | Op1::Halt | Op1::CheckCast(..) | Op1::Unreachable | Op1::Case(_)
+                ) {
+                    section = Section::Done;
+                }
+            }
+            Section::Done => return Err(located(Error::SyntaxErrorOpOutsideFunction)),
+        }
+    }
+    if let (Some((label, ..)), Section::Decl | Section::Body) = (funcs.last(), &section) {
+        return Err(Error::SyntaxErrorUnterminatedFunc(*label));
+    }
+
+    // positions count every op in the order the bytecode would have them: all the declarations, then all the bodies
+    let mut debug_info = DebugInfo::new();
+    let mut pos = 0;
+    let mut forward_decs = vec![];
+    for (label, visibility, decl, _) in &funcs {
+        let ops = decl[..decl.len() - 1].iter().map(|(op, _)| *op).collect();
+        forward_decs.push(ForwardDec::Func(*label, pos, *visibility, ops));
+        for (_, loc) in decl {
+            debug_info.insert(pos, *loc);
+            pos += 1;
+        }
+    }
+    let mut stmts = vec![];
+    for (label, visibility, _, body) in &funcs {
+        if let Visibility::Import(..) = visibility {
+            continue;
+        }
+        stmts.push(Stmt1::Func(*label, pos, body.iter().map(|(op, _)| *op).collect()));
+        for (_, loc) in body {
+            debug_info.insert(pos, *loc);
+            pos += 1;
+        }
+    }
+    Ok((data_section, forward_decs, stmts, debug_info))
+}
+
+fn operand<T: FromStr>(words: &[&str], i: usize) -> Result<T, Error> {
+    match words.get(i).map(|word| word.parse()) {
+        Some(Ok(n)) => Ok(n),
+        _ => Err(Error::SyntaxErrorBadOperand(words.join(" "))),
+    }
+}
+
+/// The op a line spells out, as the mnemonic followed by its operands.
+fn op(words: &[&str]) -> Result<Op1, Error> {
+    let (op, arity) = match words[0] {
+        "unique" => (Op1::Unique, 0),
+        "handle" => (Op1::Handle, 0),
+        "i32" => (Op1::I32, 0),
+        "tuple" => (Op1::Tuple(operand(words, 1)?), 1),
+        "some" => (Op1::Some, 0),
+        "all" => (Op1::All, 0),
+        "rgn" => (Op1::Rgn, 0),
+        "end" => (Op1::End, 0),
+        "app" => (Op1::App, 0),
+        "func" => (Op1::Func(operand(words, 1)?), 1),
+        "ctget" => (Op1::CTGet(operand(words, 1)?), 1),
+        "lced" => (Op1::Lced, 0),
+        "unpack" => (Op1::Unpack, 0),
+        "get" => (Op1::Get(operand(words, 1)?), 1),
+        "init" => (Op1::Init(operand(words, 1)?), 1),
+        "malloc" => (Op1::Malloc, 0),
+        "proj" => (Op1::Proj(operand(words, 1)?), 1),
+        "call" => (Op1::Call, 0),
+        "lit" => {
+            // a number that's a valid operand, just too big, gets a more specific error
+            let value: i64 = operand(words, 1)?;
+            (Op1::Lit(value.try_into().map_err(|_| Error::LiteralOutOfRange(value))?), 1)
+        }
+        "global_func" => (Op1::GlobalFunc(operand(words, 1)?), 1),
+        "halt" => (Op1::Halt, 0),
+        "pack" => (Op1::Pack, 0),
+        "size" => (Op1::Size(operand(words, 1)?), 1),
+        "new_rgn" => (Op1::NewRgn(operand(words, 1)?), 1),
+        "free_rgn" => (Op1::FreeRgn, 0),
+        "ptr" => (Op1::Ptr, 0),
+        "deref" => (Op1::Deref, 0),
+        "arr" => (Op1::Arr, 0),
+        "arr_mut" => (Op1::ArrMut, 0),
+        "arr_proj" => (Op1::ArrProj, 0),
+        "add" => (Op1::Add, 0),
+        "mul" => (Op1::Mul, 0),
+        "div" => (Op1::Div, 0),
+        "call_nz" => (Op1::CallNZ, 0),
+        "data" => (Op1::Data(operand(words, 1)?), 1),
+        "data_sec" => (Op1::DataSec, 0),
+        "u8" => (Op1::U8, 0),
+        "copy_n" => (Op1::CopyN, 0),
+        "u8_lit" => (Op1::U8Lit(operand(words, 1)?), 1),
+        "u8_to_i32" => (Op1::U8ToI32, 0),
+        "import" => (Op1::Import(operand(words, 1)?, operand(words, 2)?), 2),
+        "export" => (Op1::Export(operand(words, 1)?, operand(words, 2)?), 2),
+        "modulo" => (Op1::Modulo, 0),
+        "i32_to_u8" => (Op1::I32ToU8, 0),
+        "read" => (Op1::Read(operand(words, 1)?), 1),
+        "write" => (Op1::Write(operand(words, 1)?), 1),
+        "tagged" => (Op1::Tagged, 0),
+        "pack_tagged" => (Op1::PackTagged, 0),
+        "check_cast" => (Op1::CheckCast(operand(words, 1)?, operand(words, 2)?), 2),
+        "unreachable" => (Op1::Unreachable, 0),
+        "reset_rgn" => (Op1::ResetRgn, 0),
+        "rotate" => (Op1::Rotate(operand(words, 1)?), 1),
+        "loop" => (Op1::Loop, 0),
+        "break" => (Op1::Break, 0),
+        "continue" => (Op1::Continue, 0),
+        "loop_end" => (Op1::LoopEnd, 0),
+        "select" => (Op1::Select, 0),
+        "arr_len" => (Op1::ArrLen, 0),
+        "stack_depth" => (Op1::StackDepth, 0),
+        "drop_handle" => (Op1::DropHandle, 0),
+        "mem_copy" => (Op1::MemCopy, 0),
+        "sub" => (Op1::Sub, 0),
+        "rec" => (Op1::Rec, 0),
+        "fold" => (Op1::Fold, 0),
+        "unfold" => (Op1::Unfold, 0),
+        "sum" => (Op1::Sum(operand(words, 1)?), 1),
+        "inj" => (Op1::Inj(operand(words, 1)?), 1),
+        "case" => (Op1::Case(operand(words, 1)?), 1),
+        "i64" => (Op1::I64, 0),
+        "f64" => (Op1::F64, 0),
+        "lit_i64" => (Op1::LitI64(operand(words, 1)?), 1),
+        "lit_f64" => (Op1::LitF64(operand::<f64>(words, 1)?.to_bits()), 1),
+        "i64_to_i32" => (Op1::I64ToI32, 0),
+        "f64_to_i64" => (Op1::F64ToI64, 0),
+        "dup" => (Op1::Dup, 0),
+        "swap" => (Op1::Swap, 0),
+        "pop" => (Op1::Pop, 0),
+        "size_of" => (Op1::SizeOf, 0),
+        "size_add" => (Op1::SizeAdd, 0),
+        "size_mul" => (Op1::SizeMul(operand(words, 1)?), 1),
+        "clean" => (Op1::Clean(operand(words, 1)?), 1),
+        "heap" => (Op1::Heap, 0),
+        "nop" => (Op1::Nop, 0),
+        "assert" => (Op1::Assert, 0),
+        "get_local" => (Op1::GetLocal(operand(words, 1)?), 1),
+        "arr_alloc" => (Op1::ArrAlloc, 0),
+        mnemonic => return Err(Error::SyntaxErrorUnknownMnemonic(mnemonic.to_string())),
+    };
+    if words.len() != arity + 1 {
+        return Err(Error::SyntaxErrorBadOperand(words.join(" ")));
+    }
+    Ok(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_declarations_first() {
+        let src = "
+            bytes 7 8
+            func 0:
+            func 0 ; main
+            lced
+            lit 5
+            global_func 1
+            call
+            func 1:
+            i32
+            func 1
+            lced
+            i32_to_u8
+            halt
+        ";
+        let (data_section, forward_decs, stmts, debug_info) = go(src).unwrap();
+        assert_eq!(data_section, vec![7, 8]);
+        assert!(matches!(&forward_decs[..], [
+            ForwardDec::Func(0, 0, Visibility::Local, decl0),
+            ForwardDec::Func(1, 2, Visibility::Local, decl1),
+        ] if matches!(decl0[..], [Op1::Func(0)]) && matches!(decl1[..], [Op1::I32, Op1::Func(1)])));
+        assert!(matches!(&stmts[..], [
+            Stmt1::Func(0, 5, body0),
+            Stmt1::Func(1, 8, body1),
+        ] if matches!(body0[..], [Op1::Lit(5), Op1::GlobalFunc(1), Op1::Call])
+            && matches!(body1[..], [Op1::I32ToU8, Op1::Halt])));
+        // `lit 5` is the first op of the first body, on the sixth line
+        assert_eq!(debug_info[&5], SourceLoc { file_id: 0, line: 6, col: 13 });
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let located = |src: &str| match go(src) {
+            Err(Error::Located(loc, e)) => (loc.line, *e),
+            _ => panic!("{} assembled", src),
+        };
+        assert!(matches!(located("func 0:\nfunc 0\nlced\nfly\n"), (4, Error::SyntaxErrorUnknownMnemonic(m)) if m == "fly"));
+        assert!(matches!(located("func 0:\nfunc 0\nlced\nlit\n"), (4, Error::SyntaxErrorBadOperand(_))));
+        assert!(matches!(located("func 1:\n"), (1, Error::SyntaxErrorLabelOutOfOrder(0, 1))));
+        assert!(matches!(located("lit 1\n"), (1, Error::SyntaxErrorOpOutsideFunction)));
+        assert!(matches!(located("func 0:\nfunc 0\nfunc 1:\n"), (3, Error::SyntaxErrorUnterminatedFunc(0))));
+        assert!(matches!(go("func 0:\nfunc 0\nlced\n"), Err(Error::SyntaxErrorUnterminatedFunc(0))));
+    }
+
+    #[test]
+    fn literals_are_i32s() {
+        let lit = |n: i64| go(&format!("func 0:\nfunc 0\nlced\nlit {}\nhalt\n", n)).map(|(_, _, stmts, _)| stmts);
+        for n in [i32::MIN, i32::MAX] {
+            assert!(matches!(&lit(n.into()).unwrap()[..], [Stmt1::Func(0, _, body)] if body[..] == [Op1::Lit(n), Op1::Halt]));
+        }
+        for n in [i64::from(i32::MIN) - 1, i64::from(i32::MAX) + 1] {
+            assert!(matches!(lit(n), Err(Error::Located(_, e)) if matches!(*e, Error::LiteralOutOfRange(m) if m == n)));
+        }
+    }
+}
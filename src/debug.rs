@@ -0,0 +1,74 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::header::*;
+
+/// Shrink a program while `predicate` keeps holding, for turning a large failing program into a small reproducer.
+/// Whole functions are removed first, then the instructions of each remaining function.
+/// The predicate should hold for the program it's given.
+pub fn minimize(program: Vec<Stmt1>, predicate: impl Fn(&[Stmt1]) -> bool) -> Vec<Stmt1> {
+    let mut program = ddmin(program, &predicate);
+    for i in 0..program.len() {
+        let Stmt1::Func(label, pos, ops) = program[i].clone();
+        let ops = ddmin(ops, &|ops: &[Op1]| {
+            let mut candidate = program.clone();
+            candidate[i] = Stmt1::Func(label, pos, ops.to_vec());
+            predicate(&candidate)
+        });
+        program[i] = Stmt1::Func(label, pos, ops);
+    }
+    program
+}
+
+/// Delta debugging (ddmin), removing chunks of `items` at finer and finer granularity.
+/// The result is 1-minimal: removing any single item makes the test fail.
+fn ddmin<T: Clone>(mut items: Vec<T>, test: &dyn Fn(&[T]) -> bool) -> Vec<T> {
+    let mut n = 2;
+    while !items.is_empty() {
+        let chunk_size = items.len().div_ceil(n);
+        let reduced = (0..items.len()).step_by(chunk_size).find_map(|start| {
+            let end = (start + chunk_size).min(items.len());
+            let complement = [&items[..start], &items[end..]].concat();
+            test(&complement).then_some(complement)
+        });
+        match reduced {
+            Some(complement) => {
+                items = complement;
+                n = (n - 1).max(2);
+            }
+            None if chunk_size == 1 => break,
+            None => n = (n * 2).min(items.len()),
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify;
+
+    #[test]
+    fn ddmin_is_one_minimal() {
+        let items = ddmin((0..20).collect(), &|items: &[i32]| items.contains(&3) && items.contains(&7));
+        assert_eq!(items, vec![3, 7]);
+    }
+
+    #[test]
+    fn minimized_programs_fail_the_same_way() {
+        let forward_decs = vec![ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        let ops = vec![Op1::Lit(1), Op1::Lit(2), Op1::Add, Op1::U8Lit(4), Op1::LitF64(3), Op1::Halt];
+        let halts_on_f64 = |stmts: &[Stmt1]| {
+            matches!(verify::go_default(vec![], &forward_decs, stmts.to_vec()), Err(Error::TypeErrorHaltStatus(_, Op1::Halt, Type::F64)))
+        };
+        let program = vec![Stmt1::Func(0, 0, ops)];
+        assert!(halts_on_f64(&program));
+        let minimized = minimize(program, halts_on_f64);
+        assert!(halts_on_f64(&minimized));
+        let [Stmt1::Func(0, 0, ops)] = &minimized[..] else { panic!("{:?}", minimized) };
+        assert!(matches!(ops[..], [Op1::LitF64(_), Op1::Halt]), "{:?}", ops);
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::header::*;
+use crate::link::Module;
+
+/// Encode a module as bytecode, the inverse of `parse::go`.
+/// Each declaration is terminated by the `lced`, `export`, or `import` its visibility calls for,
+/// and bodies already end in their terminators.
+/// The debug section is only written if there's debug info, in order of position.
+pub fn go(module: &Module) -> ByteStream {
+    let mut bytes = vec![];
+    bytes.extend((module.data_section.len() as u32).to_le_bytes());
+    bytes.extend(&module.data_section);
+    bytes.extend((module.forward_decs.len() as u32).to_le_bytes());
+    for ForwardDec::Func(_, _, visibility, ops) in &module.forward_decs {
+        for op in ops {
+            bytes.extend(op_to_bytes(op));
+        }
+        bytes.extend(op_to_bytes(&match visibility {
+            Visibility::Local => Op1::Lced,
+            Visibility::Export(a, b) => Op1::Export(*a, *b),
+            Visibility::Import(a, b) => Op1::Import(*a, *b),
+        }));
+    }
+    for Stmt1::Func(_, _, ops) in &module.stmts {
+        for op in ops {
+            bytes.extend(op_to_bytes(op));
+        }
+    }
+    if !module.debug_info.is_empty() {
+        let mut entries: Vec<_> = module.debug_info.iter().collect();
+        entries.sort_by_key(|(pos, _)| **pos);
+        bytes.push(0xFF);
+        bytes.extend((entries.len() as u32).to_le_bytes());
+        for (pos, loc) in entries {
+            for n in [*pos, loc.file_id, loc.line, loc.col] {
+                bytes.extend(n.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+fn op_to_bytes(op: &Op1) -> Vec<u8> {
+    match op {
+        Op1::Unique => vec![0x00],
+        Op1::Handle => vec![0x01],
+        Op1::I32 => vec![0x02],
+        Op1::Tuple(n) => vec![0x03, *n],
+        Op1::Some => vec![0x04],
+        Op1::All => vec![0x05],
+        Op1::Rgn => vec![0x06],
+        Op1::End => vec![0x07],
+        Op1::App => vec![0x08],
+        Op1::Func(n) => vec![0x09, *n],
+        Op1::CTGet(i) => vec![0x0A, *i],
+        Op1::Lced => vec![0x0B],
+        Op1::Unpack => vec![0x0C],
+        Op1::Get(i) => vec![0x0D, *i],
+        Op1::Init(i) => vec![0x0E, *i],
+        Op1::Malloc => vec![0x0F],
+        Op1::Proj(i) => vec![0x10, *i],
+        Op1::Call => vec![0x11],
+        Op1::Lit(lit) => [vec![0x13], lit.to_le_bytes().to_vec()].concat(),
+        Op1::GlobalFunc(label) => [vec![0x14], label.to_le_bytes().to_vec()].concat(),
+        Op1::Halt => vec![0x15],
+        Op1::Pack => vec![0x16],
+        Op1::Size(s) => [vec![0x17], s.to_le_bytes().to_vec()].concat(),
+        Op1::NewRgn(s) => [vec![0x18], s.to_le_bytes().to_vec()].concat(),
+        Op1::FreeRgn => vec![0x19],
+        Op1::Ptr => vec![0x1A],
+        Op1::Deref => vec![0x1B],
+        Op1::Arr => vec![0x1C],
+        Op1::ArrMut => vec![0x1D],
+        Op1::ArrProj => vec![0x1E],
+        Op1::Add => vec![0x1F],
+        Op1::Mul => vec![0x20],
+        Op1::Div => vec![0x21],
+        Op1::CallNZ => vec![0x22],
+        Op1::Data(offset) => [vec![0x23], offset.to_le_bytes().to_vec()].concat(),
+        Op1::DataSec => vec![0x24],
+        Op1::U8 => vec![0x25],
+        Op1::CopyN => vec![0x26],
+        Op1::U8Lit(lit) => vec![0x27, *lit],
+        Op1::U8ToI32 => vec![0x28],
+        Op1::Import(a, b) => [vec![0x29], a.to_le_bytes().to_vec(), b.to_le_bytes().to_vec()].concat(),
+        Op1::Export(a, b) => [vec![0x2A], a.to_le_bytes().to_vec(), b.to_le_bytes().to_vec()].concat(),
+        Op1::Modulo => vec![0x2B],
+        Op1::I32ToU8 => vec![0x2C],
+        Op1::Read(channel) => vec![0x2D, *channel],
+        Op1::Write(channel) => vec![0x2E, *channel],
+        Op1::Tagged => vec![0x2F],
+        Op1::PackTagged => vec![0x30],
+        Op1::CheckCast(f, g) => [vec![0x31], f.to_le_bytes().to_vec(), g.to_le_bytes().to_vec()].concat(),
+        Op1::Unreachable => vec![0x32],
+        Op1::ResetRgn => vec![0x33],
+        Op1::Rotate(i) => vec![0x34, *i],
+        Op1::Loop => vec![0x35],
+        Op1::Break => vec![0x36],
+        Op1::Continue => vec![0x37],
+        Op1::LoopEnd => vec![0x38],
+        Op1::Select => vec![0x39],
+        Op1::ArrLen => vec![0x3A],
+        Op1::StackDepth => vec![0x3B],
+        Op1::DropHandle => vec![0x3C],
+        Op1::MemCopy => vec![0x3D],
+        Op1::Sub => vec![0x3E],
+        Op1::Rec => vec![0x3F],
+        Op1::Fold => vec![0x40],
+        Op1::Unfold => vec![0x41],
+        Op1::Sum(n) => vec![0x42, *n],
+        Op1::Inj(i) => vec![0x43, *i],
+        Op1::Case(n) => vec![0x44, *n],
+        Op1::I64 => vec![0x45],
+        Op1::F64 => vec![0x46],
+        Op1::LitI64(lit) => [vec![0x47], lit.to_le_bytes().to_vec()].concat(),
+        Op1::LitF64(bits) => [vec![0x48], bits.to_le_bytes().to_vec()].concat(),
+        Op1::I64ToI32 => vec![0x49],
+        Op1::F64ToI64 => vec![0x4A],
+        Op1::Dup => vec![0x4B],
+        Op1::Swap => vec![0x4C],
+        Op1::Pop => vec![0x4D],
+        Op1::SizeOf => vec![0x4E],
+        Op1::SizeAdd => vec![0x4F],
+        Op1::SizeMul(n) => vec![0x50, *n],
+        Op1::Clean(n) => vec![0x51, *n],
+        Op1::Heap => vec![0x52],
+        Op1::Nop => vec![0x53],
+        Op1::Assert => vec![0x54],
+        Op1::GetLocal(i) => vec![0x55, *i],
+        Op1::ArrAlloc => vec![0x56],
+    }
+}
+
+/// Encode verified functions as JSON, for tooling that wants to inspect them.
+#[cfg(feature = "serde")]
+pub fn to_json(program: &[Stmt2]) -> String {
+    serde_json::to_string(program).unwrap()
+}
+
+/// Decode functions written by `to_json`.
+#[cfg(feature = "serde")]
+pub fn from_json(json: &str) -> serde_json::Result<Vec<Stmt2>> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::tests::assemble;
+
+    fn decode(bytes: &ByteStream) -> Module {
+        let (data_section, forward_decs, stmts, debug_info) = parse::go(bytes).unwrap();
+        Module { data_section, forward_decs, stmts, debug_info }
+    }
+
+    #[test]
+    fn hand_written_bytecode_round_trips() {
+        let mut bytes = vec![
+            2, 0, 0, 0, 0xAB, 0xCD, // a data section of two bytes
+            2, 0, 0, 0, // two functions
+            0x09, 0, 0x0B, // func 0: ()->0
+            0x02, 0x09, 1, 0x2A, // func 1: (i32)->0, exported as 1 2
+        ];
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend([0x13, 7, 0, 0, 0, 0x14, 1, 0, 0, 0, 0x11, 0x2C, 0x15]);
+        assert_eq!(go(&decode(&bytes)), bytes);
+    }
+
+    #[test]
+    fn assembled_programs_round_trip() {
+        let src = "
+            bytes 1 2 3
+            func 0:
+            func 0
+            lced
+            lit 5
+            global_func 1
+            call
+            func 1:
+            i32
+            func 1
+            import 3 4
+        ";
+        let module = assemble(src);
+        let bytes = go(&module);
+        let decoded = decode(&bytes);
+        assert!(matches!(&decoded.forward_decs[1], ForwardDec::Func(1, _, Visibility::Import(3, 4), _)));
+        // the debug section carries the assembly's source locations
+        assert_eq!(decoded.debug_info, module.debug_info);
+        assert_eq!(go(&decoded), bytes);
+    }
+
+    #[test]
+    fn random_modules_round_trip() {
+        for module in crate::testgen::random_modules(0, 500) {
+            let bytes = go(&module);
+            let decoded = decode(&bytes);
+            assert_eq!(decoded.data_section, module.data_section);
+            assert_eq!(format!("{:?}", decoded.forward_decs), format!("{:?}", module.forward_decs));
+            assert_eq!(format!("{:?}", decoded.stmts), format!("{:?}", module.stmts));
+            assert_eq!(go(&decoded), bytes);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trips() {
+        // regions keep their uniqueness as well as their id
+        let frees = "func 0:\nfunc 0\nlced\nu8_lit 0\nhalt\n\
+                     func 1:\nunique\nrgn\nctget 0\nhandle\nfunc 1\nend\nlced\nfree_rgn\nu8_lit 0\nhalt\n";
+        let program = crate::compile(vec![assemble(frees)]).unwrap();
+        let json = to_json(&program.funcs);
+        assert!(json.contains(r#"{"unique":true,"id":{"Var":[1,"#), "{}", json);
+        assert_eq!(from_json(&json).unwrap(), program.funcs);
+        assert!(from_json("[{}]").is_err());
+    }
+}
@@ -4,10 +4,57 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::fmt;
+
 use crate::header::*;
 use crate::pretty::Pretty;
+use crate::verify::{diff_path, type_eq};
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", msg(self))
+    }
+}
 
-pub fn msg(e: Error) -> String {
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnusedRegion(label, r) => write!(
+                f,
+                "Warning: Function {} is quantified over region {}, but none of its parameters mention it",
+                label,
+                r.pretty()
+            ),
+        }
+    }
+}
+
+/// Where two unequal types first differ, like "at component 2 of the tuple: expected i32, found u8".
+/// This is `None` if they differ at the top, since then the whole types already say it all.
+fn type_diff(expected: &Type, actual: &Type) -> Option<String> {
+    let path = diff_path(expected, actual);
+    if path.is_empty() {
+        return None;
+    }
+    let (_, expected, actual) = path.last().unwrap();
+    let places: Vec<&str> = path.iter().map(|(place, _, _)| place.as_str()).collect();
+    Some(places.join(", ") + ": expected " + &expected.pretty() + ", found " + &actual.pretty())
+}
+
+/// Where two unequal lists of argument types first differ.
+fn args_diff(expected: &[Type], actual: &[Type]) -> Option<String> {
+    if expected.len() != actual.len() {
+        return None;
+    }
+    let (i, (t1, t2)) = expected.iter().zip(actual).enumerate().find(|(_, (t1, t2))| !type_eq(t1, t2))?;
+    let place = "at argument ".to_string() + &i.to_string();
+    Some(match type_diff(t1, t2) {
+        Some(diff) => place + ", " + &diff,
+        None => place + ": expected " + &t1.pretty() + ", found " + &t2.pretty(),
+    })
+}
+
+fn msg(e: &Error) -> String {
     match e {
         Error::SyntaxErrorParamNeeded(pos, op) => {
             format!("Syntax Error: Parameter needed for opcode {:?} at pos {}", op, pos)
@@ -16,7 +63,10 @@ pub fn msg(e: Error) -> String {
             format!("Syntax Error: Unknown opcode {:?} at pos {}", op, pos)
         },
         Error::TypeErrorMainHasArgs => {
-            format!("Type Error: Main function cannot have arguments")
+            "Type Error: Main function cannot have arguments".to_string()
+        },
+        Error::TypeErrorMainPolymorphic(t) => {
+            format!("Type Error: Main function cannot be polymorphic, but its type is {}", t.pretty())
         },
         Error::TypeErrorNonEmptyQuantificationStack(label) => {
             format!("Type Error: Non-empty quantification stack at label {}", label)
@@ -38,6 +88,7 @@ pub fn msg(e: Error) -> String {
         },
         Error::TypeError(pos, op, t1, t2) => {
             format!("Type Error: Expected type {} at pos {} for opcode {} but found {}", t1.pretty(), pos, op.pretty(), t2.pretty())
+                + &type_diff(t1, t2).map(|diff| ". They differ ".to_string() + &diff).unwrap_or_default()
         },
         Error::SizeError(pos, op, s1, s2) => {
             format!("Size Error: Expected size {} at pos {} for opcode {} but found {}", s1, pos, op.pretty(), s2)
@@ -60,6 +111,9 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorGetOutOfRange(pos, i, max) => {
             format!("Type Error: get out of range at pos {}: the stack depth is {} but got {}", pos, max, i)
         },
+        Error::TypeErrorRotateOutOfRange(pos, i, max) => {
+            format!("Type Error: rotate out of range at pos {}: the stack depth is {} but got {}", pos, max, i)
+        },
         Error::TypeErrorInitOutOfRange(pos, i, max) => {
             format!("Type Error: init out of range at pos {}: the stack depth is {} but got {}", pos, max, i)
         },
@@ -86,6 +140,7 @@ pub fn msg(e: Error) -> String {
         },
         Error::TypeErrorCallArgTypesMismatch(pos, ts1, ts2) => {
             format!("Type Error: Call argument types mismatch at pos {}: expected {} but got {}", pos, ts1.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "), ts2.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "))
+                + &args_diff(ts1, ts2).map(|diff| ". They differ ".to_string() + &diff).unwrap_or_default()
         },
         Error::TypeErrorMallocNonTuple(pos, op, t) => {
             format!("Type Error: Expected tuple type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
@@ -105,20 +160,24 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorDoubleInit(pos, op, n) => {
             format!("Type Error: Double init at pos {} for opcode {}: component {} has already been initialized", pos, op.pretty(), n)
         },
-        Error::TypeErrorUninitializedRead(pos, op, n) => {
-            format!("Type Error: Uninitialized read at pos {} for opcode {}: component {} has not been initialized", pos, op.pretty(), n)
+        Error::TypeErrorUninitializedRead(pos, op, n, ts) => {
+            let components = ts.iter().map(|(init, t)| t.pretty() + if *init { "✓" } else { "✗" }).collect::<Vec<String>>().join(", ");
+            format!("Type Error: Uninitialized read at pos {} for opcode {}: component {} has not been initialized in {{{}}}", pos, op.pretty(), n, components)
         },
         Error::TooBigForStack(pos, op, t) => {
             format!("Type Error: Too big for stack at pos {} for opcode {}: {}", pos, op.pretty(), t.pretty())
         },
-        Error::ForwardDeclNotType(t) => {
-            format!("Forward declaration of non-type: {}", t.pretty())
+        Error::ForwardDeclNotType(label, t) => {
+            format!("Type Error: Function {} is declared with type {}, which isn't a function type", label, t.pretty())
+        },
+        Error::ForwardDeclRuntimeOp(label, op) => {
+            format!("Forward declaration of function {} uses runtime opcode: {}", label, op.pretty())
         },
-        Error::ForwardDeclRuntimeOp(op) => {
-            format!("Forward declaration of runtime opcode: {}", op.pretty())
+        Error::ForwardDeclBadStack(label, ctvals) => {
+            format!("Forward declaration of function {} leaves a bad stack: {}", label, ctvals.iter().map(|ctval| ctval.kind().pretty()).collect::<Vec<_>>().join(", "))
         },
-        Error::ForwardDeclBadStack(ctvals) => {
-            format!("Forward declaration of bad stack: {}", ctvals.iter().map(|ctval| ctval.kind().pretty()).collect::<Vec<_>>().join(", "))
+        Error::DeclarationOpInBody(pos, op) => {
+            format!("Syntax Error: opcode {} at pos {} can only end a forward declaration, not appear in a function body", op.pretty(), pos)
         },
         Error::UnknownGlobalFunc(pos, op, label) => {
             format!("Unknown global function at pos {}, opcode {}: {}", pos, op.pretty(), label)
@@ -126,6 +185,12 @@ pub fn msg(e: Error) -> String {
         Error::UnexpectedEOF => {
             "Unexpected end of file".to_string()
         },
+        Error::CannotReadFile(filename, reason) => {
+            format!("File Error: Couldn't read {}: {}", filename, reason)
+        },
+        Error::CannotWriteFile(filename, reason) => {
+            format!("File Error: Couldn't write {}: {}", filename, reason)
+        },
         Error::TypeErrorArrayExpected(pos, op, t) => {
             format!("Type Error: Expected array type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
         },
@@ -143,6 +208,179 @@ pub fn msg(e: Error) -> String {
         },
         Error::UnknownChannel(pos, op, c) => {
             format!("Unknown channel {} at pos {} for opcode {}", c, pos, op.pretty())
+        },
+        Error::TypeErrorTaggedExistentialExpected(pos, op, t) => {
+            format!("Type Error: Expected tagged existential type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
+        Error::TypeErrorUntaggable(pos, op, t) => {
+            format!("Type Error: Type {} at pos {} for opcode {} has no runtime tag", t.pretty(), pos, op.pretty())
+        },
+        Error::InsufficientCapabilityForCall(pos, op, r) => {
+            format!("Region Access Error: The function called at pos {} for opcode {} needs access to region {}, which the caller doesn't have", pos, op.pretty(), r.pretty())
+        },
+        Error::RegionResetWhileReferenced(pos, op, r) => {
+            format!("Region Error: Region {} is reset at pos {} for opcode {} while pointers into it are still on the stack", r.pretty(), pos, op.pretty())
+        }
+        Error::TypeErrorCheckCastBranchesDiffer(pos) => {
+            format!("Type Error: the two functions of the check cast at pos {} take different compile-time arguments", pos)
+        }
+        Error::SizeTooLarge(pos, op, s, max) => {
+            format!("Size Error: Size {} at pos {} for opcode {} exceeds the maximum size {}", s, pos, op.pretty(), max)
+        }
+        Error::UndeclaredFunction(label) => {
+            format!("Type Error: Function {} is defined, but there's no declaration to check it against", label)
+        }
+        Error::MissingTerminator(label) => {
+            format!("Type Error: Function {} doesn't end in a terminator like halt or call, so it would run off the end", label)
+        }
+        Error::UnreachableCode(pos, op) => {
+            format!("Type Error: {} at pos {} comes after a terminator, so it can never run", op.pretty(), pos)
+        }
+        Error::EmptyFunction(label) => {
+            format!("Type Error: Function {} has no runtime instructions", label)
+        }
+        Error::TypeErrorLoopStackMismatch(pos, op, ts1, ts2) => {
+            format!("Type Error: Loop state mismatch at pos {} for opcode {}: expected stack {} but found {}", pos, op.pretty(), ts1.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "), ts2.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "))
+        }
+        Error::TypeErrorLoopCTStackMismatch(pos, op, ct1, ct2) => {
+            format!("Type Error: Loop state mismatch at pos {} for opcode {}: expected compile-time stack {} but found {}", pos, op.pretty(), ct1.iter().map(|v| v.pretty()).collect::<Vec<_>>().join(", "), ct2.iter().map(|v| v.pretty()).collect::<Vec<_>>().join(", "))
+        }
+        Error::LoopRegionsMismatch(pos, op, rs1, rs2) => {
+            format!("Region Error: Loop state mismatch at pos {} for opcode {}: expected live regions {} but found {}", pos, op.pretty(), rs1.iter().map(|r| r.pretty()).collect::<Vec<_>>().join(", "), rs2.iter().map(|r| r.pretty()).collect::<Vec<_>>().join(", "))
+        }
+        Error::LoopControlOutsideLoop(pos, op) => {
+            format!("Syntax Error: Opcode {} at pos {} is not inside a loop", op.pretty(), pos)
+        }
+        Error::LoopWithoutBreak(pos) => {
+            format!("Syntax Error: The loop ending at pos {} has no break, so it can never exit", pos)
+        }
+        Error::UnclosedLoop(label) => {
+            format!("Syntax Error: Function {} has a loop with no loop_end", label)
+        }
+        Error::RegionFreedButStillReferenced(pos, op, r) => {
+            format!("Region Error: Region {} is freed at pos {} for opcode {} while handles or pointers into it are still on the stack", r.pretty(), pos, op.pretty())
+        }
+        Error::ZeroSizedArrayElement(pos, op, t) => {
+            format!("Size Error: The array at pos {} for opcode {} has elements of type {}, which takes no space, so the runtime couldn't tell how many there are", pos, op.pretty(), t.pretty())
         }
+        Error::NonEmptyCompileTimeStackAtExit(label) => {
+            format!("Type Error: Function {} leaves unused values on the compile-time stack", label)
+        }
+        Error::DuplicateExport(a, b) => {
+            format!("Link Error: More than one function is exported as {} {}", a, b)
+        }
+        Error::TypeVarSizeInconsistent(pos, op, id, s1, s2) => {
+            format!("Size Error: Type variable a{} is bound at size {} but used at size {} at pos {} for opcode {}", id.1, s1, s2, pos, op.pretty())
+        }
+        Error::UniqueHandleDropped(pos, op, r) => {
+            format!("Uniqueness Error: The handle to unique region {} is dropped at pos {} for opcode {}, but unique regions must be freed with free_rgn", r.pretty(), pos, op.pretty())
+        }
+        Error::TypeErrorHaltStatus(pos, op, t) => {
+            format!("Type Error: {} at pos {} expects an integer or a zero-size exit status but the top of the stack is {}", op.pretty(), pos, t.pretty())
+        }
+        Error::MemCopyTypeMismatch(pos, t1, t2) => {
+            format!("Type Error: mem_copy at pos {} copies {} into a pointer to {}", pos, t1.pretty(), t2.pretty())
+        }
+        Error::SyntaxErrorUnknownMnemonic(mnemonic) => {
+            format!("Syntax Error: Unknown op {}", mnemonic)
+        }
+        Error::SyntaxErrorBadOperand(line) => {
+            format!("Syntax Error: Bad operands in `{}`", line)
+        }
+        Error::LiteralOutOfRange(value) => {
+            format!("Syntax Error: The literal {} doesn't fit in an i32, which ranges from {} to {}", value, i32::MIN, i32::MAX)
+        }
+        Error::SyntaxErrorLabelOutOfOrder(expected, found) => {
+            format!("Syntax Error: Expected function {} next but found function {}; functions have to be in label order", expected, found)
+        }
+        Error::SyntaxErrorUnterminatedFunc(label) => {
+            format!("Syntax Error: Function {} ends before its declaration and body are finished", label)
+        }
+        Error::SyntaxErrorOpOutsideFunction => {
+            "Syntax Error: Op outside of any function".to_string()
+        }
+        Error::TypeErrorRecExpected(pos, op, t) => {
+            format!("Type Error: Expected recursive type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        }
+        Error::TypeErrorSumExpected(pos, op, t) => {
+            format!("Type Error: Expected sum type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        }
+        Error::TypeErrorInjOutOfRange(pos, i, n) => {
+            format!("Type Error: inj out of range at pos {}: the sum has {} variants but got {}", pos, n, i)
+        }
+        Error::TypeErrorCaseArity(pos, n, variants) => {
+            format!("Type Error: case at pos {} has {} functions for a sum with {} variants", pos, n, variants)
+        }
+        Error::TypeErrorCaseBranchesDiffer(pos, i) => {
+            format!("Type Error: function {} of the case at pos {} takes different compile-time arguments than function 0", i, pos)
+        }
+        Error::FreshIdExhausted(label, max) => {
+            format!("Limit Error: Function {} needs more than the {} fresh ids the verifier allows", label, max)
+        }
+        Error::RegionLeak(pos, r) => {
+            format!("Region Error: The program halts at pos {} without freeing unique region {}, which was created in the same function", pos, r.pretty())
+        }
+        Error::Located(loc, e) => {
+            format!("{} (source file {}, line {}, col {})", msg(e), loc.file_id, loc.line, loc.col)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_uses_the_error_message() {
+        assert_eq!(Error::UnexpectedEOF.to_string(), "Unexpected end of file");
+        let loc = SourceLoc { file_id: 7, line: 40, col: 2 };
+        let located = Error::Located(loc, Box::new(Error::UnexpectedEOF));
+        assert_eq!(format!("{}", located), "Unexpected end of file (source file 7, line 40, col 2)");
+    }
+
+    fn var(n: u32) -> Type {
+        Type::Var(Id(0, n), 4)
+    }
+
+    fn tuple(ts: Vec<Type>) -> Type {
+        Type::Tuple(ts.into_iter().map(|t| (true, t)).collect())
+    }
+
+    #[test]
+    fn diff_points_at_a_deep_field() {
+        let expected = tuple(vec![Type::I32, tuple(vec![Type::U8, Type::I32])]);
+        let actual = tuple(vec![Type::I32, tuple(vec![Type::U8, Type::I64])]);
+        assert_eq!(
+            type_diff(&expected, &actual).unwrap(),
+            "at component 1 of the tuple, at component 1 of the tuple: expected i32, found i64"
+        );
+    }
+
+    #[test]
+    fn diff_under_a_quantifier_does_not_capture() {
+        // `exists a1. (a1, a1)` against `exists a2. (a2, a1)`, where the second a1 is free,
+        // so renaming a2 to a1 would make the bodies look equal
+        let expected = Type::Exists(Id(0, 1), 4, Box::new(tuple(vec![var(1), var(1)])));
+        let actual = Type::Exists(Id(0, 2), 4, Box::new(tuple(vec![var(2), var(1)])));
+        let path = diff_path(&expected, &actual);
+        let places: Vec<&str> = path.iter().map(|(place, _, _)| place.as_str()).collect();
+        assert_eq!(places, vec!["under the quantifier", "at component 1 of the tuple"]);
+        let (_, t1, t2) = path.last().unwrap();
+        assert!(!type_eq(t1, t2));
+    }
+
+    #[test]
+    fn diff_under_nested_quantifiers_finds_the_first_difference() {
+        // `exists a1. (exists a2. (a1, a2), i32)` against `exists a3. (exists a4. (a4, a4), u8)`,
+        // where the inner packages differ even though the comparison of them renames a variable too
+        let exists = |n, t| Type::Exists(Id(0, n), 4, Box::new(t));
+        let expected = exists(1, tuple(vec![exists(2, tuple(vec![var(1), var(2)])), Type::I32]));
+        let actual = exists(3, tuple(vec![exists(4, tuple(vec![var(4), var(4)])), Type::U8]));
+        let path = diff_path(&expected, &actual);
+        let places: Vec<&str> = path.iter().map(|(place, _, _)| place.as_str()).collect();
+        assert_eq!(
+            places,
+            vec!["under the quantifier", "at component 0 of the tuple", "under the quantifier", "at component 0 of the tuple"]
+        );
     }
-}
\ No newline at end of file
+}
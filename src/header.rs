@@ -5,6 +5,7 @@
  */
 
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
 /// The input type for SaberVM.
 pub type ByteStream = Vec<u8>;
@@ -14,7 +15,8 @@ pub type Label = u32;
 
 /// The type for identifiers.
 /// As SaberVM is stack-based, this really just means compile-time stuff, like type variables.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Id(pub Pos, pub u32);
 
 /// The type of unverified ops.
@@ -29,18 +31,31 @@ pub enum Op1 {
     All,
     Rgn,
     End,
+    /// Instantiate the outermost quantifier of the polymorphic value on top of the stack.
     App,
     Func(u8),
+    /// Duplicate the `i`th compile-time value, counting from the top (0 is the top).
     CTGet(u8),
     Lced,
+    /// Open the existential package on top of the stack, pushing a fresh type variable for the
+    /// hidden type onto the compile-time stack and the package's contents onto the stack.
     Unpack,
+    /// Duplicate the `i`th value on the stack, counting from the top (0 is the top).
+    /// This is the same convention as `CTGet`.
     Get(u8),
     Init(u8),
     Malloc,
     Proj(u8),
+    /// Call the function on top of the stack, popping its arguments with parameter 0 on top.
+    /// A polymorphic function is instantiated first with one compile-time value per quantifier,
+    /// outermost quantifier on top, so `t2; t1; call` means the same as `t1; app; t2; app; call`.
     Call,
+    /// Push an `i32`. In bytecode it's the 4 bytes after the opcode, little-endian,
+    /// so every encoding is in range; the assembler rejects text that isn't.
     Lit(i32),
     GlobalFunc(u32),
+    /// End the program with the value on top of the stack as its exit status.
+    /// An integer exits with its low byte, and a zero-size value, like a unit, exits with 0.
     Halt,
     Pack,
     Size(u32),
@@ -53,6 +68,8 @@ pub enum Op1 {
     ArrProj,
     Add,
     Mul,
+    /// Pop two numbers of the same type and push the one below divided by the top one.
+    /// Integer division by zero stops the program with `RuntimeError::DivByZero`; floats follow IEEE 754.
     Div,
     CallNZ,
     Data(u32),
@@ -63,15 +80,106 @@ pub enum Op1 {
     U8ToI32,
     Export(u64, u64),
     Import(u64, u64),
+    /// Pop two integers of the same type and push the remainder of dividing the one below by the top one.
+    /// A zero divisor stops the program with `RuntimeError::DivByZero`, like `Div`.
     Modulo,
     I32ToU8,
     Read(u8),
     Write(u8),
+    Tagged,
+    PackTagged,
+    CheckCast(Label, Label),
+    /// End a function whatever is on the stack. Running it stops the program with `RuntimeError::ReachedUnreachable`.
+    Unreachable,
+    ResetRgn,
+    Rotate(u8),
+    Loop,
+    Break,
+    Continue,
+    LoopEnd,
+    /// Pop an i32 condition and two values of the same type,
+    /// keeping the top one if the condition is nonzero and the one below it otherwise.
+    Select,
+    /// Pop an array and push its number of elements.
+    /// Arrays are allocated with `Malloc` on an array type or with `ArrAlloc`, which store the length in a header.
+    ArrLen,
+    /// Push the number of values on the stack as a compile-time size.
+    /// This is for code generators, and has no runtime effect.
+    StackDepth,
+    /// Discard a handle to a shared region.
+    /// Unique regions have to be freed with `FreeRgn` instead.
+    DropHandle,
+    /// Pop a pointer to a fully initialized tuple and copy the tuple into the pointer below it,
+    /// which stays on the stack and points to a fully initialized tuple afterwards.
+    MemCopy,
+    /// Pop two numbers of the same type and push the one below minus the top one, like `Div` and `Modulo`.
+    Sub,
+    /// Pop a size and bind a type variable of that size standing for the recursive type
+    /// that the matching `End` builds. The body must have the same size, so the variable
+    /// can only appear behind a pointer.
+    Rec,
+    /// Pop a recursive type from the compile-time stack and a value of its unfolding,
+    /// and push the value at the recursive type. This has no runtime effect.
+    Fold,
+    /// Replace the recursive type on top of the stack with its unfolding,
+    /// which is the body with the variable replaced by the recursive type itself.
+    /// This has no runtime effect.
+    Unfold,
+    /// Pop `n` types and push the sum type with those variants, the top one being variant 0.
+    Sum(u8),
+    /// Pop a sum type from the compile-time stack and a value of its `i`th variant,
+    /// and push the value at the sum type.
+    Inj(u8),
+    /// Pop `n` functions and a sum type with `n` variants,
+    /// and call the function for the sum's variant with the variant's value on top of the stack.
+    /// Like `CallNZ`, every function has to accept the rest of the stack.
+    Case(u8),
+    I64,
+    F64,
+    LitI64(i64),
+    /// A float literal, given by its bits so that ops can still be compared for equality.
+    LitF64(u64),
+    /// Truncate an i64 to its low 32 bits.
+    I64ToI32,
+    /// Convert an f64 to an i64, rounding toward zero and saturating, with NaN becoming 0.
+    F64ToI64,
+    /// Duplicate the value on top of the stack, like `Get(0)`.
+    Dup,
+    /// Swap the top two values on the stack, like `Rotate(1)`.
+    Swap,
+    /// Discard the value on top of the stack.
+    /// Like `DropHandle`, this can't drop the handle of a unique region.
+    Pop,
+    /// Pop a type from the compile-time stack and push its size.
+    SizeOf,
+    /// Pop two sizes from the compile-time stack and push their sum.
+    SizeAdd,
+    /// Pop a size from the compile-time stack and push it times `n`.
+    SizeMul(u8),
+    /// Remove the `n` values beneath the top of the stack, keeping the top one.
+    /// This clears out a function's leftovers before a call.
+    Clean(u8),
+    /// Push the heap region onto the compile-time stack.
+    Heap,
+    /// Do nothing. This is padding for patching bytecode in place.
+    Nop,
+    /// Pop a type from the compile-time stack and reveal the tagged existential on top of the stack
+    /// as that type, like `CheckCast` but stopping the program with `RuntimeError::AssertFailed`
+    /// if the tags don't match instead of branching.
+    Assert,
+    /// Duplicate the `i`th value on the stack, counting from the bottom of the function's frame
+    /// (0 is the deepest). Unlike `Get`, pushing more values doesn't change what an index refers to.
+    GetLocal(u8),
+    /// Pop an element type from the compile-time stack, and a length and a region handle from the stack,
+    /// and push an array of that many elements in the region. Like `Malloc` on an array type,
+    /// but the region comes from the handle instead of the compile-time stack.
+    ArrAlloc,
 }
 
 /// The type of unverified ops.
 /// This includes all the static analysis ops, which disappear after verification.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op2 {
     Get(usize, usize),
     Init(usize, usize, usize),
@@ -108,6 +216,40 @@ pub enum Op2 {
     I32ToU8,
     Read(u8),
     Write(u8),
+    /// The size of the value being packed, and its tag.
+    PackTagged(usize, u64),
+    CheckCast(u64, Label, Label),
+    Unreachable,
+    ResetRgn,
+    Rotate(usize, usize),
+    Jump(u32),
+    JumpNZ(u32),
+    Select(usize),
+    ArrLen(usize),
+    Pop(usize),
+    MemCopy(usize),
+    SubI32,
+    SubU8,
+    /// The size of the variant's value, the padding after it, and the variant's index.
+    Inj(usize, usize, u8),
+    Case(u8),
+    Lit64(u64),
+    AddI64,
+    SubI64,
+    MulI64,
+    DivI64,
+    ModuloI64,
+    AddF64,
+    SubF64,
+    MulF64,
+    DivF64,
+    I64ToI32,
+    F64ToI64,
+    Dup(usize),
+    Swap(usize, usize),
+    Clean(usize, usize),
+    HeapHandle,
+    Assert(u64),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -119,18 +261,34 @@ pub enum Visibility {
 
 #[derive(Debug)]
 pub enum ForwardDec {
-    Func(Pos, Visibility, Vec<Op1>),
+    Func(Label, Pos, Visibility, Vec<Op1>),
+}
+
+/// A location in the source code that a program was compiled from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub file_id: u32,
+    pub line: u32,
+    pub col: u32,
 }
 
+/// The optional debug section of a program, mapping op positions to source locations.
+pub type DebugInfo = HashMap<Pos, SourceLoc>;
+
+/// What the parser produces: the data section, the forward declarations, the function bodies,
+/// and the debug info.
+pub type ParsedProgram = (Vec<u8>, Vec<ForwardDec>, Vec<Stmt1>, DebugInfo);
+
 /// Statements produced by the parsing pass.
 /// Next they would go through the verification pass.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Stmt1 {
     Func(u32, Pos, Vec<Op1>),
 }
 
 /// Statements produced by the verification pass.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt2 {
     Func(Pos, Type, Vec<Op2>),
 }
@@ -142,19 +300,37 @@ pub struct IRProgram {
     pub funcs: Vec<Stmt2>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// What a region is: a region variable, or one of the built-in regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RgnId {
     Var(Id),
     DataSection,
+    /// The global heap, which is live for the whole program and never freed.
+    Heap,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The one representation of regions, used by the parser, verifier, and error messages alike.
+/// `unique` is the capability held on a region, not part of its identity, so the verifier asks whether
+/// two regions are the same by comparing their `id`s, and `==` does the same.
+/// Code that cares about the capability compares `unique` explicitly.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     pub unique: bool,
     pub id: RgnId,
 }
 
+impl PartialEq for Region {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Region {}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     I32,
     U8,
@@ -167,9 +343,28 @@ pub enum Type {
     ForallRegion(Region, Box<Type>, Vec<Region>),
     Exists(Id, usize, Box<Type>),
     Array(Box<Type>, Region),
+    TaggedExists(Id, usize, Box<Type>),
+    /// An iso-recursive type, converted to and from its unfolding with `Fold` and `Unfold`.
+    Rec(Id, usize, Box<Type>),
+    /// A value of one of several types. It's laid out as the value, padding up to the largest variant,
+    /// the amount of padding as a u32, and the variant's index as a u32 on top.
+    Sum(Vec<Type>),
+    I64,
+    F64,
+}
+
+/// Hashing is alpha-invariant (see `verify::type_hash`), which is coarser than
+/// the derived structural equality, so the two stay consistent.
+impl Hash for Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(crate::verify::type_hash(self));
+    }
 }
 
 impl Type {
+    /// The size of a value of this type, in bytes.
+    /// This is 0 for the empty tuple and anything made only of empty tuples,
+    /// whose ops all move 0 bytes at runtime.
     pub fn size(&self) -> usize {
         match self {
             Self::I32 => 4,
@@ -183,6 +378,12 @@ impl Type {
             Self::ForallRegion(_r, t, _captured_rgns) => t.size(),
             Self::Exists(_id, _size, t) => t.size(),
             Self::Array(_t, _r) => 16,
+            // the 8-byte tag sits on top of the hidden value
+            Self::TaggedExists(_id, _size, t) => t.size() + 8,
+            Self::Rec(_id, _size, t) => t.size(),
+            Self::Sum(ts) => ts.iter().map(Type::size).max().unwrap_or(0) + 8,
+            Self::I64 => 8,
+            Self::F64 => 8,
         }
     }
 }
@@ -217,6 +418,7 @@ pub enum Quantification {
     Region(Region),
     Forall(Id, usize),
     Exist(Id, usize),
+    Rec(Id, usize),
 }
 
 /// The type for user-facing errors (as opposed to internal SaberVM errors, which are panics).
@@ -225,6 +427,7 @@ pub enum Error {
     SyntaxErrorParamNeeded(Pos, u8),
     SyntaxErrorUnknownOp(Pos, u8),
     TypeErrorMainHasArgs,
+    TypeErrorMainPolymorphic(Type),
     TypeErrorNonEmptyQuantificationStack(Label),
     TypeErrorEmptyQuantificationStack(Pos, Op1),
     TypeErrorEmptyCTStack(Pos, Op1),
@@ -239,6 +442,7 @@ pub enum Error {
     TypeErrorTypeVarExpected(Pos, Op1, Id, Type),
     TypeErrorCTGetOutOfRange(Pos, u8, usize),
     TypeErrorGetOutOfRange(Pos, u8, usize),
+    TypeErrorRotateOutOfRange(Pos, u8, usize),
     TypeErrorInitOutOfRange(Pos, u8, usize),
     TypeErrorProjOutOfRange(Pos, u8, usize),
     TypeErrorExistentialExpected(Pos, Op1, Type),
@@ -254,17 +458,148 @@ pub enum Error {
     TypeErrorForallRegionExpected(Pos, Op1, Type),
     KindErrorBadApp(Pos, Op1, CTStackVal),
     TypeErrorDoubleInit(Pos, Op1, u8),
-    TypeErrorUninitializedRead(Pos, Op1, u8),
+    TypeErrorUninitializedRead(Pos, Op1, u8, Vec<(bool, Type)>),
     TooBigForStack(Pos, Op1, Type),
-    ForwardDeclNotType(Type),
-    ForwardDeclRuntimeOp(Op1),
-    ForwardDeclBadStack(Vec<CTStackVal>),
+    ForwardDeclNotType(Label, Type),
+    ForwardDeclRuntimeOp(Label, Op1),
+    ForwardDeclBadStack(Label, Vec<CTStackVal>),
+    DeclarationOpInBody(Pos, Op1),
     UnknownGlobalFunc(Pos, Op1, Label),
     UnexpectedEOF,
+    CannotReadFile(String, String),
+    CannotWriteFile(String, String),
     TypeErrorArrayExpected(Pos, Op1, Type),
     ReadOnlyRegionError(Pos, Op1, RgnId),
     DataSectionLoadOutOfBounds(Pos, Op1, usize, usize),
     InvalidDataSectionType(Pos, Op1, Type),
     CannotMutateDataSection(Pos, Op1),
-    UnknownChannel(Pos, Op1, u8)
+    UnknownChannel(Pos, Op1, u8),
+    TypeErrorTaggedExistentialExpected(Pos, Op1, Type),
+    TypeErrorUntaggable(Pos, Op1, Type),
+    TypeErrorCheckCastBranchesDiffer(Pos),
+    InsufficientCapabilityForCall(Pos, Op1, Region),
+    RegionResetWhileReferenced(Pos, Op1, Region),
+    SizeTooLarge(Pos, Op1, usize, usize),
+    EmptyFunction(Label),
+    MissingTerminator(Label),
+    UnreachableCode(Pos, Op1),
+    UndeclaredFunction(Label),
+    TypeErrorLoopStackMismatch(Pos, Op1, Vec<Type>, Vec<Type>),
+    TypeErrorLoopCTStackMismatch(Pos, Op1, Vec<CTStackVal>, Vec<CTStackVal>),
+    LoopRegionsMismatch(Pos, Op1, Vec<Region>, Vec<Region>),
+    LoopControlOutsideLoop(Pos, Op1),
+    LoopWithoutBreak(Pos),
+    UnclosedLoop(Label),
+    RegionFreedButStillReferenced(Pos, Op1, Region),
+    ZeroSizedArrayElement(Pos, Op1, Type),
+    NonEmptyCompileTimeStackAtExit(Label),
+    DuplicateExport(u64, u64),
+    TypeVarSizeInconsistent(Pos, Op1, Id, usize, usize),
+    UniqueHandleDropped(Pos, Op1, Region),
+    TypeErrorHaltStatus(Pos, Op1, Type),
+    MemCopyTypeMismatch(Pos, Type, Type),
+    SyntaxErrorUnknownMnemonic(String),
+    SyntaxErrorBadOperand(String),
+    LiteralOutOfRange(i64),
+    SyntaxErrorLabelOutOfOrder(Label, Label),
+    SyntaxErrorUnterminatedFunc(Label),
+    SyntaxErrorOpOutsideFunction,
+    TypeErrorRecExpected(Pos, Op1, Type),
+    TypeErrorSumExpected(Pos, Op1, Type),
+    TypeErrorInjOutOfRange(Pos, u8, usize),
+    TypeErrorCaseArity(Pos, u8, usize),
+    TypeErrorCaseBranchesDiffer(Pos, usize),
+    RegionLeak(Pos, Region),
+    FreshIdExhausted(Label, u32),
+    Located(SourceLoc, Box<Error>),
+}
+
+/// Something a program does that's allowed but probably a mistake.
+#[derive(Debug)]
+pub enum Warning {
+    /// A function is quantified over a region that none of its parameters mention.
+    UnusedRegion(Label, Region),
+}
+
+impl Error {
+    /// The position of the op that caused the error, if the error is about a specific op.
+    pub fn pos(&self) -> Option<Pos> {
+        match self {
+            Self::SyntaxErrorParamNeeded(pos, ..)
+            | Self::SyntaxErrorUnknownOp(pos, ..)
+            | Self::TypeErrorEmptyQuantificationStack(pos, ..)
+            | Self::TypeErrorEmptyCTStack(pos, ..)
+            | Self::TypeErrorEmptyStack(pos, ..)
+            | Self::DeclarationOpInBody(pos, ..)
+            | Self::KindError(pos, ..)
+            | Self::RegionError(pos, ..)
+            | Self::TypeError(pos, ..)
+            | Self::SizeError(pos, ..)
+            | Self::UniquenessError(pos, ..)
+            | Self::RegionAccessError(pos, ..)
+            | Self::TypeErrorSpecificTypeVarExpected(pos, ..)
+            | Self::TypeErrorTypeVarExpected(pos, ..)
+            | Self::TypeErrorCTGetOutOfRange(pos, ..)
+            | Self::TypeErrorGetOutOfRange(pos, ..)
+            | Self::TypeErrorRotateOutOfRange(pos, ..)
+            | Self::TypeErrorInitOutOfRange(pos, ..)
+            | Self::TypeErrorProjOutOfRange(pos, ..)
+            | Self::TypeErrorExistentialExpected(pos, ..)
+            | Self::TypeErrorRecExpected(pos, ..)
+            | Self::TypeErrorSumExpected(pos, ..)
+            | Self::TypeErrorInjOutOfRange(pos, ..)
+            | Self::TypeErrorCaseArity(pos, ..)
+            | Self::TypeErrorCaseBranchesDiffer(pos, ..)
+            | Self::RegionLeak(pos, ..)
+            | Self::TypeErrorInitTypeMismatch(pos, ..)
+            | Self::TypeErrorTupleExpected(pos, ..)
+            | Self::TypeErrorFunctionExpected(pos, ..)
+            | Self::TypeErrorRegionHandleExpected(pos, ..)
+            | Self::TypeErrorNotEnoughRuntimeArgs(pos, ..)
+            | Self::TypeErrorCallArgTypesMismatch(pos, ..)
+            | Self::TypeErrorMallocNonTuple(pos, ..)
+            | Self::TypeErrorPtrExpected(pos, ..)
+            | Self::TypeErrorForallExpected(pos, ..)
+            | Self::TypeErrorForallRegionExpected(pos, ..)
+            | Self::KindErrorBadApp(pos, ..)
+            | Self::TypeErrorDoubleInit(pos, ..)
+            | Self::TypeErrorUninitializedRead(pos, ..)
+            | Self::TooBigForStack(pos, ..)
+            | Self::UnknownGlobalFunc(pos, ..)
+            | Self::TypeErrorArrayExpected(pos, ..)
+            | Self::ReadOnlyRegionError(pos, ..)
+            | Self::DataSectionLoadOutOfBounds(pos, ..)
+            | Self::InvalidDataSectionType(pos, ..)
+            | Self::CannotMutateDataSection(pos, ..)
+            | Self::UnknownChannel(pos, ..)
+            | Self::TypeErrorTaggedExistentialExpected(pos, ..)
+            | Self::TypeErrorUntaggable(pos, ..)
+            | Self::TypeErrorCheckCastBranchesDiffer(pos)
+            | Self::InsufficientCapabilityForCall(pos, ..)
+            | Self::RegionResetWhileReferenced(pos, ..)
+            | Self::SizeTooLarge(pos, ..)
+            | Self::TypeErrorLoopStackMismatch(pos, ..)
+            | Self::TypeErrorLoopCTStackMismatch(pos, ..)
+            | Self::LoopRegionsMismatch(pos, ..)
+            | Self::LoopControlOutsideLoop(pos, ..)
+            | Self::LoopWithoutBreak(pos, ..)
+            | Self::RegionFreedButStillReferenced(pos, ..)
+            | Self::ZeroSizedArrayElement(pos, ..)
+            | Self::TypeVarSizeInconsistent(pos, ..)
+            | Self::UniqueHandleDropped(pos, ..)
+            | Self::TypeErrorHaltStatus(pos, ..)
+            | Self::UnreachableCode(pos, ..)
+            | Self::MemCopyTypeMismatch(pos, ..) => Some(*pos),
+            Self::Located(_, e) => e.pos(),
+            _ => None,
+        }
+    }
+
+    /// Attach the source location the debug section gives for this error's position, if any.
+    pub fn locate(self, debug_info: &DebugInfo) -> Error {
+        match self.pos().and_then(|pos| debug_info.get(&pos)) {
+            Some(loc) => Error::Located(*loc, Box::new(self)),
+            None => self,
+        }
+    }
 }
@@ -0,0 +1,209 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+pub mod header;
+pub mod pretty;
+mod error_msgs;
+pub mod parse;
+pub mod verify;
+pub mod vm;
+pub mod stdlib;
+pub mod debug;
+pub mod link;
+pub mod testgen;
+pub mod asm;
+pub mod encode;
+
+use std::fs;
+use std::io::Write;
+
+/// Why a program didn't run to a successful exit.
+#[derive(Debug)]
+pub enum CompileOrRuntimeError {
+    /// The program couldn't be read, assembled, or parsed.
+    Parse(Box<header::Error>),
+    /// The program failed to link or verify.
+    Verify(Box<header::Error>),
+    /// The program halted with this nonzero status.
+    Halted(u8),
+    /// The program stopped with a runtime error.
+    Runtime(vm::RuntimeError),
+}
+
+/// Constructors for the compile-time variants, to pass to `map_err`.
+impl CompileOrRuntimeError {
+    pub fn parse(e: header::Error) -> Self {
+        CompileOrRuntimeError::Parse(Box::new(e))
+    }
+
+    pub fn verify(e: header::Error) -> Self {
+        CompileOrRuntimeError::Verify(Box::new(e))
+    }
+}
+
+/// Parse, verify, and run a single bytecode program, with its output going to `out`.
+pub fn compile_and_run(bytes: &[u8], out: &mut dyn Write, limits: vm::Limits) -> Result<(), CompileOrRuntimeError> {
+    let (data_section, forward_decs, stmts, debug_info) = parse::go(&bytes.to_vec()).map_err(CompileOrRuntimeError::parse)?;
+    let ir_program = compile(vec![link::Module { data_section, forward_decs, stmts, debug_info }]).map_err(CompileOrRuntimeError::verify)?;
+    match run(vec![ir_program], out, &limits)? {
+        0 => Ok(()),
+        status => Err(CompileOrRuntimeError::Halted(status)),
+    }
+}
+
+/// Read a program from a file, assembling it if it's a `.sabervm` text file and parsing it as bytecode otherwise.
+pub fn load(filename: &str) -> Result<link::Module, header::Error> {
+    let cannot_read = |e: std::io::Error| header::Error::CannotReadFile(filename.to_string(), e.to_string());
+    let (data_section, forward_decs, stmts, debug_info) = if filename.ends_with(".sabervm") {
+        asm::go(&fs::read_to_string(filename).map_err(cannot_read)?)?
+    } else {
+        parse::go(&fs::read(filename).map_err(cannot_read)?)?
+    };
+    Ok(link::Module { data_section, forward_decs, stmts, debug_info })
+}
+
+/// Verify each module on its own, leaving the VM to link them by their imports and exports.
+/// This is how multiple files are run by default, so each one only has to agree with the others
+/// on the types of the functions it imports. Only the first module has a main.
+pub fn compile_separately(modules: Vec<link::Module>) -> Result<Vec<header::IRProgram>, header::Error> {
+    let opts = verify::VerifyOptions::default();
+    (0..)
+        .zip(modules)
+        .map(|(i, module)| {
+            let verify = if i == 0 { verify::go } else { verify::library };
+            verify(module.data_section, &module.forward_decs, module.stmts, &opts).map_err(|e| e.locate(&module.debug_info))
+        })
+        .collect()
+}
+
+/// Link the modules together into one program and verify the result.
+/// Unlike `compile_separately`, this checks every call across modules against the function it reaches.
+pub fn compile(modules: Vec<link::Module>) -> Result<header::IRProgram, header::Error> {
+    let program = link::merge(modules)?;
+    verify::go_default(program.data_section, &program.forward_decs, program.stmts).map_err(|e| e.locate(&program.debug_info))
+}
+
+/// Run verified programs, with the standard library if they use it, returning the status the first one halted with.
+pub fn run(ir_programs: Vec<header::IRProgram>, out: &mut dyn Write, limits: &vm::Limits) -> Result<u8, CompileOrRuntimeError> {
+    vm::go(
+        stdlib::link(ir_programs).map_err(CompileOrRuntimeError::verify)?,
+        &mut vm::Output {
+            out,
+            err: &mut std::io::stderr(),
+        },
+        limits,
+    )
+    .map_err(CompileOrRuntimeError::Runtime)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn assemble(src: &str) -> link::Module {
+        let (data_section, forward_decs, stmts, debug_info) = asm::go(src).unwrap();
+        link::Module { data_section, forward_decs, stmts, debug_info }
+    }
+
+    /// A program that writes the digit of `2 * 3` and a newline to standard output.
+    /// Its stdout handler then halts with the given status.
+    fn print_product(status: u8) -> Vec<u8> {
+        let mut bytes = vec![
+            0, 0, 0, 0, // no data section
+            2, 0, 0, 0, // two functions
+            0x09, 0, 0x0B, // func 0: ()->0
+            0x02, 0x02, 0x02, 0x02, 0x03, 4, 0x09, 1, 0x0B, // func 1: ((i32, i32, i32, i32))->0
+            // an array of two bytes in a new region
+            0x18, 64, 0, 0, 0, 0x0D, 0, 0x13, 2, 0, 0, 0, 0x0A, 0, 0x25, 0x1C, 0x0F,
+            // '0' + 2 * 3, then a newline
+            0x13, 2, 0, 0, 0, 0x13, 3, 0, 0, 0, 0x20, 0x13, 48, 0, 0, 0, 0x1F, 0x2C, 0x13, 0, 0, 0, 0, 0x1D,
+            0x27, 10, 0x13, 1, 0, 0, 0, 0x1D,
+            // the handler, as an existential hiding its 16-byte environment
+            0x17, 16, 0, 0, 0, 0x04, 0x0A, 0, 0x0A, 1, 0x09, 1, 0x03, 2, 0x07,
+            0x02, 0x02, 0x02, 0x02, 0x03, 4, 0x0A, 0, 0x0A, 0, 0x09, 1, 0x03, 2, 0x0F, 0x14, 1, 0, 0, 0, 0x0E, 0,
+            0x02, 0x02, 0x02, 0x02, 0x03, 4, 0x0F,
+        ];
+        for i in 0..4 {
+            bytes.extend([0x13, 0, 0, 0, 0, 0x0E, i]);
+        }
+        bytes.extend([
+            0x0E, 1, 0x16,
+            // write the array to standard output, then free the region
+            0x27, 0, 0x0D, 3, 0x2E, 0, 0x19, 0x27, 0, 0x15,
+            // the handler halts
+            0x27, status, 0x15,
+        ]);
+        bytes
+    }
+
+    #[test]
+    fn compiles_and_runs_bytecode() {
+        let mut out = Vec::new();
+        assert!(compile_and_run(&print_product(0), &mut out, vm::Limits::default()).is_ok());
+        assert_eq!(String::from_utf8(out).unwrap(), "6\n");
+    }
+
+    #[test]
+    fn bin_runs_against_runtime() {
+        let bin = assemble(include_str!("../bin.sabervm"));
+        let runtime = assemble(include_str!("../runtime.sabervm"));
+        // the checked-in bytecode has to be what its source assembles to
+        assert!(encode::go(&bin) == include_bytes!("../bin.svm"));
+        assert!(encode::go(&runtime) == include_bytes!("../runtime.svm"));
+        let mut out = Vec::new();
+        let status = run(compile_separately(vec![bin, runtime]).unwrap(), &mut out, &vm::Limits::default());
+        assert_eq!(status.unwrap(), 0);
+        assert_eq!(String::from_utf8(out).unwrap(), "7\n");
+    }
+
+    #[test]
+    fn bin_runs_linked_with_runtime() {
+        let bin = assemble(include_str!("../bin.sabervm"));
+        let runtime = assemble(include_str!("../runtime.sabervm"));
+        let mut out = Vec::new();
+        let status = run(vec![compile(vec![bin, runtime]).unwrap()], &mut out, &vm::Limits::default());
+        assert_eq!(status.unwrap(), 0);
+        assert_eq!(String::from_utf8(out).unwrap(), "7\n");
+    }
+
+    #[test]
+    fn nonzero_halt_is_err() {
+        let mut out = Vec::new();
+        let res = compile_and_run(&print_product(3), &mut out, vm::Limits::default());
+        assert!(matches!(res, Err(CompileOrRuntimeError::Halted(3))));
+        assert_eq!(String::from_utf8(out).unwrap(), "6\n");
+    }
+
+    #[test]
+    fn runtime_error_is_err() {
+        // a region when no regions are allowed
+        let bytes = [0, 0, 0, 0, 1, 0, 0, 0, 0x09, 0, 0x0B, 0x18, 64, 0, 0, 0, 0x19, 0x27, 0, 0x15];
+        let res = compile_and_run(&bytes, &mut Vec::new(), vm::Limits { max_live_regions: 0 });
+        assert!(matches!(res, Err(CompileOrRuntimeError::Runtime(vm::RuntimeError::TooManyRegions))));
+    }
+
+    #[test]
+    fn verify_error_is_verify_error() {
+        // halting with nothing on the stack
+        let bytes = [0, 0, 0, 0, 1, 0, 0, 0, 0x09, 0, 0x0B, 0x15];
+        let res = compile_and_run(&bytes, &mut Vec::new(), vm::Limits::default());
+        assert!(matches!(res, Err(CompileOrRuntimeError::Verify(_))));
+    }
+
+    #[test]
+    fn parse_error_is_parse_error() {
+        // a data section length with nothing after it
+        let res = compile_and_run(&[0, 0, 0, 0], &mut Vec::new(), vm::Limits::default());
+        assert!(matches!(res, Err(CompileOrRuntimeError::Parse(e)) if matches!(*e, header::Error::UnexpectedEOF)));
+    }
+
+    #[test]
+    fn missing_file_is_error() {
+        for filename in ["no_such_file.sabervm", "no_such_file.svm"] {
+            assert!(matches!(load(filename), Err(header::Error::CannotReadFile(f, _)) if f == filename));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use crate::header::*;
+
+/// A parsed but unverified program, as produced by `parse::go`.
+#[derive(Default)]
+pub struct Module {
+    pub data_section: Vec<u8>,
+    pub forward_decs: Vec<ForwardDec>,
+    pub stmts: Vec<Stmt1>,
+    pub debug_info: DebugInfo,
+}
+
+/// Merge separately compiled modules into one program, with the first module's main function as main.
+/// Each module's labels, positions, and data section offsets are moved past those of the modules before it,
+/// and an import of a function another module exports becomes a direct reference to that function.
+/// Imports nobody here exports are kept, so the result can still be linked against other programs by the VM.
+pub fn merge(modules: Vec<Module>) -> Result<Module, Error> {
+    let mut exported = HashSet::new();
+    for module in &modules {
+        for ForwardDec::Func(_, _, vis, _) in &module.forward_decs {
+            if let Visibility::Export(a, b) = vis {
+                if !exported.insert((*a, *b)) {
+                    return Err(Error::DuplicateExport(*a, *b));
+                }
+            }
+        }
+    }
+    let is_resolved = |vis: &Visibility| matches!(vis, Visibility::Import(a, b) if exported.contains(&(*a, *b)));
+
+    // every declaration that stays gets the next label, in order
+    let mut label_maps: Vec<HashMap<Label, Label>> = vec![];
+    let mut export_labels = HashMap::new();
+    let mut next_label = 0;
+    for module in &modules {
+        let mut label_map = HashMap::new();
+        for ForwardDec::Func(label, _, vis, _) in &module.forward_decs {
+            if is_resolved(vis) {
+                continue;
+            }
+            label_map.insert(*label, next_label);
+            if let Visibility::Export(a, b) = vis {
+                export_labels.insert((*a, *b), next_label);
+            }
+            next_label += 1;
+        }
+        label_maps.push(label_map);
+    }
+    for (module, label_map) in modules.iter().zip(&mut label_maps) {
+        for ForwardDec::Func(label, _, vis, _) in &module.forward_decs {
+            if let Visibility::Import(a, b) = vis {
+                if let Some(label2) = export_labels.get(&(*a, *b)) {
+                    label_map.insert(*label, *label2);
+                }
+            }
+        }
+    }
+
+    let mut merged = Module::default();
+    let mut pos_offset = 0;
+    for (module, label_map) in modules.into_iter().zip(label_maps) {
+        let len = module_len(&module);
+        let data_offset = merged.data_section.len() as u32;
+        for ForwardDec::Func(label, pos, vis, ops) in module.forward_decs {
+            if !is_resolved(&vis) {
+                merged.forward_decs.push(ForwardDec::Func(label_map[&label], pos + pos_offset, vis, ops));
+            }
+        }
+        for Stmt1::Func(label, pos, ops) in module.stmts {
+            let relabel = |pos: Pos, op: &Op1, label: &Label| match label_map.get(label) {
+                Some(label) => Ok(*label),
+                None => Err(Error::UnknownGlobalFunc(pos, *op, *label)),
+            };
+            let ops = (pos + pos_offset..)
+                .zip(ops)
+                .map(|(pos, op)| match op {
+                    Op1::GlobalFunc(l) => Ok(Op1::GlobalFunc(relabel(pos, &op, &l)?)),
+                    Op1::CheckCast(a, b) => Ok(Op1::CheckCast(relabel(pos, &op, &a)?, relabel(pos, &op, &b)?)),
+                    Op1::Data(loc) => Ok(Op1::Data(loc + data_offset)),
+                    op => Ok(op),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            merged.stmts.push(Stmt1::Func(label_map[&label], pos + pos_offset, ops));
+        }
+        merged
+            .debug_info
+            .extend(module.debug_info.into_iter().map(|(pos, loc)| (pos + pos_offset, loc)));
+        merged.data_section.extend(module.data_section);
+        pos_offset += len;
+    }
+    Ok(merged)
+}
+
+/// The number of op positions a module takes up, counting the terminator of each declaration.
+fn module_len(module: &Module) -> Pos {
+    let decs = module
+        .forward_decs
+        .iter()
+        .map(|ForwardDec::Func(_, pos, _, ops)| pos + ops.len() as Pos + 1);
+    let stmts = module.stmts.iter().map(|Stmt1::Func(_, pos, ops)| pos + ops.len() as Pos);
+    decs.chain(stmts).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{verify, vm};
+
+    /// A module of functions, given by their type ops, visibility, and body ops if they have a body.
+    fn module(funcs: Vec<(Vec<Op1>, Visibility, Option<Vec<Op1>>)>) -> Module {
+        let mut module = Module::default();
+        for (label, (decl, vis, body)) in (0..).zip(funcs) {
+            module.forward_decs.push(ForwardDec::Func(label, 0, vis, decl));
+            if let Some(body) = body {
+                module.stmts.push(Stmt1::Func(label, 0, body));
+            }
+        }
+        module
+    }
+
+    /// Main passes 5 to the other module, which multiplies it by 10 and passes it back to be incremented.
+    fn main_module() -> Module {
+        let takes_i32 = || vec![Op1::I32, Op1::Func(1)];
+        module(vec![
+            (vec![Op1::Func(0)], Visibility::Local, Some(vec![Op1::Lit(5), Op1::GlobalFunc(1), Op1::Call])),
+            (takes_i32(), Visibility::Import(1, 1), None),
+            (takes_i32(), Visibility::Export(2, 2), Some(vec![Op1::Lit(1), Op1::Add, Op1::I32ToU8, Op1::Halt])),
+        ])
+    }
+
+    fn times_ten() -> Module {
+        let takes_i32 = || vec![Op1::I32, Op1::Func(1)];
+        module(vec![
+            (takes_i32(), Visibility::Export(1, 1), Some(vec![Op1::Lit(10), Op1::Mul, Op1::GlobalFunc(1), Op1::Call])),
+            (takes_i32(), Visibility::Import(2, 2), None),
+        ])
+    }
+
+    /// Verify modules, the first one as a main, and run them together, returning the status they halt with.
+    fn status(modules: Vec<Module>) -> u8 {
+        let opts = verify::VerifyOptions::default();
+        let programs = (0..)
+            .zip(modules)
+            .map(|(i, module)| {
+                let verify = if i == 0 { verify::go } else { verify::library };
+                verify(module.data_section, &module.forward_decs, module.stmts, &opts).unwrap()
+            })
+            .collect();
+        vm::go(programs, &mut vm::Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &vm::Limits::default()).unwrap()
+    }
+
+    #[test]
+    fn modules_call_each_other() {
+        let merged = merge(vec![main_module(), times_ten()]).unwrap();
+        // both imports are resolved, so only the three defined functions are left
+        assert_eq!(merged.forward_decs.len(), 3);
+        assert!(merged.forward_decs.iter().all(|ForwardDec::Func(_, _, vis, _)| !matches!(vis, Visibility::Import(..))));
+        assert_eq!(status(vec![merged]), 51);
+        assert_eq!(status(vec![main_module(), times_ten()]), 51);
+    }
+
+    #[test]
+    fn duplicate_exports() {
+        let res = merge(vec![main_module(), times_ten(), times_ten()]);
+        assert!(matches!(res, Err(Error::DuplicateExport(1, 1))));
+    }
+
+    #[test]
+    fn unknown_global_funcs() {
+        let calls_nothing = module(vec![(vec![Op1::Func(0)], Visibility::Local, Some(vec![Op1::GlobalFunc(4), Op1::Call]))]);
+        assert!(matches!(merge(vec![calls_nothing]), Err(Error::UnknownGlobalFunc(_, Op1::GlobalFunc(4), 4))));
+    }
+}
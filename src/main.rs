@@ -4,37 +4,71 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-mod header;
-mod pretty;
-mod error_msgs;
-mod parse;
-mod verify;
-mod vm;
-
 use std::fs;
 use std::env;
 use std::process::exit;
 
-fn go(bytes: Vec<header::ByteStream>) -> Result<(), header::Error> {
-    let mut ir_programs = vec![];
-    for prog in bytes {
-        let (data_section, types_instrs, unverified_stmts) = parse::go(&prog)?;
-        // println!("{}", unverified_stmts.iter().map(|f|f.pretty() + "\n").collect::<String>());
-        let ir_program = verify::go(data_section, types_instrs, unverified_stmts)?;
-        ir_programs.push(ir_program);
-    }
-    let status = vm::go(ir_programs);
-    if status != 0 {
-        exit(status.into());
-    }
+use sabervm::{compile, compile_separately, load, run, CompileOrRuntimeError};
+use sabervm::{encode, header, vm};
+
+/// Verify and run programs. They're verified one by one and linked by the VM,
+/// unless `linked`, in which case they're merged into one program and verified together.
+fn go(filenames: &[String], linked: bool) -> Result<u8, CompileOrRuntimeError> {
+    let modules = filenames.iter().map(String::as_str).map(load).collect::<Result<Vec<_>, _>>().map_err(CompileOrRuntimeError::parse)?;
+    let ir_programs = if linked { compile(modules).map(|p| vec![p]) } else { compile_separately(modules) };
+    run(ir_programs.map_err(CompileOrRuntimeError::verify)?, &mut std::io::stdout(), &vm::Limits::default())
+}
+
+/// Assemble a text program and write it out as bytecode.
+fn assemble(filename: &str, out_filename: &str) -> Result<(), Box<header::Error>> {
+    let cannot_write = |e: std::io::Error| header::Error::CannotWriteFile(out_filename.to_string(), e.to_string());
+    fs::write(out_filename, encode::go(&load(filename)?)).map_err(cannot_write)?;
+    Ok(())
+}
+
+/// Verify a program and print its functions as JSON.
+#[cfg(feature = "serde")]
+fn json(filename: &str) -> Result<(), Box<header::Error>> {
+    let ir_program = compile(vec![load(filename)?])?;
+    println!("{}", encode::to_json(&ir_program.funcs));
     Ok(())
 }
 
 fn main() {
     let args = env::args().collect::<Vec<_>>();
-    let bytes: Vec<header::ByteStream> = args.iter().skip(1).map(|filename| fs::read(filename).unwrap()).collect();
-    let res = go(bytes);
-    if let Err(e) = res {
-        println!("{}", error_msgs::msg(e));
+    if args.get(1).is_some_and(|arg| arg == "--assemble") {
+        let (Some(filename), Some(out_filename)) = (args.get(2), args.get(3)) else {
+            println!("Usage: sabervm --assemble <file> <out-file>");
+            exit(1);
+        };
+        if let Err(e) = assemble(filename, out_filename) {
+            println!("{}", e);
+            exit(1);
+        }
+        return;
+    }
+    #[cfg(feature = "serde")]
+    if args.get(1).is_some_and(|arg| arg == "--json") {
+        let Some(filename) = args.get(2) else {
+            println!("Usage: sabervm --json <file>");
+            exit(1);
+        };
+        if let Err(e) = json(filename) {
+            println!("{}", e);
+            exit(1);
+        }
+        return;
+    }
+    // files are verified separately and linked by the VM,
+    // unless `--link` asks for them to be merged into one program and verified together
+    let (linked, filenames) = match &args[1..] {
+        [flag, filenames @ ..] if flag == "--link" => (true, filenames),
+        filenames => (false, filenames),
+    };
+    match go(filenames, linked) {
+        Ok(0) => {}
+        Ok(status) | Err(CompileOrRuntimeError::Halted(status)) => exit(status.into()),
+        Err(CompileOrRuntimeError::Parse(e) | CompileOrRuntimeError::Verify(e)) => println!("{}", e),
+        Err(CompileOrRuntimeError::Runtime(_)) => exit(1),
     }
 }
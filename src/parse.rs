@@ -10,17 +10,21 @@ use crate::header::*;
 /// A sequence of (possibly parameterized) opcodes.
 type LexedOpcodes = Vec<Op1>;
 
+/// The byte that starts the optional debug section at the end of a program.
+/// It isn't an opcode, so the lexer can't mistake it for one.
+const DEBUG_SECTION_START: u8 = 0xFF;
+
 /// Lex bytes into (possibly parameterized) intructions.
-fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
+fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32, DebugInfo), Error> {
     let mut bytes_iter = bytes.iter();
     let mut lexed_opcodes = vec![];
+    let mut debug_info = DebugInfo::new();
     let mut data_section_len_vec: [u8; 4] = [0, 0, 0, 0];
-    for i in 0..4 {
+    for slot in data_section_len_vec.iter_mut() {
         let Some(a) = bytes_iter.next() else {
-            dbg!("a");
             return Err(Error::UnexpectedEOF);
         };
-        data_section_len_vec[i] = *a;
+        *slot = *a;
     }
     let data_section_len_u32 = u32::from_le_bytes(data_section_len_vec);
     let data_section_len = data_section_len_u32 as usize;
@@ -28,29 +32,30 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
     let mut data_section = Vec::with_capacity(data_section_len);
     for _ in 0..data_section_len {
         data_section.push(
-            *(bytes_iter.next().ok_or_else(|| {
-                dbg!(data_section_len);
-                Error::UnexpectedEOF
-            })?),
+            *(bytes_iter.next().ok_or(Error::UnexpectedEOF)?),
         );
     }
     let mut a = [0, 0, 0, 0];
-    for i in 0..4 {
+    for slot in a.iter_mut() {
         match bytes_iter.next() {
             None => {
-                dbg!("c");
                 return Err(Error::UnexpectedEOF);
             }
             Some(b) => {
-                a[i] = *b;
+                *slot = *b;
             }
         }
     }
-    let mut pos = 8 + data_section_len_u32;
+    // positions are indices into the sequence of opcodes
+    let mut pos = 0;
     let n = u32::from_le_bytes(a);
     loop {
         match bytes_iter.next() {
             None => break,
+            Some(&DEBUG_SECTION_START) => {
+                debug_info = lex_debug_section(&mut bytes_iter)?;
+                break;
+            }
             Some(byte) => lexed_opcodes.push(match byte {
                 0x00 => Op1::Unique,
                 0x01 => Op1::Handle,
@@ -91,15 +96,15 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                 // 0x12 => Op1::Print,
                 0x13 => {
                     let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in n.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::Lit(i32::from_le_bytes(n))
                 }
                 0x14 => {
                     let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in n.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::GlobalFunc(u32::from_le_bytes(n))
                 }
@@ -107,15 +112,15 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                 0x16 => Op1::Pack,
                 0x17 => {
                     let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in n.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::Size(u32::from_le_bytes(n))
                 }
                 0x18 => {
                     let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in n.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::NewRgn(u32::from_le_bytes(n))
                 }
@@ -131,8 +136,8 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                 0x22 => Op1::CallNZ,
                 0x23 => {
                     let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in n.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::Data(u32::from_le_bytes(n))
                 }
@@ -147,11 +152,11 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                 0x29 => {
                     let mut a: [u8; 8] = [0,0,0,0,0,0,0,0];
                     let mut b: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    for i in 0..8 {
-                        a[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in a.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
-                    for i in 0..8 {
-                        b[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in b.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::Import(
                         u64::from_le_bytes(a),
@@ -161,11 +166,11 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                 0x2A => {
                     let mut a: [u8; 8] = [0,0,0,0,0,0,0,0];
                     let mut b: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    for i in 0..8 {
-                        a[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in a.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
-                    for i in 0..8 {
-                        b[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    for slot in b.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
                     }
                     Op1::Export(
                         u64::from_le_bytes(a),
@@ -182,12 +187,115 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
                     None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
                     Some(n) => Op1::Write(*n),
                 },
+                0x2F => Op1::Tagged,
+                0x30 => Op1::PackTagged,
+                0x31 => {
+                    let mut a = [0u8, 0, 0, 0];
+                    let mut b = [0u8, 0, 0, 0];
+                    for slot in a.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    }
+                    for slot in b.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    }
+                    Op1::CheckCast(u32::from_le_bytes(a), u32::from_le_bytes(b))
+                }
+                0x32 => Op1::Unreachable,
+                0x33 => Op1::ResetRgn,
+                0x34 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::Rotate(*n),
+                },
+                0x35 => Op1::Loop,
+                0x36 => Op1::Break,
+                0x37 => Op1::Continue,
+                0x38 => Op1::LoopEnd,
+                0x39 => Op1::Select,
+                0x3A => Op1::ArrLen,
+                0x3B => Op1::StackDepth,
+                0x3C => Op1::DropHandle,
+                0x3D => Op1::MemCopy,
+                0x3E => Op1::Sub,
+                0x3F => Op1::Rec,
+                0x40 => Op1::Fold,
+                0x41 => Op1::Unfold,
+                0x42 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::Sum(*n),
+                },
+                0x43 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(i) => Op1::Inj(*i),
+                },
+                0x44 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::Case(*n),
+                },
+                0x45 => Op1::I64,
+                0x46 => Op1::F64,
+                0x47 | 0x48 => {
+                    let mut lit = [0u8; 8];
+                    for slot in lit.iter_mut() {
+                        *slot = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
+                    }
+                    if *byte == 0x47 {
+                        Op1::LitI64(i64::from_le_bytes(lit))
+                    } else {
+                        Op1::LitF64(u64::from_le_bytes(lit))
+                    }
+                }
+                0x49 => Op1::I64ToI32,
+                0x4A => Op1::F64ToI64,
+                0x4B => Op1::Dup,
+                0x4C => Op1::Swap,
+                0x4D => Op1::Pop,
+                0x4E => Op1::SizeOf,
+                0x4F => Op1::SizeAdd,
+                0x50 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::SizeMul(*n),
+                },
+                0x51 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::Clean(*n),
+                },
+                0x52 => Op1::Heap,
+                0x53 => Op1::Nop,
+                0x54 => Op1::Assert,
+                0x55 => match bytes_iter.next() {
+                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
+                    Some(n) => Op1::GetLocal(*n),
+                },
+                0x56 => Op1::ArrAlloc,
                 op => return Err(Error::SyntaxErrorUnknownOp(pos, *op)),
             }),
         }
         pos += 1;
     }
-    Ok((data_section, lexed_opcodes, n))
+    Ok((data_section, lexed_opcodes, n, debug_info))
+}
+
+/// Lex the debug section, which is a u32 count of entries
+/// followed by that many (pos, file_id, line, col) entries of four u32s each.
+/// The verifier doesn't look at these, they're only used to point errors at the original source.
+fn lex_debug_section(bytes_iter: &mut std::slice::Iter<'_, u8>) -> Result<DebugInfo, Error> {
+    let mut next_u32 = || {
+        let mut n = [0u8, 0, 0, 0];
+        for slot in n.iter_mut() {
+            *slot = *bytes_iter.next().ok_or(Error::UnexpectedEOF)?;
+        }
+        Ok(u32::from_le_bytes(n))
+    };
+    let count = next_u32()?;
+    let mut debug_info = DebugInfo::new();
+    for _ in 0..count {
+        let pos = next_u32()?;
+        let file_id = next_u32()?;
+        let line = next_u32()?;
+        let col = next_u32()?;
+        debug_info.insert(pos, SourceLoc { file_id, line, col });
+    }
+    Ok(debug_info)
 }
 
 fn parse_forward_decs(
@@ -199,13 +307,15 @@ fn parse_forward_decs(
     let mut current_stmt_opcodes = vec![];
     let mut pos = 0;
     for i in 0..n {
+        let start = pos;
         loop {
+            pos += 1;
             match tokens_iter.next() {
                 None => {
                     return Err(Error::UnexpectedEOF)
                 }
                 Some(Op1::Lced) => {
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Local, current_stmt_opcodes));
+                    forward_decs.push(ForwardDec::Func(i, start, Visibility::Local, current_stmt_opcodes));
                     break;
                 }
                 Some(Op1::Export(a, b)) => {
@@ -213,7 +323,7 @@ fn parse_forward_decs(
                     // but other files can refer to it using the 128-bit (non-namespaced) UID that is a and b.
                     // The type has just been forward-declared,
                     // so other files can know it before all of this file is processed.
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Export(*a, *b), current_stmt_opcodes));
+                    forward_decs.push(ForwardDec::Func(i, start, Visibility::Export(*a, *b), current_stmt_opcodes));
                     break;
                 }
                 Some(Op1::Import(a, b)) => {
@@ -222,12 +332,11 @@ fn parse_forward_decs(
                     // so this won't be one of the implementations in this file.
                     // However, we now know its type, and we can refer to it with global_func
                     // as if it were at this spot in the list of functions in this file
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Import(*a, *b), current_stmt_opcodes));
+                    forward_decs.push(ForwardDec::Func(i, start, Visibility::Import(*a, *b), current_stmt_opcodes));
                     break;
                 }
                 Some(op) => current_stmt_opcodes.push(*op),
             }
-            pos += 1;
         }
         current_stmt_opcodes = vec![];
     }
@@ -239,8 +348,10 @@ fn parse(mut tokens_iter: std::slice::Iter<'_, Op1>, forward_decs: &Vec<ForwardD
     let mut current_stmt_opcodes = vec![];
     for decl in forward_decs {
         match decl {
-            ForwardDec::Func(i, Visibility::Local | Visibility::Export(_, _), _) => {
+            ForwardDec::Func(i, _, Visibility::Local | Visibility::Export(_, _), _) => {
+                let start = pos;
                 loop {
+                    pos += 1;
                     match tokens_iter.next() {
                         None => break,
                         Some(Op1::Call) => {
@@ -255,28 +366,101 @@ fn parse(mut tokens_iter: std::slice::Iter<'_, Op1>, forward_decs: &Vec<ForwardD
                             current_stmt_opcodes.push(Op1::Halt);
                             break;
                         }
+                        Some(Op1::CheckCast(a, b)) => {
+                            current_stmt_opcodes.push(Op1::CheckCast(*a, *b));
+                            break;
+                        }
+                        Some(Op1::Unreachable) => {
+                            current_stmt_opcodes.push(Op1::Unreachable);
+                            break;
+                        }
+                        Some(Op1::Case(n)) => {
+                            current_stmt_opcodes.push(Op1::Case(*n));
+                            break;
+                        }
                         Some(op) => current_stmt_opcodes.push(*op),
                     }
-                    pos += 1;
                 }
-                parsed_stmts.push(Stmt1::Func(*i, pos, current_stmt_opcodes));
+                parsed_stmts.push(Stmt1::Func(*i, start, current_stmt_opcodes));
                 current_stmt_opcodes = vec![];
             }
-            ForwardDec::Func(_, Visibility::Import(_, _), _) => {}
+            ForwardDec::Func(_, _, Visibility::Import(_, _), _) => {}
         }
     }
-    if current_stmt_opcodes.len() > 0 {
-        dbg!(current_stmt_opcodes);
+    if !current_stmt_opcodes.is_empty() {
         return Err(Error::UnexpectedEOF);
     }
     Ok(parsed_stmts)
 }
 
 /// Lex a stream of bytes, maybe return an error, otherwise parse.
-pub fn go(istream: &ByteStream) -> Result<(Vec<u8>, Vec<ForwardDec>, Vec<Stmt1>), Error> {
+pub fn go(istream: &ByteStream) -> Result<ParsedProgram, Error> {
     // this is two-pass currently (lex and parse); it would be straightforward to fuse these passes.
-    let (data_section, tokens, n) = lex(istream)?;
+    let (data_section, tokens, n, debug_info) = lex(istream)?;
     let (forward_decs, rest, pos) = parse_forward_decs(&tokens, n)?;
     let stmts = parse(rest, &forward_decs, pos)?;
-    Ok((data_section, forward_decs, stmts))
+    Ok((data_section, forward_decs, stmts, debug_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program whose main function halts at pos 2 with nothing on the stack, which fails verification,
+    /// followed by a debug section with the given entries.
+    fn bad_halt(debug_entries: &[(Pos, SourceLoc)]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0, 1, 0, 0, 0, 0x09, 0, 0x0B, 0x15];
+        bytes.push(DEBUG_SECTION_START);
+        bytes.extend((debug_entries.len() as u32).to_le_bytes());
+        for (pos, loc) in debug_entries {
+            for n in [*pos, loc.file_id, loc.line, loc.col] {
+                bytes.extend(n.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    const LOC: SourceLoc = SourceLoc { file_id: 7, line: 40, col: 2 };
+
+    #[test]
+    fn decodes_hand_written_bytecode() {
+        // a data section of two bytes, then one function: ()->0 with a u8, an i32 and a one-byte operand in its body
+        let bytes = vec![2, 0, 0, 0, 0xAB, 0xCD, 1, 0, 0, 0, 0x09, 0, 0x0B, 0x27, 3, 0x13, 7, 0, 0, 0, 0x0D, 4, 0x15];
+        let (data_section, forward_decs, stmts, debug_info) = go(&bytes).unwrap();
+        assert_eq!(data_section, vec![0xAB, 0xCD]);
+        assert!(matches!(&forward_decs[..], [ForwardDec::Func(0, 0, Visibility::Local, ops)] if ops == &[Op1::Func(0)]));
+        let [Stmt1::Func(0, 2, ops)] = &stmts[..] else {
+            panic!("expected one function, got {:?}", stmts);
+        };
+        assert_eq!(ops, &[Op1::U8Lit(3), Op1::Lit(7), Op1::Get(4), Op1::Halt]);
+        assert!(debug_info.is_empty());
+        // an operand cut off by the end of the program
+        assert!(matches!(go(&bytes[..bytes.len() - 2].to_vec()), Err(Error::SyntaxErrorParamNeeded(_, 0x0D))));
+    }
+
+    #[test]
+    fn debug_section_is_read() {
+        let (_, _, _, debug_info) = go(&bad_halt(&[(2, LOC)])).unwrap();
+        assert_eq!(debug_info, DebugInfo::from([(2, LOC)]));
+        // without a debug section there's nothing to point at
+        let mut bare = bad_halt(&[]);
+        bare.truncate(bare.len() - 5);
+        assert!(go(&bare).unwrap().3.is_empty());
+    }
+
+    #[test]
+    fn truncated_debug_section() {
+        let mut bytes = bad_halt(&[(2, LOC)]);
+        bytes.pop();
+        assert!(matches!(go(&bytes), Err(Error::UnexpectedEOF)));
+    }
+
+    #[test]
+    fn errors_point_at_the_original_source() {
+        let res = crate::compile_and_run(&bad_halt(&[(2, LOC)]), &mut Vec::new(), crate::vm::Limits::default());
+        let Err(crate::CompileOrRuntimeError::Verify(e)) = res else {
+            panic!("a halt with nothing on the stack verified");
+        };
+        assert!(matches!(*e, Error::Located(l, ref e) if l == LOC && matches!(**e, Error::TypeErrorEmptyStack(2, _))));
+    }
 }
@@ -60,12 +60,52 @@ impl Pretty for Op1 {
             Op1::I32ToU8 => "i32_to_u8".to_string(),
             Op1::Read(c) => "read ".to_string() + &c.to_string(),
             Op1::Write(c) => "write ".to_string() + &c.to_string(),
+            Op1::Tagged => "tagged".to_string(),
+            Op1::PackTagged => "pack_tagged".to_string(),
+            Op1::CheckCast(a, b) => "check_cast ".to_string() + &a.to_string() + " " + &b.to_string(),
+            Op1::Unreachable => "unreachable".to_string(),
+            Op1::ResetRgn => "reset_rgn".to_string(),
+            Op1::Rotate(n) => "rotate ".to_string() + &n.to_string(),
+            Op1::Loop => "loop".to_string(),
+            Op1::Break => "break".to_string(),
+            Op1::Continue => "continue".to_string(),
+            Op1::LoopEnd => "loop_end".to_string(),
+            Op1::Select => "select".to_string(),
+            Op1::ArrLen => "arr_len".to_string(),
+            Op1::StackDepth => "stack_depth".to_string(),
+            Op1::DropHandle => "drop_handle".to_string(),
+            Op1::MemCopy => "mem_copy".to_string(),
+            Op1::Sub => "sub".to_string(),
+            Op1::Rec => "rec".to_string(),
+            Op1::Fold => "fold".to_string(),
+            Op1::Unfold => "unfold".to_string(),
+            Op1::Sum(n) => "sum ".to_string() + &n.to_string(),
+            Op1::Inj(i) => "inj ".to_string() + &i.to_string(),
+            Op1::Case(n) => "case ".to_string() + &n.to_string(),
+            Op1::I64 => "i64".to_string(),
+            Op1::F64 => "f64".to_string(),
+            Op1::LitI64(i) => "lit_i64 ".to_string() + &i.to_string(),
+            Op1::LitF64(bits) => "lit_f64 ".to_string() + &f64::from_bits(*bits).to_string(),
+            Op1::I64ToI32 => "i64_to_i32".to_string(),
+            Op1::F64ToI64 => "f64_to_i64".to_string(),
+            Op1::Dup => "dup".to_string(),
+            Op1::Swap => "swap".to_string(),
+            Op1::Pop => "pop".to_string(),
+            Op1::SizeOf => "size_of".to_string(),
+            Op1::SizeAdd => "size_add".to_string(),
+            Op1::SizeMul(n) => "size_mul ".to_string() + &n.to_string(),
+            Op1::Clean(n) => "clean ".to_string() + &n.to_string(),
+            Op1::Heap => "heap".to_string(),
+            Op1::Nop => "nop".to_string(),
+            Op1::Assert => "assert".to_string(),
+            Op1::GetLocal(n) => "get_local ".to_string() + &n.to_string(),
+            Op1::ArrAlloc => "arr_alloc".to_string(),
         }
     }
 }
 
 fn int_pair_to_str(a: &u64, b: &u64) -> String {
-    return std::str::from_utf8(&a.to_le_bytes()).unwrap().to_owned() + &std::str::from_utf8(&b.to_le_bytes()).unwrap();
+    std::str::from_utf8(&a.to_le_bytes()).unwrap().to_owned() + std::str::from_utf8(&b.to_le_bytes()).unwrap()
 }
 
 impl Pretty for Op2 {
@@ -106,6 +146,38 @@ impl Pretty for Op2 {
             Op2::I32ToU8 => "i32_to_u8".to_string(),
             Op2::Read(c) => "read ".to_string() + &c.to_string(),
             Op2::Write(c) => "write ".to_string() + &c.to_string(),
+            Op2::PackTagged(size, tag) => "pack_tagged ".to_string() + &size.to_string() + " " + &tag.to_string(),
+            Op2::CheckCast(tag, a, b) => "check_cast ".to_string() + &tag.to_string() + " " + &a.to_string() + " " + &b.to_string(),
+            Op2::Unreachable => "unreachable".to_string(),
+            Op2::ResetRgn => "reset_rgn".to_string(),
+            Op2::Rotate(s1, s2) => "rotate ".to_string() + &s1.to_string() + " " + &s2.to_string(),
+            Op2::Jump(target) => "jump ".to_string() + &target.to_string(),
+            Op2::JumpNZ(target) => "jump_nz ".to_string() + &target.to_string(),
+            Op2::Select(s) => "select ".to_string() + &s.to_string(),
+            Op2::ArrLen(s) => "arr_len ".to_string() + &s.to_string(),
+            Op2::Pop(s) => "pop ".to_string() + &s.to_string(),
+            Op2::MemCopy(s) => "mem_copy ".to_string() + &s.to_string(),
+            Op2::SubI32 => "sub_i32".to_string(),
+            Op2::SubU8 => "sub_u8".to_string(),
+            Op2::Inj(size, pad, i) => "inj ".to_string() + &size.to_string() + " " + &pad.to_string() + " " + &i.to_string(),
+            Op2::Case(n) => "case ".to_string() + &n.to_string(),
+            Op2::Lit64(bits) => "lit64 ".to_string() + &bits.to_string(),
+            Op2::AddI64 => "add_i64".to_string(),
+            Op2::SubI64 => "sub_i64".to_string(),
+            Op2::MulI64 => "mul_i64".to_string(),
+            Op2::DivI64 => "div_i64".to_string(),
+            Op2::ModuloI64 => "modulo_i64".to_string(),
+            Op2::AddF64 => "add_f64".to_string(),
+            Op2::SubF64 => "sub_f64".to_string(),
+            Op2::MulF64 => "mul_f64".to_string(),
+            Op2::DivF64 => "div_f64".to_string(),
+            Op2::I64ToI32 => "i64_to_i32".to_string(),
+            Op2::F64ToI64 => "f64_to_i64".to_string(),
+            Op2::Dup(size) => "dup ".to_string() + &size.to_string(),
+            Op2::Swap(s1, s2) => "swap ".to_string() + &s1.to_string() + " " + &s2.to_string(),
+            Op2::Clean(s1, s2) => "clean ".to_string() + &s1.to_string() + " " + &s2.to_string(),
+            Op2::HeapHandle => "heap_handle".to_string(),
+            Op2::Assert(tag) => "assert ".to_string() + &tag.to_string(),
         }
     }
 }
@@ -126,20 +198,46 @@ impl Pretty for Stmt2 {
     }
 }
 
+/// Render a verified program with one function per paragraph: its label and type, then one op per line.
+/// The ops have the sizes and offsets verification worked out, in the order `Op2` lists them.
+pub fn disassemble(program: &IRProgram) -> String {
+    let mut out = String::new();
+    let mut imports: Vec<_> = program.imports.iter().collect();
+    imports.sort();
+    for (label, (a, b)) in imports {
+        out += &format!("import {} {} as func {}\n", a, b, label);
+    }
+    let mut exports: Vec<_> = program.exports.iter().collect();
+    exports.sort();
+    for ((a, b), label) in exports {
+        out += &format!("export {} {} as func {}\n", a, b, label);
+    }
+    for Stmt2::Func(label, t, ops) in &program.funcs {
+        if !out.is_empty() {
+            out += "\n";
+        }
+        out += &format!("func {}: {}\n", label, t.pretty());
+        for op in ops {
+            out += &format!("    {}\n", op.pretty());
+        }
+    }
+    out
+}
+
 impl Pretty for RgnId {
     fn pretty(&self) -> String {
         match self {
             RgnId::Var(id) => "r".to_string() + &id.1.to_string(),
             RgnId::DataSection => "data_section".to_string(),
+            RgnId::Heap => "heap".to_string(),
         }
     }
 }
 
+/// Unique regions are marked with a `!`.
 impl Pretty for Region {
     fn pretty(&self) -> String {
-        match self {
-            Region { id, .. } => id.pretty()
-        }
+        self.id.pretty() + own_suffix(self)
     }
 }
 
@@ -148,23 +246,26 @@ impl Pretty for Type {
         match self {
             Type::I32 => "i32".to_string(),
             Type::U8 => "u8".to_string(),
+            Type::I64 => "i64".to_string(),
+            Type::F64 => "f64".to_string(),
             Type::Handle(r) => "handle(".to_string() + &r.pretty() + ")",
-            Type::Tuple(ts) => "(".to_string() + &ts.iter().map(|(_, t)| t.pretty()).collect::<Vec<String>>().join(", ") + ")",
+            Type::Tuple(ts) => "(".to_string() + &ts.iter().map(|(init, t)| if *init { t.pretty() } else { "uninit ".to_string() + &t.pretty() }).collect::<Vec<String>>().join(", ") + ")",
             Type::Ptr(t, r) => t.pretty() + "@" + &r.pretty(),
             Type::Var(id, _) => "a".to_string() + &id.1.to_string(),
             Type::Func(ts) => "(".to_string() + &ts.iter().map(|t| t.pretty()).collect::<Vec<String>>().join(", ") + ")->0",
             Type::Forall(id, size, t) => "forall a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
-            Type::ForallRegion(r, t, _) => "forall ".to_string() + &r.pretty() + ": Rgn" + own_suffix(r) + ". " + &t.pretty(),
+            Type::ForallRegion(r, t, _) => "forall ".to_string() + &r.id.pretty() + ": Rgn" + own_suffix(r) + ". " + &t.pretty(),
             Type::Exists(id, size, t) => "exists a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
             Type::Array(t, r) => t.pretty() + "[]@" + &r.pretty(),
+            Type::TaggedExists(id, size, t) => "tagged exists a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
+            Type::Sum(ts) => "(".to_string() + &ts.iter().map(|t| t.pretty()).collect::<Vec<String>>().join(" | ") + ")",
+            Type::Rec(id, size, t) => "rec a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
         }
     }
 }
 
 fn own_suffix(r: &Region) -> &str {
-    match r {
-        Region { unique, .. } => if *unique { "!" } else { "" },
-    }
+    if r.unique { "!" } else { "" }
 }
 
 impl Pretty for Kind {
@@ -186,3 +287,68 @@ impl Pretty for CTStackVal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::tests::assemble;
+
+    #[test]
+    fn disassembly_shows_offsets_and_sizes() {
+        let module = assemble(
+            "
+            func 0:
+            func 0
+            lced
+            lit 9
+            lit 2
+            get 1
+            mul
+            add
+            global_func 1
+            call
+            func 1:
+            i32
+            func 1
+            import 1 1
+            func 2:
+            i32
+            func 1
+            export 2 2
+            i32_to_u8
+            halt
+        ",
+        );
+        // the 9 is 4 bytes under the top, and the imported function has no body to show
+        let expected = "\
+import 1 1 as func 1
+export 2 2 as func 2
+
+func 0: ()->0
+    lit 9
+    lit 2
+    get 4 4
+    mul_i32
+    add_i32
+    global_func 1
+    call
+
+func 2: (i32)->0
+    i32_to_u8
+    halt
+";
+        assert_eq!(disassemble(&compile(vec![module]).unwrap()), expected);
+    }
+
+    #[test]
+    fn types_show_init_flags_and_uniqueness() {
+        let r = |unique| Region { unique, id: RgnId::Var(Id(0, 1)) };
+        let pair = Type::Tuple(vec![(true, Type::I32), (false, Type::U8)]);
+        assert_eq!(Type::Ptr(Box::new(pair), r(true)).pretty(), "(i32, uninit u8)@r1!");
+        assert_eq!(Type::Handle(r(false)).pretty(), "handle(r1)");
+        // the binder shows its uniqueness once
+        let forall = Type::ForallRegion(r(true), Box::new(Type::Func(vec![Type::Handle(r(true))])), vec![]);
+        assert_eq!(forall.pretty(), "forall r1: Rgn!. (handle(r1!))->0");
+    }
+}
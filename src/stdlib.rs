@@ -0,0 +1,192 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::header::*;
+use crate::verify;
+
+/// The first half of the UID of every standard library function.
+const STD_NAMESPACE: u64 = u64::from_le_bytes(*b"saberstd");
+
+/// `((i32, i32), ((i32, i32))->0)->0`
+/// Calls the continuation with the components of the pair swapped.
+pub const SWAP_PAIR: (u64, u64) = (STD_NAMESPACE, 0);
+
+/// `forall r: Rgn. (handle(r), i32, i32, ((i32, i32)@r)->0)->0`
+/// Allocates the two i32s as a pair in the region and calls the continuation with the pointer.
+pub const ALLOC_PAIR: (u64, u64) = (STD_NAMESPACE, 1);
+
+/// A standard library function's UID, and the ops for its type and body.
+type StdFunc = ((u64, u64), Vec<Op1>, Vec<Op1>);
+
+/// The standard library as (UID, type, body) triples.
+/// Functions in SaberVM don't return, so every one of these takes a continuation as its last parameter.
+fn funcs() -> Vec<StdFunc> {
+    vec![
+        (
+            SWAP_PAIR,
+            vec![
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Func(1),
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Func(2),
+            ],
+            vec![
+                Op1::Get(0),
+                Op1::Proj(0),
+                Op1::Rotate(1),
+                Op1::Proj(1),
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Malloc,
+                Op1::Rotate(1),
+                Op1::Init(0),
+                Op1::Rotate(1),
+                Op1::Init(1),
+                Op1::Rotate(1),
+                Op1::Call,
+            ],
+        ),
+        (
+            ALLOC_PAIR,
+            vec![
+                Op1::Rgn,
+                Op1::CTGet(0),
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Ptr,
+                Op1::Func(1),
+                Op1::I32,
+                Op1::I32,
+                Op1::CTGet(3),
+                Op1::Handle,
+                Op1::Func(4),
+                Op1::End,
+            ],
+            vec![
+                Op1::CTGet(0),
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Ptr,
+                Op1::Malloc,
+                Op1::Rotate(1),
+                Op1::Init(0),
+                Op1::Rotate(1),
+                Op1::Init(1),
+                Op1::Rotate(1),
+                Op1::Call,
+            ],
+        ),
+    ]
+}
+
+/// Build and verify the standard library.
+/// It's linked in after the user's programs that import its functions by UID.
+pub fn go() -> Result<IRProgram, Error> {
+    let funcs = funcs();
+    let mut forward_decs = vec![];
+    let mut stmts = vec![];
+    // positions are laid out as if the library were a bytecode file:
+    // all the declarations, then all the bodies
+    let mut pos = 0;
+    for (label, ((a, b), type_ops, _)) in (0..).zip(&funcs) {
+        forward_decs.push(ForwardDec::Func(label, pos, Visibility::Export(*a, *b), type_ops.clone()));
+        pos += type_ops.len() as u32 + 1;
+    }
+    for (label, (_, _, body_ops)) in (0..).zip(funcs) {
+        let len = body_ops.len() as u32;
+        stmts.push(Stmt1::Func(label, pos, body_ops));
+        pos += len;
+    }
+    verify::library(vec![], &forward_decs, stmts, &verify::VerifyOptions::default())
+}
+
+/// The programs to run for some verified programs: the programs themselves,
+/// followed by the standard library if any of them imports anything from it.
+pub fn link(mut programs: Vec<IRProgram>) -> Result<Vec<IRProgram>, Error> {
+    let uses_std = programs.iter().flat_map(|p| p.imports.values()).any(|(a, _)| *a == STD_NAMESPACE);
+    if uses_std {
+        programs.push(go()?);
+    }
+    Ok(programs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm;
+
+    /// Run `main` with the standard library function `uid` imported as function 1,
+    /// halting with what the continuation, function 2, halts with.
+    fn run_with_import(main: Vec<Op1>, import: Vec<Op1>, uid: (u64, u64), cont: (Vec<Op1>, Vec<Op1>)) -> u8 {
+        let (cont_type, cont_body) = cont;
+        let forward_decs = vec![
+            ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)]),
+            ForwardDec::Func(1, 0, Visibility::Import(uid.0, uid.1), import),
+            ForwardDec::Func(2, 0, Visibility::Local, cont_type),
+        ];
+        let program = verify::go_default(vec![], &forward_decs, vec![Stmt1::Func(0, 0, main), Stmt1::Func(2, 0, cont_body)]).unwrap();
+        vm::go(link(vec![program]).unwrap(), &mut vm::Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &vm::Limits::default()).unwrap()
+    }
+
+    #[test]
+    fn stdlib_verifies() {
+        assert!(go().is_ok());
+    }
+
+    #[test]
+    fn swap_pair() {
+        let pair = vec![Op1::I32, Op1::I32, Op1::Tuple(2)];
+        let main = [
+            vec![Op1::GlobalFunc(2)],
+            pair.clone(),
+            vec![Op1::Malloc, Op1::Lit(3), Op1::Init(0), Op1::Lit(8), Op1::Init(1), Op1::GlobalFunc(1), Op1::Call],
+        ];
+        let import = [pair.clone(), vec![Op1::Func(1)], pair.clone(), vec![Op1::Func(2)]].concat();
+        let cont = ([pair, vec![Op1::Func(1)]].concat(), vec![Op1::Proj(0), Op1::I32ToU8, Op1::Halt]);
+        assert_eq!(run_with_import(main.concat(), import, SWAP_PAIR, cont), 8);
+    }
+
+    #[test]
+    fn alloc_pair() {
+        let pair_ptr = vec![Op1::CTGet(0), Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Func(1)];
+        let main = vec![
+            Op1::NewRgn(64),
+            Op1::GlobalFunc(2),
+            Op1::CTGet(0),
+            Op1::App,
+            Op1::Lit(8),
+            Op1::Lit(3),
+            Op1::Rotate(3),
+            Op1::GlobalFunc(1),
+            Op1::CTGet(0),
+            Op1::App,
+            Op1::Call,
+        ];
+        let import =
+            [vec![Op1::Rgn], pair_ptr.clone(), vec![Op1::I32, Op1::I32, Op1::CTGet(3), Op1::Handle, Op1::Func(4), Op1::End]]
+                .concat();
+        let cont = ([vec![Op1::Rgn], pair_ptr, vec![Op1::End]].concat(), vec![Op1::Proj(0), Op1::I32ToU8, Op1::Halt]);
+        assert_eq!(run_with_import(main, import, ALLOC_PAIR, cont), 3);
+    }
+
+    #[test]
+    fn linked_only_when_imported() {
+        let program = IRProgram {
+            data_section: vec![],
+            imports: Default::default(),
+            exports: Default::default(),
+            funcs: vec![],
+        };
+        assert_eq!(link(vec![program]).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,469 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::header::*;
+use crate::link::Module;
+use crate::verify::type_eq;
+
+/// A linear congruential generator, so a corpus only depends on its seed.
+#[derive(Clone)]
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, n: u32) -> u32 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as u32) % n
+    }
+}
+
+/// A generated program and the status it has to exit with.
+pub struct Case {
+    pub module: Module,
+    pub expected: u8,
+}
+
+/// Generate `count` well-typed programs from `seed`, the same ones every time.
+/// Each program threads an i32 through straight-line code using tuples, regions, and existentials,
+/// then passes it down a chain of calls (some region-polymorphic) until one of them halts with it.
+pub fn well_typed_programs(seed: u64, count: usize) -> Vec<Case> {
+    let mut rng = Lcg(seed);
+    (0..count).map(|_| program(&mut rng)).collect()
+}
+
+/// Where a region-polymorphic function's region is, relative to the tops of the stacks.
+#[derive(Clone, Copy)]
+struct RgnParam {
+    /// How many compile-time values are above the region.
+    ct_depth: u8,
+    /// How many values are between the region's handle and the accumulator.
+    below: u8,
+}
+
+fn program(rng: &mut Lcg) -> Case {
+    let poly: Vec<bool> = (0..rng.below(4)).map(|_| rng.below(2) == 0).collect();
+    let mut acc = rng.below(100) as i32;
+    let mut funcs = vec![(vec![Op1::Func(0)], vec![Op1::Lit(acc)])];
+    for &p in &poly {
+        let t = if p {
+            vec![Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::I32, Op1::Func(2), Op1::End]
+        } else {
+            vec![Op1::I32, Op1::Func(1)]
+        };
+        funcs.push((t, vec![]));
+    }
+    // function i calls function i + 1, and the last one halts
+    for i in 0..funcs.len() {
+        let mut rgn = (i > 0 && poly[i - 1]).then_some(RgnParam { ct_depth: 0, below: 0 });
+        let ops = &mut funcs[i].1;
+        for _ in 0..rng.below(6) {
+            snippet(rng, ops, &mut acc, &mut rgn);
+        }
+        match poly.get(i) {
+            None => ops.extend([Op1::I32ToU8, Op1::Halt]),
+            Some(false) => ops.extend([Op1::GlobalFunc(i as Label + 1), Op1::Call]),
+            Some(true) => ops.extend([
+                // enough for every allocation the callee can make
+                Op1::NewRgn(256),
+                Op1::Rotate(1),
+                Op1::GlobalFunc(i as Label + 1),
+                Op1::CTGet(0),
+                Op1::Call,
+            ]),
+        }
+    }
+    let mut module = Module::default();
+    let mut pos = 0;
+    for (label, (t, _)) in (0..).zip(&funcs) {
+        module.forward_decs.push(ForwardDec::Func(label, pos, Visibility::Local, t.clone()));
+        pos += t.len() as Pos + 1;
+    }
+    for (label, (_, body)) in (0..).zip(funcs) {
+        let len = body.len() as Pos;
+        module.stmts.push(Stmt1::Func(label, pos, body));
+        pos += len;
+    }
+    Case {
+        module,
+        expected: acc as u8,
+    }
+}
+
+/// Append a piece of code that replaces the i32 on top of the stack, keeping track of its value.
+fn snippet(rng: &mut Lcg, ops: &mut Vec<Op1>, acc: &mut i32, rgn: &mut Option<RgnParam>) {
+    let k = rng.below(100) as i32;
+    match rng.below(if rgn.is_some() { 7 } else { 6 }) {
+        0 => {
+            ops.extend([Op1::Lit(k), Op1::Add]);
+            *acc += k;
+        }
+        1 => {
+            ops.extend([Op1::Lit(k), Op1::Mul, Op1::Lit(1000), Op1::Modulo]);
+            *acc = *acc * k % 1000;
+        }
+        2 => {
+            // a tuple on the stack
+            let i = rng.below(2) as u8;
+            ops.extend([
+                Op1::I32,
+                Op1::I32,
+                Op1::Tuple(2),
+                Op1::Malloc,
+                Op1::Rotate(1),
+                Op1::Init(0),
+                Op1::Lit(k),
+                Op1::Init(1),
+                Op1::Proj(i),
+            ]);
+            if i == 1 {
+                *acc = k;
+            }
+        }
+        3 => {
+            // a round trip through a fresh region, whose variable stays on the compile-time stack
+            ops.extend([
+                Op1::NewRgn(64),
+                Op1::Get(0),
+                Op1::CTGet(0),
+                Op1::I32,
+                Op1::Tuple(1),
+                Op1::Ptr,
+                Op1::Malloc,
+                Op1::Rotate(2),
+                Op1::Init(0),
+                Op1::Proj(0),
+                Op1::Rotate(1),
+                Op1::FreeRgn,
+            ]);
+            if let Some(rgn) = rgn {
+                rgn.ct_depth += 1;
+            }
+        }
+        4 => {
+            // pack a copy as `exists a: 4. a`, which stays behind on the stack,
+            // and the witness of unpacking it stays on the compile-time stack
+            ops.extend([
+                Op1::Get(0),
+                Op1::Size(4),
+                Op1::Some,
+                Op1::CTGet(0),
+                Op1::End,
+                Op1::I32,
+                Op1::Pack,
+                Op1::Unpack,
+                Op1::Rotate(1),
+            ]);
+            if let Some(rgn) = rgn {
+                rgn.below += 1;
+                rgn.ct_depth += 1;
+            }
+        }
+        5 => {
+            let cond = rng.below(2) as i32;
+            ops.extend([Op1::Lit(k), Op1::Lit(cond), Op1::Select]);
+            if cond != 0 {
+                *acc = k;
+            }
+        }
+        _ => {
+            // a round trip through the region the function is polymorphic over
+            let rgn = rgn.unwrap();
+            ops.extend([
+                Op1::Get(rgn.below + 1),
+                Op1::CTGet(rgn.ct_depth),
+                Op1::I32,
+                Op1::Tuple(1),
+                Op1::Ptr,
+                Op1::Malloc,
+                Op1::Rotate(1),
+                Op1::Init(0),
+                Op1::Proj(0),
+            ]);
+        }
+    }
+}
+
+/// Generate `count` pairs of types to compare with `type_eq`, the same ones every time.
+/// About half of the pairs are the same type with different bound variable names.
+pub fn type_pairs(seed: u64, count: usize) -> Vec<(Type, Type)> {
+    let mut rng = Lcg(seed);
+    (0..count)
+        .map(|_| {
+            let alpha_equivalent = rng.below(2) == 0;
+            let mut shape = rng.clone();
+            // the second type's binders are numbered one behind the first's,
+            // so a binder on one side has the same id as the next binder in on the other side
+            let a = random_type(&mut rng, 3, &mut vec![], &mut Id(1, 1));
+            if alpha_equivalent {
+                // the same random choices with different binder ids give an alpha-equivalent type
+                (a, random_type(&mut shape, 3, &mut vec![], &mut Id(1, 0)))
+            } else {
+                (a, random_type(&mut rng, 3, &mut vec![], &mut Id(1, 0)))
+            }
+        })
+        .collect()
+}
+
+enum Bound {
+    T(Id, usize),
+    R(Region),
+}
+
+/// A random type whose variables are all bound, with new binders named from `next_id`.
+/// The random choices don't depend on `next_id`, so two types made from the same generator state
+/// differ only in the names of their bound variables.
+fn random_type(rng: &mut Lcg, depth: u32, scope: &mut Vec<Bound>, next_id: &mut Id) -> Type {
+    let region = |rng: &mut Lcg, scope: &[Bound]| {
+        let bound: Vec<Region> = scope.iter().filter_map(|b| if let Bound::R(r) = b { Some(*r) } else { None }).collect();
+        let unique = rng.below(2) == 0;
+        match rng.below(bound.len() as u32 + 3) as usize {
+            // a free region, which the two types share
+            0 => Region { unique, id: RgnId::Var(Id(0, 0)) },
+            1 => Region { unique: false, id: RgnId::DataSection },
+            2 => Region { unique: false, id: RgnId::Heap },
+            i => Region { unique, ..bound[i - 3] },
+        }
+    };
+    let mut fresh = || {
+        next_id.1 += 1;
+        *next_id
+    };
+    match rng.below(if depth == 0 { 4 } else { 14 }) {
+        0 => Type::I32,
+        1 => Type::U8,
+        2 => Type::Handle(region(rng, scope)),
+        3 => {
+            let vars: Vec<Type> = scope.iter().filter_map(|b| if let Bound::T(id, s) = b { Some(Type::Var(*id, *s)) } else { None }).collect();
+            let i = rng.below(vars.len() as u32 + 1) as usize;
+            vars.get(i).cloned().unwrap_or(Type::I32)
+        }
+        4 => Type::Tuple((0..rng.below(3)).map(|_| (rng.below(2) == 0, random_type(rng, depth - 1, scope, next_id))).collect()),
+        5 => Type::Ptr(Box::new(random_type(rng, depth - 1, scope, next_id)), region(rng, scope)),
+        6 => Type::Array(Box::new(random_type(rng, depth - 1, scope, next_id)), region(rng, scope)),
+        7 => Type::Func((0..rng.below(3)).map(|_| random_type(rng, depth - 1, scope, next_id)).collect()),
+        13 => Type::Sum((0..rng.below(3)).map(|_| random_type(rng, depth - 1, scope, next_id)).collect()),
+        8 => {
+            let captured_rgns = (0..rng.below(3)).map(|_| region(rng, scope)).collect();
+            let r = Region { unique: rng.below(2) == 0, id: RgnId::Var(fresh()) };
+            scope.push(Bound::R(r));
+            let body = random_type(rng, depth - 1, scope, next_id);
+            scope.pop();
+            Type::ForallRegion(r, Box::new(body), captured_rgns)
+        }
+        quantifier => {
+            let (id, s) = (fresh(), 4 * (rng.below(2) as usize + 1));
+            scope.push(Bound::T(id, s));
+            let body = Box::new(random_type(rng, depth - 1, scope, next_id));
+            scope.pop();
+            match quantifier {
+                9 => Type::Forall(id, s, body),
+                10 => Type::Exists(id, s, body),
+                11 => Type::TaggedExists(id, s, body),
+                _ => Type::Rec(id, s, body),
+            }
+        }
+    }
+}
+
+/// A deliberately simple second implementation of `type_eq` to check it against:
+/// both types are put in a normal form and compared structurally.
+pub fn type_eq_reference(a: &Type, b: &Type) -> bool {
+    fn same(a: &Type, b: &Type) -> bool {
+        match (a, b) {
+            (Type::Func(ts1), Type::Func(ts2)) | (Type::Sum(ts1), Type::Sum(ts2)) => {
+                ts1.len() == ts2.len() && ts1.iter().zip(ts2).all(|(t1, t2)| same(t1, t2))
+            }
+            (Type::Tuple(ts1), Type::Tuple(ts2)) => {
+                ts1.len() == ts2.len() && ts1.iter().zip(ts2).all(|((i1, t1), (i2, t2))| i1 == i2 && same(t1, t2))
+            }
+            (Type::Ptr(t1, r1), Type::Ptr(t2, r2)) | (Type::Array(t1, r1), Type::Array(t2, r2)) => r1 == r2 && same(t1, t2),
+            (Type::Forall(id1, s1, t1), Type::Forall(id2, s2, t2))
+            | (Type::Exists(id1, s1, t1), Type::Exists(id2, s2, t2))
+            | (Type::TaggedExists(id1, s1, t1), Type::TaggedExists(id2, s2, t2))
+            | (Type::Rec(id1, s1, t1), Type::Rec(id2, s2, t2)) => id1 == id2 && s1 == s2 && same(t1, t2),
+            (Type::ForallRegion(r1, t1, c1), Type::ForallRegion(r2, t2, c2)) => {
+                r1 == r2 && r1.unique == r2.unique && c1 == c2 && same(t1, t2)
+            }
+            (a, b) => a == b,
+        }
+    }
+    same(&normalize(a, &mut vec![]), &normalize(b, &mut vec![]))
+}
+
+/// Name bound variables by how many binders are between them and the top of the type,
+/// forget the uniqueness of each use of a region, which `type_eq` ignores, and sort and deduplicate captured regions.
+/// A region binder keeps its uniqueness, since that's what the function is allowed to do with the region.
+fn normalize(t: &Type, binders: &mut Vec<Bound>) -> Type {
+    let canonical = |n: usize| Id(u32::MAX, n as u32);
+    let region = |r: &Region, binders: &[Bound]| {
+        let id = match binders.iter().rposition(|b| matches!(b, Bound::R(r2) if r2.id == r.id)) {
+            Some(n) => RgnId::Var(canonical(n)),
+            None => r.id,
+        };
+        Region { unique: false, id }
+    };
+    fn under(b: Bound, t: &Type, binders: &mut Vec<Bound>) -> Type {
+        binders.push(b);
+        let t = normalize(t, binders);
+        binders.pop();
+        t
+    }
+    match t {
+        Type::I32 | Type::U8 | Type::I64 | Type::F64 => t.clone(),
+        Type::Handle(r) => Type::Handle(region(r, binders)),
+        Type::Tuple(ts) => Type::Tuple(ts.iter().map(|(init, t)| (*init, normalize(t, binders))).collect()),
+        Type::Ptr(t, r) => Type::Ptr(Box::new(normalize(t, binders)), region(r, binders)),
+        Type::Array(t, r) => Type::Array(Box::new(normalize(t, binders)), region(r, binders)),
+        Type::Func(ts) => Type::Func(ts.iter().map(|t| normalize(t, binders)).collect()),
+        Type::Sum(ts) => Type::Sum(ts.iter().map(|t| normalize(t, binders)).collect()),
+        Type::Var(id, s) => match binders.iter().rposition(|b| matches!(b, Bound::T(id2, _) if id2 == id)) {
+            Some(n) => Type::Var(canonical(n), *s),
+            None => t.clone(),
+        },
+        Type::Forall(id, s, body) => {
+            Type::Forall(canonical(binders.len()), *s, Box::new(under(Bound::T(*id, *s), body, binders)))
+        }
+        Type::Exists(id, s, body) => {
+            Type::Exists(canonical(binders.len()), *s, Box::new(under(Bound::T(*id, *s), body, binders)))
+        }
+        Type::TaggedExists(id, s, body) => {
+            Type::TaggedExists(canonical(binders.len()), *s, Box::new(under(Bound::T(*id, *s), body, binders)))
+        }
+        Type::Rec(id, s, body) => {
+            Type::Rec(canonical(binders.len()), *s, Box::new(under(Bound::T(*id, *s), body, binders)))
+        }
+        Type::ForallRegion(r, body, captured_rgns) => {
+            let canonical_r = Region { unique: r.unique, id: RgnId::Var(canonical(binders.len())) };
+            let mut captured_rgns: Vec<Region> = captured_rgns.iter().map(|r| region(r, binders)).collect();
+            captured_rgns.sort_by_key(|r| r.id);
+            captured_rgns.dedup();
+            Type::ForallRegion(canonical_r, Box::new(under(Bound::R(*r), body, binders)), captured_rgns)
+        }
+    }
+}
+
+/// The pairs of types `type_eq` and `type_eq_reference` disagree on.
+pub fn type_eq_disagreements(pairs: &[(Type, Type)]) -> Vec<&(Type, Type)> {
+    pairs.iter().filter(|(a, b)| type_eq(a, b) != type_eq_reference(a, b)).collect()
+}
+
+/// Generate `count` modules of random ops, the same ones every time.
+/// They're only well-formed enough to parse, with each declaration and body ending in a terminator
+/// and no terminators anywhere else, so encoding and parsing them should give them back unchanged.
+pub fn random_modules(seed: u64, count: usize) -> Vec<Module> {
+    let mut rng = Lcg(seed);
+    (0..count).map(|_| random_module(&mut rng)).collect()
+}
+
+fn random_module(rng: &mut Lcg) -> Module {
+    let data_section = (0..rng.below(4)).map(|_| rng.below(256) as u8).collect();
+    // positions count ops, terminators included, from the first declaration on, like the parser does
+    let mut pos = 0;
+    let mut forward_decs = vec![];
+    for label in 0..=rng.below(3) {
+        let ops: Vec<Op1> = (0..rng.below(6)).map(|_| random_op(rng)).collect();
+        let visibility = match rng.below(3) {
+            0 => Visibility::Local,
+            1 => Visibility::Export(random_u64(rng), random_u64(rng)),
+            _ => Visibility::Import(random_u64(rng), random_u64(rng)),
+        };
+        forward_decs.push(ForwardDec::Func(label, pos, visibility, ops));
+        pos += forward_decs.last().map_or(0, |ForwardDec::Func(_, _, _, ops)| ops.len() as u32 + 1);
+    }
+    let mut stmts = vec![];
+    for ForwardDec::Func(label, _, visibility, _) in &forward_decs {
+        if let Visibility::Import(..) = visibility {
+            continue;
+        }
+        let mut ops: Vec<Op1> = (0..rng.below(6)).map(|_| random_op(rng)).collect();
+        ops.push(match rng.below(6) {
+            0 => Op1::Call,
+            1 => Op1::CallNZ,
+            2 => Op1::Halt,
+            3 => Op1::CheckCast(rng.below(u32::MAX), rng.below(u32::MAX)),
+            4 => Op1::Unreachable,
+            _ => Op1::Case(rng.below(256) as u8),
+        });
+        stmts.push(Stmt1::Func(*label, pos, ops.clone()));
+        pos += ops.len() as u32;
+    }
+    Module { data_section, forward_decs, stmts, debug_info: DebugInfo::default() }
+}
+
+fn random_u64(rng: &mut Lcg) -> u64 {
+    (u64::from(rng.below(u32::MAX)) << 32) | u64::from(rng.below(u32::MAX))
+}
+
+/// A random op with random operands, other than the ones that end a declaration or a body.
+fn random_op(rng: &mut Lcg) -> Op1 {
+    let byte = rng.below(256) as u8;
+    let word = rng.below(u32::MAX);
+    let wide = random_u64(rng);
+    let ops = [
+        Op1::Unique, Op1::Handle, Op1::I32, Op1::Tuple(byte), Op1::Some, Op1::All, Op1::Rgn, Op1::End, Op1::App,
+        Op1::Func(byte), Op1::CTGet(byte), Op1::Unpack, Op1::Get(byte), Op1::Init(byte), Op1::Malloc, Op1::Proj(byte),
+        Op1::Lit(word as i32), Op1::GlobalFunc(word), Op1::Pack, Op1::Size(word), Op1::NewRgn(word), Op1::FreeRgn,
+        Op1::Ptr, Op1::Deref, Op1::Arr, Op1::ArrMut, Op1::ArrProj, Op1::Add, Op1::Mul, Op1::Div, Op1::Data(word),
+        Op1::DataSec, Op1::U8, Op1::CopyN, Op1::U8Lit(byte), Op1::U8ToI32, Op1::Modulo, Op1::I32ToU8, Op1::Read(byte),
+        Op1::Write(byte), Op1::Tagged, Op1::PackTagged, Op1::ResetRgn, Op1::Rotate(byte), Op1::Loop, Op1::Break,
+        Op1::Continue, Op1::LoopEnd, Op1::Select, Op1::ArrLen, Op1::StackDepth, Op1::DropHandle, Op1::MemCopy, Op1::Sub,
+        Op1::Rec, Op1::Fold, Op1::Unfold, Op1::Sum(byte), Op1::Inj(byte), Op1::I64, Op1::F64, Op1::LitI64(wide as i64),
+        Op1::LitF64(wide), Op1::I64ToI32, Op1::F64ToI64, Op1::Dup, Op1::Swap, Op1::Pop, Op1::SizeOf, Op1::SizeAdd,
+        Op1::SizeMul(byte), Op1::Clean(byte), Op1::Heap, Op1::Nop, Op1::Assert, Op1::GetLocal(byte), Op1::ArrAlloc,
+    ];
+    ops[rng.below(ops.len() as u32) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::{self, type_hash};
+    use crate::vm;
+
+    #[test]
+    fn corpus_depends_only_on_the_seed() {
+        let stmts = |seed| well_typed_programs(seed, 20).into_iter().map(|case| format!("{:?}", case.module.stmts)).collect::<Vec<_>>();
+        assert_eq!(stmts(7), stmts(7));
+        assert_ne!(stmts(7), stmts(8));
+    }
+
+    #[test]
+    fn corpus_programs_verify_and_exit_as_expected() {
+        for case in well_typed_programs(0, 100) {
+            let module = case.module;
+            let ir_program = verify::go_default(module.data_section, &module.forward_decs, module.stmts).unwrap();
+            let status = vm::go(vec![ir_program], &mut vm::Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &vm::Limits::default());
+            assert_eq!(status, Ok(case.expected));
+        }
+    }
+
+    #[test]
+    fn type_eq_matches_reference() {
+        for seed in 0..20 {
+            assert!(type_eq_disagreements(&type_pairs(seed, 500)).is_empty());
+        }
+    }
+
+    #[test]
+    fn func_arities_must_match() {
+        let (unary, nullary) = (Type::Func(vec![Type::I32]), Type::Func(vec![]));
+        assert!(!type_eq(&unary, &nullary));
+        assert!(!type_eq_reference(&unary, &nullary));
+        assert!(type_eq(&unary, &unary.clone()));
+        assert_ne!(type_hash(&unary), type_hash(&nullary));
+        assert_ne!(type_hash(&unary), type_hash(&Type::Func(vec![Type::U8])));
+    }
+
+    #[test]
+    fn region_binders_keep_their_uniqueness() {
+        let r = |unique| Region { unique, id: RgnId::Var(Id(0, 0)) };
+        let poly = |binder, usage| Type::ForallRegion(r(binder), Box::new(Type::Func(vec![Type::Handle(r(usage))])), vec![]);
+        // a binder's uniqueness is what the function may do with the region, but a use's isn't part of the type
+        assert!(!type_eq(&poly(true, true), &poly(false, true)));
+        assert!(!type_eq_reference(&poly(true, true), &poly(false, true)));
+        assert!(type_eq(&poly(true, true), &poly(true, false)));
+        assert!(type_eq_reference(&poly(true, true), &poly(true, false)));
+    }
+}
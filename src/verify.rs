@@ -6,131 +6,349 @@
 
 use crate::header::RgnId::DataSection;
 use crate::header::*;
-use crate::pretty::Pretty;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+/// Settings for the verifier.
+pub struct VerifyOptions {
+    /// Reject programs that halt without freeing the unique regions they created.
+    pub strict_regions: bool,
+    /// How many ids the verifier may create for one function's type or body before giving up.
+    pub max_fresh_ids: u32,
+    /// Whether `check` looks for warnings.
+    pub collect_warnings: bool,
+    /// Clean up each function's verified ops with `peephole`.
+    pub peephole: bool,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            strict_regions: true,
+            max_fresh_ids: u32::MAX,
+            collect_warnings: true,
+            peephole: true,
+        }
+    }
+}
+
+/// The counter ids are made from, which can't go past a limit.
+/// The counter runs across the whole program, but each function's type and body
+/// only get `max_fresh_ids` ids of their own, starting wherever the counter is when they're checked.
+#[derive(Clone, Copy)]
+pub struct FreshIds {
+    next: u32,
+    end: u32,
+    max: u32,
+}
+
+impl FreshIds {
+    fn new(opts: &VerifyOptions) -> Self {
+        FreshIds { next: 0, end: 0, max: opts.max_fresh_ids }
+    }
+
+    /// The counter with a new allowance, for checking another function's type or body.
+    fn for_function(self) -> Self {
+        FreshIds { end: self.next.saturating_add(self.max), ..self }
+    }
+
+    /// A new id belonging to the function with the given label.
+    fn id(&mut self, label: Label) -> Result<Id, Error> {
+        if self.next >= self.end {
+            return Err(Error::FreshIdExhausted(label, self.max));
+        }
+        self.next += 1;
+        Ok(Id(label, self.next - 1))
+    }
+}
+
+/// `go` with the default options.
+pub fn go_default(
+    data_section: Vec<u8>,
+    types_instrs: &[ForwardDec],
+    unverified_stmts: impl IntoIterator<Item = Stmt1>,
+) -> Result<IRProgram, Error> {
+    go(data_section, types_instrs, unverified_stmts, &VerifyOptions::default())
+}
 
 pub fn go(
     data_section: Vec<u8>,
-    types_instrs: Vec<ForwardDec>,
-    unverified_stmts: Vec<Stmt1>,
+    types_instrs: &[ForwardDec],
+    unverified_stmts: impl IntoIterator<Item = Stmt1>,
+    opts: &VerifyOptions,
 ) -> Result<IRProgram, Error> {
-    let mut types = HashMap::new();
-    let mut fresh_id = 0;
-    let mut imports = HashMap::new();
-    let mut exports = HashMap::new();
-    for stmt in types_instrs {
-        match type_pass(&stmt, fresh_id) {
-            Ok((l, vis, t, new_fresh_id)) => {
-                types.insert(l, t);
-                match vis {
-                    Visibility::Import(a, b) => {
-                        imports.insert(l, (a, b));
-                    }
-                    Visibility::Export(a, b) => {
-                        exports.insert((a, b), l);
-                    }
-                    Visibility::Local => {}
+    let program = library(data_section, types_instrs, unverified_stmts, opts)?;
+    check_main(&program.funcs)?;
+    Ok(program)
+}
+
+/// Verify a program like `go`, but one that's only linked against another program's main,
+/// so its first function is an ordinary function rather than a main.
+pub fn library(
+    data_section: Vec<u8>,
+    types_instrs: &[ForwardDec],
+    unverified_stmts: impl IntoIterator<Item = Stmt1>,
+    opts: &VerifyOptions,
+) -> Result<IRProgram, Error> {
+    let decls = declarations(types_instrs, opts)?;
+    let verified_stmts: Vec<Stmt2> = definitions(data_section.len(), unverified_stmts.into_iter(), &decls, opts)
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(IRProgram {
+        data_section,
+        imports: decls.imports,
+        exports: decls.exports,
+        funcs: verified_stmts,
+    })
+}
+
+/// Everything `check` found in a program.
+pub struct CheckResult {
+    /// The verified program, if there were no errors.
+    pub program: Option<IRProgram>,
+    pub errors: Vec<Error>,
+    pub warnings: Vec<Warning>,
+}
+
+/// Verify a program like `go`, but check every declaration and definition
+/// instead of stopping at the first error, and look for things that are allowed but probably mistakes.
+/// A definition whose declaration has an error is skipped, since there's no type to check it against.
+pub fn check(
+    data_section: Vec<u8>,
+    types_instrs: &[ForwardDec],
+    unverified_stmts: impl IntoIterator<Item = Stmt1>,
+    opts: &VerifyOptions,
+) -> CheckResult {
+    let (decls, decl_errors) = all_declarations(types_instrs, opts);
+    let mut errors = vec![];
+    let mut failed_labels = HashSet::new();
+    for (label, e) in decl_errors {
+        failed_labels.insert(label);
+        errors.push(e);
+    }
+    let mut warnings = vec![];
+    let mut verified_stmts = vec![];
+    for stmt in unverified_stmts {
+        let Stmt1::Func(label, ..) = &stmt;
+        if failed_labels.contains(label) {
+            continue;
+        }
+        match verify_function(data_section.len(), &stmt, &decls, opts) {
+            Ok(stmt) => {
+                let Stmt2::Func(label, t, _) = &stmt;
+                if opts.collect_warnings {
+                    warnings.extend(unused_regions(*label, t));
                 }
-                fresh_id = new_fresh_id;
+                verified_stmts.push(stmt);
             }
-            Err(e) => return Err(e),
+            Err(e) => errors.push(e),
         }
     }
-    let verified_stmts: Vec<Stmt2> = unverified_stmts
-        .iter()
-        .map(|stmt| definition_pass(data_section.len(), stmt, &types, fresh_id))
-        .collect::<Result<Vec<_>, Error>>()?;
-    match verified_stmts.get(0) {
-        Some(Stmt2::Func(_, Type::Func(param_ts), _)) => {
-            if param_ts.len() != 0 {
-                return Err(Error::TypeErrorMainHasArgs);
-            }
+    // main is the first function, which is only known when every function verified
+    if errors.is_empty() {
+        if let Err(e) = check_main(&verified_stmts) {
+            errors.push(e);
         }
-        _ => (),
     }
-    Ok(IRProgram {
+    let program = errors.is_empty().then_some(IRProgram {
         data_section,
-        imports,
-        exports,
+        imports: decls.imports,
+        exports: decls.exports,
         funcs: verified_stmts,
-    })
+    });
+    CheckResult { program, errors, warnings }
+}
+
+/// Main is the first function, and nothing can pass it arguments or instantiate it.
+/// Its body can't fall off the end, since every body ends in a terminator,
+/// but it may end with a call rather than a halt, in continuation-passing style.
+fn check_main(verified_stmts: &[Stmt2]) -> Result<(), Error> {
+    match verified_stmts.first() {
+        Some(Stmt2::Func(_, t @ (Type::Forall(..) | Type::ForallRegion(..)), _)) => {
+            Err(Error::TypeErrorMainPolymorphic(t.clone()))
+        }
+        Some(Stmt2::Func(_, Type::Func(param_ts), _)) if !param_ts.is_empty() => Err(Error::TypeErrorMainHasArgs),
+        _ => Ok(()),
+    }
+}
+
+/// The regions a function is quantified over that none of its parameters mention.
+/// The function can't have a handle or pointer for such a region, so the quantifier is probably a mistake.
+fn unused_regions(label: Label, t: &Type) -> Vec<Warning> {
+    let mut rgns = vec![];
+    let mut t = t;
+    loop {
+        match t {
+            Type::Forall(_, _, body) => t = body,
+            Type::ForallRegion(r, body, _) => {
+                rgns.push(*r);
+                t = body;
+            }
+            _ => break,
+        }
+    }
+    let Type::Func(param_ts) = t else {
+        return vec![];
+    };
+    rgns.into_iter()
+        .filter(|r| !param_ts.iter().any(|t| mentions_rgn(t, r.id)))
+        .map(|r| Warning::UnusedRegion(label, r))
+        .collect()
+}
+
+/// Check if a type mentions the given region anywhere, including in captured regions.
+fn mentions_rgn(t: &Type, r: RgnId) -> bool {
+    match t {
+        Type::Handle(r2) => r2.id == r,
+        Type::Ptr(t, r2) | Type::Array(t, r2) => r2.id == r || mentions_rgn(t, r),
+        Type::Tuple(ts) => ts.iter().any(|(_, t)| mentions_rgn(t, r)),
+        Type::Sum(ts) | Type::Func(ts) => ts.iter().any(|t| mentions_rgn(t, r)),
+        Type::Exists(_, _, t) | Type::TaggedExists(_, _, t) | Type::Forall(_, _, t) | Type::Rec(_, _, t) => {
+            mentions_rgn(t, r)
+        }
+        Type::ForallRegion(_, t, captured_rgns) => {
+            captured_rgns.iter().any(|r2| r2.id == r) || mentions_rgn(t, r)
+        }
+        _ => false,
+    }
+}
+
+/// Everything the definition pass needs to know about the forward declarations.
+pub struct Declarations {
+    pub types: HashMap<Label, Type>,
+    pub imports: HashMap<Label, (u64, u64)>,
+    pub exports: HashMap<(u64, u64), Label>,
+    pub fresh_ids: FreshIds,
+}
+
+/// Run the type pass over every forward declaration.
+/// This has to finish before any definition can be checked,
+/// since any function can refer to any other.
+pub fn declarations(types_instrs: &[ForwardDec], opts: &VerifyOptions) -> Result<Declarations, Error> {
+    let (decls, errors) = all_declarations(types_instrs, opts);
+    match errors.into_iter().next() {
+        Some((_label, e)) => Err(e),
+        None => Ok(decls),
+    }
+}
+
+/// Run the type pass over every forward declaration, even after one fails,
+/// collecting the errors along with the label of the declaration each came from.
+fn all_declarations(types_instrs: &[ForwardDec], opts: &VerifyOptions) -> (Declarations, Vec<(Label, Error)>) {
+    let mut errors = vec![];
+    let mut types = HashMap::new();
+    let mut fresh_ids = FreshIds::new(opts);
+    let mut imports = HashMap::new();
+    let mut exports = HashMap::new();
+    for stmt in types_instrs {
+        let (l, vis, t, new_fresh_ids) = match type_pass(stmt, fresh_ids.for_function()) {
+            Ok(res) => res,
+            Err(e) => {
+                let ForwardDec::Func(label, ..) = stmt;
+                errors.push((*label, e));
+                continue;
+            }
+        };
+        types.insert(l, t);
+        match vis {
+            Visibility::Import(a, b) => {
+                imports.insert(l, (a, b));
+            }
+            Visibility::Export(a, b) => {
+                exports.insert((a, b), l);
+            }
+            Visibility::Local => {}
+        }
+        fresh_ids = new_fresh_ids;
+    }
+    (
+        Declarations {
+            types,
+            imports,
+            exports,
+            fresh_ids,
+        },
+        errors,
+    )
+}
+
+/// Verify function definitions lazily, one at a time as they arrive,
+/// so a streaming parser never has to buffer the whole program.
+pub fn definitions<'a>(
+    data_section_len: usize,
+    unverified_stmts: impl Iterator<Item = Stmt1> + 'a,
+    decls: &'a Declarations,
+    opts: &'a VerifyOptions,
+) -> impl Iterator<Item = Result<Stmt2, Error>> + 'a {
+    unverified_stmts.map(move |stmt| verify_function(data_section_len, &stmt, decls, opts))
+}
+
+/// Verify one function definition against the program's declarations.
+/// Definitions only depend on declarations, never on each other, so after an edit
+/// only the edited function needs verifying again, as long as its declaration didn't change.
+/// `decls` has to declare the function itself and every function it refers to.
+/// It's the types map and the fresh-id source together, rather than the two separately,
+/// because the ids have to carry on from where the type pass left off and stay under `opts.max_fresh_ids`,
+/// which a bare starting id can't promise. `data_section_len` bounds the data section ops.
+/// This doesn't check anything that's only about main; see `check_main`.
+pub fn verify_function(
+    data_section_len: usize,
+    stmt: &Stmt1,
+    decls: &Declarations,
+    opts: &VerifyOptions,
+) -> Result<Stmt2, Error> {
+    definition_pass(data_section_len, stmt, &decls.types, decls.fresh_ids.for_function(), opts)
 }
 
+/// Compute the type of a forward declaration.
+/// Ids are pairs of the declaring label and a counter, and the counter is threaded
+/// through every forward declaration and then handed to the definition pass.
+/// That keeps the ids a function creates in its body disjoint from every id in its own type,
+/// and the label component keeps them disjoint from ids created by other functions.
 pub fn type_pass(
     stmt: &ForwardDec,
-    mut fresh_id: u32,
-) -> Result<(Label, Visibility, Type, u32), Error> {
-    let ForwardDec::Func(label, visibility, ops) = stmt;
+    mut fresh_ids: FreshIds,
+) -> Result<(Label, Visibility, Type, FreshIds), Error> {
+    let ForwardDec::Func(label, pos, visibility, ops) = stmt;
     let mut next_region_is_unique = false;
     let mut compile_time_stack: Vec<CTStackVal> = vec![];
     let mut quantification_stack: Vec<Quantification> = vec![];
-    let mut pos = *label;
-    for op in ops {
-        match op {
-            Op1::Unique => next_region_is_unique = true,
-            Op1::Handle => handle_handle(pos, op, &mut compile_time_stack)?,
-            Op1::I32 => compile_time_stack.push(CTStackVal::Type(Type::I32)),
-            Op1::Tuple(n) => handle_tuple(n, pos, op, &mut compile_time_stack)?,
-            Op1::Some => handle_some(
-                pos,
-                op,
-                &mut compile_time_stack,
-                &mut fresh_id,
-                label,
-                &mut quantification_stack,
-            )?,
-            Op1::All => handle_all(
-                pos,
-                op,
-                &mut compile_time_stack,
-                &mut fresh_id,
-                label,
-                &mut quantification_stack,
-            )?,
-            Op1::Rgn => handle_rgn(
-                &mut next_region_is_unique,
-                label,
-                &mut fresh_id,
-                &mut compile_time_stack,
-                &mut quantification_stack,
-            )?,
-            Op1::End => handle_end(pos, op, &mut compile_time_stack, &mut quantification_stack)?,
-            Op1::Func(n) => handle_func(n, pos, op, &mut compile_time_stack)?,
-            Op1::CTGet(i) => handle_ctget(pos, i, &mut compile_time_stack)?,
-            Op1::Size(s) => compile_time_stack.push(CTStackVal::Size((*s).try_into().unwrap())),
-            Op1::Ptr => handle_ptr(pos, op, &mut compile_time_stack)?,
-            Op1::Arr => handle_arr(pos, op, &mut compile_time_stack)?,
-            Op1::DataSec => compile_time_stack.push(CTStackVal::Region(Region {
-                unique: false,
-                id: DataSection,
-            })),
-            Op1::U8 => compile_time_stack.push(CTStackVal::Type(Type::U8)),
-            op => return Err(Error::ForwardDeclRuntimeOp(*op)),
+    for (pos, op) in (*pos..).zip(ops.iter()) {
+        let mut ctx = CTContext {
+            label: *label,
+            compile_time_stack: &mut compile_time_stack,
+            quantification_stack: &mut quantification_stack,
+            fresh_ids: &mut fresh_ids,
+            next_region_is_unique: &mut next_region_is_unique,
+        };
+        if !handle_ct_op(pos, op, &mut ctx)? {
+            return Err(Error::ForwardDeclRuntimeOp(*label, *op));
         }
-        pos += 1;
     }
     match &compile_time_stack[..] {
-        [CTStackVal::Type(t)] => Ok((*label, *visibility, t.clone(), pos)),
-        _ => return Err(Error::ForwardDeclBadStack(compile_time_stack)),
+        [CTStackVal::Type(t)] => Ok((*label, *visibility, t.clone(), fresh_ids)),
+        _ => Err(Error::ForwardDeclBadStack(*label, compile_time_stack)),
     }
 }
 
-pub fn definition_pass(
+fn definition_pass(
     data_section_len: usize,
     stmt: &Stmt1,
     types: &HashMap<Label, Type>,
-    mut fresh_id: u32,
+    mut fresh_ids: FreshIds,
+    opts: &VerifyOptions,
 ) -> Result<Stmt2, Error> {
     let Stmt1::Func(label, pos, ops) = stmt;
     let mut pos = *pos;
     let mut ops_iter = ops.iter();
 
     let Some(my_type) = types.get(label).cloned() else {
-        panic!("Type not found for label {}", label);
+        return Err(Error::UndeclaredFunction(*label));
     };
     // The stacks used for this pass algorithm.
-    let (mut compile_time_stack, mut stack_type) = setup_verifier(&my_type)?;
+    let (mut compile_time_stack, mut stack_type) = setup_verifier(*label, &my_type)?;
     compile_time_stack.reverse();
+    // The function's own binders, which the compile-time stack has to be back to at the end.
+    let entry_compile_time_stack = compile_time_stack.clone();
     // println!("Stack type:");
     // for t in &stack_type {
     //     println!("- {}", t.pretty());
@@ -147,92 +365,150 @@ pub fn definition_pass(
     }];
     for ctval in &compile_time_stack {
         if let CTStackVal::Region(r) = ctval {
-            rgn_vars.push(r.clone());
+            rgn_vars.push(*r);
         }
     }
 
+    // The regions this function creates with `NewRgn`, which it has to free before halting.
+    let mut new_rgns: Vec<RgnId> = vec![];
+
+    // The witnesses of the existentials this function unpacks.
+    let mut witnesses: Vec<Id> = vec![];
+
     let mut next_region_is_unique = false;
 
+    // The loops the current op is nested in, innermost last.
+    let mut loop_stack: Vec<LoopFrame> = vec![];
+
     loop {
         // dbg!(&compile_time_stack.iter().map(|v| v.pretty()).collect::<Vec<_>>());
         // dbg!(&stack_type.iter().map(|v| v.pretty()).collect::<Vec<_>>());
         match ops_iter.next() {
             None => break,
+            // a terminator hands control away for good, so nothing after it could run
+            Some(op) if verified_ops.last().is_some_and(is_terminator) => return Err(Error::UnreachableCode(pos, *op)),
             Some(op) => match op {
-                Op1::Unique => next_region_is_unique = true,
-                Op1::Handle => handle_handle(pos, op, &mut compile_time_stack)?,
-                Op1::I32 => compile_time_stack.push(CTStackVal::Type(Type::I32)),
-                Op1::Tuple(n) => handle_tuple(n, pos, op, &mut compile_time_stack)?,
-                Op1::Some => handle_some(
+                op if handle_ct_op(
                     pos,
                     op,
-                    &mut compile_time_stack,
-                    &mut fresh_id,
-                    label,
-                    &mut quantification_stack,
-                )?,
-                Op1::All => handle_all(
-                    pos,
-                    op,
-                    &mut compile_time_stack,
-                    &mut fresh_id,
-                    label,
-                    &mut quantification_stack,
-                )?,
-                Op1::Rgn => handle_rgn(
-                    &mut next_region_is_unique,
-                    label,
-                    &mut fresh_id,
-                    &mut compile_time_stack,
-                    &mut quantification_stack,
-                )?,
-                Op1::End => {
-                    handle_end(pos, op, &mut compile_time_stack, &mut quantification_stack)?
-                }
-                Op1::App => match compile_time_stack.pop() {
-                    Some(CTStackVal::Type(t_arg)) => {
-                        let (id, s, t) = match stack_type.pop() {
-                            Some(Type::Forall(id, s, t)) => (id, s, t),
-                            Some(t) => return Err(Error::TypeErrorForallExpected(pos, *op, t)),
-                            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
-                        };
-                        if s != t_arg.size() {
-                            return Err(Error::SizeError(pos, *op, s, t_arg.size()));
+                    &mut CTContext {
+                        label: *label,
+                        compile_time_stack: &mut compile_time_stack,
+                        quantification_stack: &mut quantification_stack,
+                        fresh_ids: &mut fresh_ids,
+                        next_region_is_unique: &mut next_region_is_unique,
+                    },
+                )? => {}
+                Op1::App => {
+                    let Some(arg) = compile_time_stack.pop() else {
+                        return Err(Error::TypeErrorEmptyCTStack(pos, *op));
+                    };
+                    let Some(t) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    stack_type.push(instantiate(pos, *op, &t, arg, &rgn_vars)?);
+                }
+                Op1::Lced | Op1::Import(_, _) | Op1::Export(_, _) => {
+                    return Err(Error::DeclarationOpInBody(pos, *op))
+                }
+                Op1::Inj(i) => {
+                    let variant_ts = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(Type::Sum(ts))) => ts,
+                        Some(CTStackVal::Type(t)) => return Err(Error::TypeErrorSumExpected(pos, *op, t)),
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    let Some(variant_t) = variant_ts.get(usize::from(*i)) else {
+                        return Err(Error::TypeErrorInjOutOfRange(pos, *i, variant_ts.len()));
+                    };
+                    match stack_type.pop() {
+                        Some(t) if type_eq(&t, variant_t) => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, variant_t.clone(), t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let sum = Type::Sum(variant_ts.clone());
+                    check_stack_size(pos, op, &sum)?;
+                    verified_ops.push(Op2::Inj(variant_t.size(), sum.size() - 8 - variant_t.size(), *i));
+                    stack_type.push(sum);
+                }
+                Op1::Case(n) => {
+                    let mut branch_ts = vec![];
+                    for _ in 0..*n {
+                        match stack_type.pop() {
+                            Some(t) => branch_ts.push(t),
+                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
-                        let new_t =
-                            substitute_t(&*t, &HashMap::from([(id, t_arg)]), &HashMap::new());
-                        stack_type.push(new_t);
                     }
-                    Some(CTStackVal::Region(r_arg)) => {
-                        let (r, t, captured_rgns) = match stack_type.pop() {
-                            Some(Type::ForallRegion(r, t, captured_rgns)) => (r, t, captured_rgns),
-                            Some(t) => {
-                                return Err(Error::TypeErrorForallRegionExpected(pos, *op, t))
+                    let variant_ts = match stack_type.pop() {
+                        Some(Type::Sum(ts)) if ts.len() == usize::from(*n) => ts,
+                        Some(Type::Sum(ts)) => return Err(Error::TypeErrorCaseArity(pos, *n, ts.len())),
+                        Some(t) => return Err(Error::TypeErrorSumExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    // every branch is checked against its own copy of the stacks, with its variant's value on top;
+                    // whichever branch runs, the code after has to see the same compile-time stack,
+                    // so every branch has to take the same compile-time arguments
+                    let mut compile_time_stack_after: Option<Vec<CTStackVal>> = None;
+                    for (i, (branch_t, variant_t)) in branch_ts.iter().zip(variant_ts).enumerate() {
+                        let mut branch_stack_type = stack_type.clone();
+                        let mut branch_compile_time_stack = compile_time_stack.clone();
+                        branch_stack_type.push(variant_t);
+                        handle_call(
+                            pos,
+                            branch_t,
+                            &mut branch_stack_type,
+                            &mut branch_compile_time_stack,
+                            &rgn_vars,
+                            *op,
+                        )?;
+                        match &compile_time_stack_after {
+                            Some(ct) if !ct_stack_eq(ct, &branch_compile_time_stack) => {
+                                return Err(Error::TypeErrorCaseBranchesDiffer(pos, i));
                             }
-                            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
-                        };
-                        if r.unique && captured_rgns.iter().any(|r2| r_arg.id == r2.id) {
-                            return Err(Error::RegionAccessError(pos, *op, r_arg));
+                            Some(_) => {}
+                            None => compile_time_stack_after = Some(branch_compile_time_stack),
                         }
-                        let new_t =
-                            substitute_t(&*t, &HashMap::new(), &HashMap::from([(r.id, r_arg)]));
-                        stack_type.push(new_t);
                     }
-                    Some(ctval) => return Err(Error::KindErrorBadApp(pos, *op, ctval)),
-                    None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    if let Some(ct) = compile_time_stack_after {
+                        compile_time_stack = ct;
+                    }
+                    verified_ops.push(Op2::Case(*n));
+                }
+                Op1::Fold => {
+                    let (id, s, body) = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(Type::Rec(id, s, body))) => (id, s, body),
+                        Some(CTStackVal::Type(t)) => return Err(Error::TypeErrorRecExpected(pos, *op, t)),
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    let rec = Type::Rec(id, s, body);
+                    let unfolded = unfold(&rec);
+                    match stack_type.pop() {
+                        Some(t) if type_eq(&t, &unfolded) => stack_type.push(rec),
+                        Some(t) => return Err(Error::TypeError(pos, *op, unfolded, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                }
+                Op1::Unfold => match stack_type.pop() {
+                    Some(rec @ Type::Rec(..)) => stack_type.push(unfold(&rec)),
+                    Some(t) => return Err(Error::TypeErrorRecExpected(pos, *op, t)),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::Func(n) => handle_func(n, pos, op, &mut compile_time_stack)?,
-                Op1::CTGet(i) => handle_ctget(pos, i, &mut compile_time_stack)?,
-                Op1::Lced => panic!("Lced should not appear in this context"),
-                Op1::Import(_, _) => panic!("Import should not appear in this context"),
-                Op1::Export(_, _) => panic!("Export should not appear in this context"),
                 Op1::Unpack => {
-                    let t = match stack_type.pop() {
-                        Some(Type::Exists(_id, _s, t)) => t,
+                    let (id, s, t) = match stack_type.pop() {
+                        Some(Type::Exists(id, s, t)) => (id, s, t),
                         Some(t) => return Err(Error::TypeErrorExistentialExpected(pos, *op, t)),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    stack_type.push(*t);
+                    // Every unpack gets its own witness, since two packages of the same
+                    // existential type can hide different types. Witnesses are never in
+                    // scope of each other, so nested packages can be opened in any order.
+                    // The witness goes on the compile-time stack, so the body can be repacked.
+                    let witness_id = fresh_ids.id(*label)?;
+                    witnesses.push(witness_id);
+                    let witness = Type::Var(witness_id, s);
+                    stack_type.push(substitute_t(&t, &HashMap::from([(id, witness.clone())]), &HashMap::new()));
+                    compile_time_stack.push(CTStackVal::Type(witness));
                 }
                 Op1::Get(i) => {
                     let stack_len = stack_type.len();
@@ -252,16 +528,73 @@ pub fn definition_pass(
                     stack_type.push(t);
                     verified_ops.push(Op2::Get(offset, size));
                 }
+                Op1::GetLocal(i) => {
+                    let stack_len = stack_type.len();
+                    let i2 = usize::from(*i);
+                    if stack_len <= i2 {
+                        return Err(Error::TypeErrorGetOutOfRange(pos, *i, stack_len));
+                    }
+                    // the byte offset from the top is the total size of the values above slot `i`
+                    let offset: usize = stack_type[i2 + 1..].iter().map(Type::size).sum();
+                    let t = stack_type[i2].clone();
+                    let size = t.size();
+                    stack_type.push(t);
+                    verified_ops.push(Op2::Get(offset, size));
+                }
+                Op1::Rotate(i) => {
+                    let stack_len = stack_type.len();
+                    let i2 = usize::from(*i);
+                    if stack_len <= i2 {
+                        return Err(Error::TypeErrorRotateOutOfRange(pos, *i, stack_len));
+                    }
+                    // the byte offset is the total size of the values the rotated value is moved past
+                    let offset: usize = stack_type[stack_len - i2..].iter().map(Type::size).sum();
+                    let t = stack_type.remove(stack_len - 1 - i2);
+                    let size = t.size();
+                    stack_type.push(t);
+                    verified_ops.push(Op2::Rotate(offset, size));
+                }
+                Op1::Dup => {
+                    let Some(t) = stack_type.last().cloned() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    verified_ops.push(Op2::Dup(t.size()));
+                    stack_type.push(t);
+                }
+                Op1::Swap => {
+                    let (Some(t1), Some(t2)) = (stack_type.pop(), stack_type.pop()) else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    verified_ops.push(Op2::Swap(t1.size(), t2.size()));
+                    stack_type.push(t1);
+                    stack_type.push(t2);
+                }
+                Op1::Pop => {
+                    let Some(t) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    if let Some(r) = unique_handle_in(&t, &rgn_vars) {
+                        return Err(Error::UniqueHandleDropped(pos, *op, r));
+                    }
+                    verified_ops.push(Op2::Pop(t.size()));
+                }
+                Op1::Clean(n) => {
+                    let n = usize::from(*n);
+                    if stack_type.len() <= n {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    }
+                    let t = stack_type.pop().unwrap();
+                    let removed = stack_type.split_off(stack_type.len() - n);
+                    if let Some(r) = removed.iter().find_map(|t| unique_handle_in(t, &rgn_vars)) {
+                        return Err(Error::UniqueHandleDropped(pos, *op, r));
+                    }
+                    verified_ops.push(Op2::Clean(removed.iter().map(Type::size).sum(), t.size()));
+                    stack_type.push(t);
+                }
                 Op1::Init(i) => {
                     let mb_val = stack_type.pop();
                     let mb_tpl = stack_type.pop();
-                    let f = |component_types: Vec<(bool, Type)>,
-                             g: &dyn Fn(
-                        &Type,
-                        Vec<(bool, Type)>,
-                        &mut Vec<Type>,
-                        &mut Vec<Op2>,
-                    ) -> ()| {
+                    let f = |component_types: Vec<(bool, Type)>, g: InitFn| {
                         let formal = match component_types.get(usize::from(*i)) {
                             Some((false, formal)) => formal,
                             Some((true, _t)) => {
@@ -296,12 +629,8 @@ pub fn definition_pass(
                               mut component_types: Vec<(bool, Type)>,
                               stack_type: &mut Vec<Type>,
                               verified_ops: &mut Vec<Op2>| {
-                                let mut offset = 0;
+                                let offset = component_offset(&component_types, *i);
                                 let tpl_size = component_types.iter().map(|(_, t)| t.size()).sum();
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
                                 component_types[*i as usize] = (true, actual.clone());
                                 stack_type.push(Type::Tuple(component_types));
                                 verified_ops.push(Op2::Init(offset, actual.size(), tpl_size));
@@ -311,7 +640,9 @@ pub fn definition_pass(
                             let Type::Tuple(component_types) = *boxed_t else {
                                 return Err(Error::TypeErrorTupleExpected(pos, *op, *boxed_t));
                             };
-                            if rgn_vars.iter().all(|r2| r.id != r2.id) {
+                            // checked on every init, since the region may have been freed
+                            // since the last init through this pointer
+                            if !rgn_accessible(&r, &rgn_vars) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
                             f(
@@ -320,11 +651,7 @@ pub fn definition_pass(
                                   mut component_types: Vec<(bool, Type)>,
                                   stack_type: &mut Vec<Type>,
                                   verified_ops: &mut Vec<Op2>| {
-                                    let mut offset = 0;
-                                    for i2 in 0..*i {
-                                        let (_, t) = &component_types[i2 as usize];
-                                        offset += t.size();
-                                    }
+                                    let offset = component_offset(&component_types, *i);
                                     component_types[*i as usize] = (true, actual.clone());
                                     stack_type
                                         .push(Type::Ptr(Box::new(Type::Tuple(component_types)), r));
@@ -339,6 +666,18 @@ pub fn definition_pass(
                 Op1::Malloc => {
                     let mb_type = compile_time_stack.pop();
                     match mb_type {
+                        // the heap's handle is global, so it's supplied here instead of taken from the stack
+                        Some(CTStackVal::Type(Type::Ptr(t, r))) if r.id == RgnId::Heap => {
+                            let t = *t;
+                            let size = t.size();
+                            let Type::Tuple(component_types) = t else {
+                                return Err(Error::TypeErrorMallocNonTuple(pos, *op, t));
+                            };
+                            let ts = component_types.into_iter().map(|(_, t)| (false, t)).collect();
+                            stack_type.push(Type::Ptr(Box::new(Type::Tuple(ts)), r));
+                            verified_ops.push(Op2::HeapHandle);
+                            verified_ops.push(Op2::Malloc(size));
+                        }
                         Some(CTStackVal::Type(Type::Ptr(t, r))) => {
                             let r2 = match stack_type.pop() {
                                 Some(Type::Handle(r2)) => r2,
@@ -351,7 +690,7 @@ pub fn definition_pass(
                             if r.id != r2.id {
                                 return Err(Error::RegionError(pos, *op, r, r2));
                             }
-                            if rgn_vars.iter().all(|r2: &Region| r.id != r2.id) {
+                            if !rgn_accessible(&r, &rgn_vars) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
                             let t = *t;
@@ -373,10 +712,8 @@ pub fn definition_pass(
                                 ts.push((false, t))
                             }
                             let t = Type::Tuple(ts);
+                            check_stack_size(pos, op, &t)?;
                             let size = t.size();
-                            if size > 4096 {
-                                return Err(Error::TooBigForStack(pos, *op, t));
-                            }
                             stack_type.push(t);
                             verified_ops.push(Op2::Alloca(size));
                         }
@@ -396,10 +733,15 @@ pub fn definition_pass(
                                 }
                                 None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                             }
-                            if rgn_vars.iter().all(|r2: &Region| r.id != r2.id) {
+                            if !rgn_accessible(&r, &rgn_vars) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
+                            // the runtime only knows the length in bytes, so it couldn't bounds-check
+                            // an array of zero-sized elements
                             let size = (*t).size();
+                            if size == 0 {
+                                return Err(Error::ZeroSizedArrayElement(pos, *op, *t));
+                            }
                             stack_type.push(Type::Array(t, r));
                             verified_ops.push(Op2::NewArr(size));
                         }
@@ -408,21 +750,18 @@ pub fn definition_pass(
                     };
                 }
                 Op1::Proj(i) => {
-                    let mut f = |component_types: Vec<(bool, Type)>,
-                                 mut stack_type: &mut Vec<Type>,
-                                 g: &dyn Fn(
-                        &Type,
-                        usize,
-                        &mut Vec<Type>,
-                        &mut Vec<Op2>,
-                        Vec<(bool, Type)>,
-                    ) -> ()| {
+                    let mut f = |component_types: Vec<(bool, Type)>, stack_type: &mut Vec<Type>, g: ProjFn| {
                         let s: usize = component_types.iter().map(|(_, t)| t.size()).sum();
                         let mb_t = component_types.get(usize::from(*i)).cloned();
                         let t = match mb_t {
                             Some((true, t)) => t,
                             Some((false, _)) => {
-                                return Err(Error::TypeErrorUninitializedRead(pos, *op, *i))
+                                return Err(Error::TypeErrorUninitializedRead(
+                                    pos,
+                                    *op,
+                                    *i,
+                                    component_types,
+                                ))
                             }
                             None => {
                                 return Err(Error::TypeErrorProjOutOfRange(
@@ -432,7 +771,7 @@ pub fn definition_pass(
                                 ))
                             }
                         };
-                        g(&t, s, &mut stack_type, &mut verified_ops, component_types);
+                        g(&t, s, stack_type, &mut verified_ops, component_types);
                         Ok(())
                     };
                     let Some(tpl) = stack_type.pop() else {
@@ -441,11 +780,7 @@ pub fn definition_pass(
                     match tpl {
                         Type::Tuple(component_types) => {
                             f(component_types, &mut stack_type, &|t: &Type, s: usize, stack_type: &mut Vec<Type>, verified_ops: &mut Vec<Op2>, component_types: Vec<(bool, Type)>| {
-                                let mut offset = 0;
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
+                                let offset = component_offset(&component_types, *i);
                                 stack_type.push(t.clone());
                                 verified_ops.push(Op2::Proj(offset, t.size(), s));
                             })?;
@@ -453,21 +788,19 @@ pub fn definition_pass(
                         Type::Ptr(boxed_t, r) => {
                             if r.id == RgnId::DataSection {
                                 return Err(Error::ReadOnlyRegionError(pos, *op, r.id));
-                            } else if rgn_vars.iter().all(|r2| r.id != r2.id) {
+                            } else if !rgn_accessible(&r, &rgn_vars) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
                             let Type::Tuple(component_types) = *boxed_t else {
                                 return Err(Error::TypeErrorTupleExpected(pos, *op, *boxed_t));
                             };
                             f(component_types, &mut stack_type, &|t: &Type, _s: usize, stack_type: &mut Vec<Type>, verified_ops: &mut Vec<Op2>, component_types: Vec<(bool, Type)>| {
-                                let mut offset = 0;
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
+                                let offset = component_offset(&component_types, *i);
                                 stack_type.push(t.clone());
                                 verified_ops.push(Op2::ProjIP(offset, t.size()));
                             })?;
+                            // a tuple in a region can be bigger than the stack allows, and so can its components
+                            check_stack_size(pos, op, stack_type.last().unwrap())?;
                         }
                         t => return Err(Error::TypeErrorTupleExpected(pos, *op, t)),
                     }
@@ -476,7 +809,14 @@ pub fn definition_pass(
                     let Some(t) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
-                    handle_call(pos, &t, &mut stack_type, &mut compile_time_stack, Op1::Call)?;
+                    handle_call(
+                        pos,
+                        &t,
+                        &mut stack_type,
+                        &mut compile_time_stack,
+                        &rgn_vars,
+                        Op1::Call,
+                    )?;
                     verified_ops.push(Op2::Call)
                 }
                 // Op1::Print => {
@@ -497,6 +837,14 @@ pub fn definition_pass(
                     stack_type.push(Type::I32);
                     verified_ops.push(Op2::Lit(*lit))
                 }
+                Op1::LitI64(lit) => {
+                    stack_type.push(Type::I64);
+                    verified_ops.push(Op2::Lit64(*lit as u64))
+                }
+                Op1::LitF64(bits) => {
+                    stack_type.push(Type::F64);
+                    verified_ops.push(Op2::Lit64(*bits))
+                }
                 Op1::GlobalFunc(label) => {
                     let t = types
                         .get(label)
@@ -504,12 +852,113 @@ pub fn definition_pass(
                     stack_type.push(t.clone());
                     verified_ops.push(Op2::GlobalFunc(*label))
                 }
-                Op1::Halt => match stack_type.pop() {
-                    Some(Type::U8) => verified_ops.push(Op2::Halt),
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
-                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                },
-                Op1::Pack => {
+                Op1::Loop => loop_stack.push(LoopFrame {
+                    header: verified_ops.len(),
+                    entry: LoopState {
+                        stack_type: stack_type.clone(),
+                        compile_time_stack: compile_time_stack.clone(),
+                        rgn_vars: rgn_vars.clone(),
+                    },
+                    breaks: vec![],
+                    exit: None,
+                }),
+                Op1::Break => {
+                    match stack_type.pop() {
+                        Some(Type::I32) => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let Some(frame) = loop_stack.last_mut() else {
+                        return Err(Error::LoopControlOutsideLoop(pos, *op));
+                    };
+                    // every break has to agree on the state after the loop
+                    match &frame.exit {
+                        Some(exit) => {
+                            check_loop_state(pos, *op, exit, &stack_type, &compile_time_stack, &rgn_vars)?
+                        }
+                        None => {
+                            frame.exit = Some(LoopState {
+                                stack_type: stack_type.clone(),
+                                compile_time_stack: compile_time_stack.clone(),
+                                rgn_vars: rgn_vars.clone(),
+                            })
+                        }
+                    }
+                    // the target is patched in at `loop_end`
+                    frame.breaks.push(verified_ops.len());
+                    verified_ops.push(Op2::JumpNZ(0));
+                }
+                Op1::Continue => {
+                    match stack_type.pop() {
+                        Some(Type::I32) => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let Some(frame) = loop_stack.last() else {
+                        return Err(Error::LoopControlOutsideLoop(pos, *op));
+                    };
+                    check_loop_state(pos, *op, &frame.entry, &stack_type, &compile_time_stack, &rgn_vars)?;
+                    verified_ops.push(Op2::JumpNZ(frame.header as u32));
+                }
+                Op1::LoopEnd => {
+                    let Some(frame) = loop_stack.pop() else {
+                        return Err(Error::LoopControlOutsideLoop(pos, *op));
+                    };
+                    // reaching the end of the body goes around again
+                    check_loop_state(pos, *op, &frame.entry, &stack_type, &compile_time_stack, &rgn_vars)?;
+                    verified_ops.push(Op2::Jump(frame.header as u32));
+                    let Some(exit) = frame.exit else {
+                        return Err(Error::LoopWithoutBreak(pos));
+                    };
+                    let after = verified_ops.len() as u32;
+                    for i in frame.breaks {
+                        verified_ops[i] = Op2::JumpNZ(after);
+                    }
+                    stack_type = exit.stack_type;
+                    compile_time_stack = exit.compile_time_stack;
+                    rgn_vars = exit.rgn_vars;
+                }
+                // a terminator like `halt`, but it makes no demands on the stack
+                Op1::Unreachable => verified_ops.push(Op2::Unreachable),
+                Op1::Select => {
+                    match stack_type.pop() {
+                        Some(Type::I32) => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    let Some(t1) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    match stack_type.pop() {
+                        Some(t2) if type_eq(&t1, &t2) => {} // success
+                        Some(t2) => return Err(Error::TypeError(pos, *op, t1, t2)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    verified_ops.push(Op2::Select(t1.size()));
+                    stack_type.push(t1);
+                }
+                Op1::Halt => {
+                    let Some(status) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    // the status becomes the process's exit code, so the verifier picks a byte by its type:
+                    // an integer exits with its low byte, and a value with nothing in it, like a unit, exits with 0
+                    let to_exit_code: &[Op2] = match status {
+                        Type::U8 => &[],
+                        Type::I32 => &[Op2::I32ToU8],
+                        Type::I64 => &[Op2::I64ToI32, Op2::I32ToU8],
+                        _ if status.size() == 0 => &[Op2::U8Lit(0)],
+                        _ => return Err(Error::TypeErrorHaltStatus(pos, *op, status)),
+                    };
+                    if let Some(r) = rgn_vars.iter().find(|r| r.unique && new_rgns.contains(&r.id)) {
+                        if opts.strict_regions {
+                            return Err(Error::RegionLeak(pos, *r));
+                        }
+                    }
+                    verified_ops.extend_from_slice(to_exit_code);
+                    verified_ops.push(Op2::Halt)
+                }
+                Op1::Pack | Op1::PackTagged => {
                     let Some(type_of_hidden) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
@@ -523,7 +972,15 @@ pub fn definition_pass(
                             id,
                             size_of_hidden,
                             existential_type,
-                        ))) => (id, size_of_hidden, existential_type),
+                        ))) if *op == Op1::Pack => (id, size_of_hidden, existential_type),
+                        Some(CTStackVal::Type(Type::TaggedExists(
+                            id,
+                            size_of_hidden,
+                            existential_type,
+                        ))) if *op == Op1::PackTagged => (id, size_of_hidden, existential_type),
+                        Some(CTStackVal::Type(t)) if *op == Op1::PackTagged => {
+                            return Err(Error::TypeErrorTaggedExistentialExpected(pos, *op, t))
+                        }
                         Some(CTStackVal::Type(t)) => {
                             return Err(Error::TypeErrorExistentialExpected(pos, *op, t))
                         }
@@ -539,61 +996,220 @@ pub fn definition_pass(
                             type_of_hidden.size(),
                         ));
                     }
+                    let mb_tag = type_tag(&hidden_type);
                     let unpacked_type = substitute_t(
                         &existential_type,
-                        &HashMap::from([(id, hidden_type)]),
+                        &HashMap::from([(id, hidden_type.clone())]),
                         &HashMap::new(),
                     );
                     if !type_eq(&type_of_hidden, &unpacked_type) {
                         return Err(Error::TypeError(pos, *op, unpacked_type, type_of_hidden));
                     }
-                    stack_type.push(Type::Exists(id, size_of_hidden, existential_type));
-                }
-                Op1::Size(s) => compile_time_stack.push(CTStackVal::Size((*s).try_into().unwrap())),
-                Op1::NewRgn(size) => {
-                    let id = Id(*label, fresh_id);
-                    fresh_id += 1;
-                    let r = Region {
-                        unique: true,
-                        id: RgnId::Var(id),
-                    };
-                    rgn_vars.push(r.clone());
-                    stack_type.push(Type::Handle(r.clone()));
-                    compile_time_stack.push(CTStackVal::Region(r));
-                    verified_ops.push(Op2::NewRgn((*size).try_into().unwrap()));
+                    if *op == Op1::PackTagged {
+                        let Some(tag) = mb_tag else {
+                            return Err(Error::TypeErrorUntaggable(pos, *op, hidden_type));
+                        };
+                        let tagged = Type::TaggedExists(id, size_of_hidden, existential_type);
+                        // the tag makes the value 8 bytes bigger
+                        check_stack_size(pos, op, &tagged)?;
+                        stack_type.push(tagged);
+                        verified_ops.push(Op2::PackTagged(type_of_hidden.size(), tag));
+                    } else {
+                        stack_type.push(Type::Exists(id, size_of_hidden, existential_type));
+                    }
                 }
-                Op1::FreeRgn => {
-                    let r = match stack_type.pop() {
-                        Some(Type::Handle(r)) => r,
-                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                Op1::Assert => {
+                    let (id, s, body) = match stack_type.pop() {
+                        Some(Type::TaggedExists(id, s, body)) => (id, s, body),
+                        Some(t) => {
+                            return Err(Error::TypeErrorTaggedExistentialExpected(pos, *op, t))
+                        }
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    match rgn_vars.iter().find(|r2| r.id == r2.id) {
-                        Some(r2) if r2.unique => {} // success
+                    let target = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(t)) => t,
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    if target.size() != s {
+                        return Err(Error::SizeError(pos, *op, s, target.size()));
+                    }
+                    let Some(tag) = type_tag(&target) else {
+                        return Err(Error::TypeErrorUntaggable(pos, *op, target));
+                    };
+                    // execution only continues if the tags match, so the hidden type is revealed
+                    stack_type.push(substitute_t(&body, &HashMap::from([(id, target)]), &HashMap::new()));
+                    verified_ops.push(Op2::Assert(tag));
+                }
+                Op1::CheckCast(then_label, else_label) => {
+                    let (id, s, body) = match stack_type.pop() {
+                        Some(Type::TaggedExists(id, s, body)) => (id, s, body),
+                        Some(t) => {
+                            return Err(Error::TypeErrorTaggedExistentialExpected(pos, *op, t))
+                        }
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    let target = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(t)) => t,
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    if target.size() != s {
+                        return Err(Error::SizeError(pos, *op, s, target.size()));
+                    }
+                    let Some(tag) = type_tag(&target) else {
+                        return Err(Error::TypeErrorUntaggable(pos, *op, target));
+                    };
+                    let then_t = types
+                        .get(then_label)
+                        .ok_or(Error::UnknownGlobalFunc(pos, *op, *then_label))?;
+                    let else_t = types
+                        .get(else_label)
+                        .ok_or(Error::UnknownGlobalFunc(pos, *op, *else_label))?;
+                    // if the tags match, the tag is dropped and the hidden type is revealed
+                    let mut then_stack_type = stack_type.clone();
+                    let mut then_compile_time_stack = compile_time_stack.clone();
+                    then_stack_type.push(substitute_t(
+                        &body,
+                        &HashMap::from([(id, target)]),
+                        &HashMap::new(),
+                    ));
+                    handle_call(
+                        pos,
+                        then_t,
+                        &mut then_stack_type,
+                        &mut then_compile_time_stack,
+                        &rgn_vars,
+                        *op,
+                    )?;
+                    // otherwise the tagged existential is passed along untouched
+                    stack_type.push(Type::TaggedExists(id, s, body));
+                    handle_call(
+                        pos,
+                        else_t,
+                        &mut stack_type,
+                        &mut compile_time_stack,
+                        &rgn_vars,
+                        *op,
+                    )?;
+                    // the code after sees one compile-time stack whichever branch runs
+                    if !ct_stack_eq(&then_compile_time_stack, &compile_time_stack) {
+                        return Err(Error::TypeErrorCheckCastBranchesDiffer(pos));
+                    }
+                    verified_ops.push(Op2::CheckCast(tag, *then_label, *else_label));
+                }
+                Op1::StackDepth => compile_time_stack.push(CTStackVal::Size(stack_type.len())),
+                Op1::NewRgn(size) => {
+                    let id = fresh_ids.id(*label)?;
+                    let r = Region {
+                        unique: true,
+                        id: RgnId::Var(id),
+                    };
+                    rgn_vars.push(r);
+                    new_rgns.push(r.id);
+                    stack_type.push(Type::Handle(r));
+                    compile_time_stack.push(CTStackVal::Region(r));
+                    verified_ops.push(Op2::NewRgn((*size).try_into().unwrap()));
+                }
+                Op1::FreeRgn => {
+                    let r = match stack_type.pop() {
+                        Some(Type::Handle(r)) => r,
+                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    match rgn_vars.iter().find(|r2| r.id == r2.id) {
+                        Some(r2) if r2.unique => {} // success
                         Some(_r2) => return Err(Error::UniquenessError(pos, *op, r)),
                         None => return Err(Error::RegionAccessError(pos, *op, r)),
                     };
+                    // copies of the handle can still be around until unique handles are linear
+                    if stack_type.iter().any(|t| points_into(t, r.id)) {
+                        return Err(Error::RegionFreedButStillReferenced(pos, *op, r));
+                    }
                     rgn_vars.retain(|r2| r2.id != r.id);
                     verified_ops.push(Op2::FreeRgn);
                 }
-                Op1::Ptr => handle_ptr(pos, op, &mut compile_time_stack)?,
+                Op1::MemCopy => {
+                    let (src_t, src_r) = match stack_type.pop() {
+                        Some(Type::Ptr(t, r)) => (*t, r),
+                        Some(t) => return Err(Error::TypeErrorPtrExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    let (dest_t, dest_r) = match stack_type.pop() {
+                        Some(Type::Ptr(t, r)) => (*t, r),
+                        Some(t) => return Err(Error::TypeErrorPtrExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    if !rgn_accessible(&src_r, &rgn_vars) {
+                        return Err(Error::RegionAccessError(pos, *op, src_r));
+                    }
+                    if dest_r.id == DataSection {
+                        return Err(Error::CannotMutateDataSection(pos, *op));
+                    } else if !rgn_accessible(&dest_r, &rgn_vars) {
+                        return Err(Error::RegionAccessError(pos, *op, dest_r));
+                    }
+                    let Type::Tuple(src_ts) = src_t else {
+                        return Err(Error::TypeErrorTupleExpected(pos, *op, src_t));
+                    };
+                    let Type::Tuple(dest_ts) = dest_t else {
+                        return Err(Error::TypeErrorTupleExpected(pos, *op, dest_t));
+                    };
+                    if let Some(n) = src_ts.iter().position(|(init, _)| !init) {
+                        return Err(Error::TypeErrorUninitializedRead(pos, *op, n as u8, src_ts));
+                    }
+                    // the destination's components may be uninitialized, but must have the same types
+                    let initialized = Type::Tuple(dest_ts.into_iter().map(|(_, t)| (true, t)).collect());
+                    let src_t = Type::Tuple(src_ts);
+                    if !type_eq(&src_t, &initialized) {
+                        return Err(Error::MemCopyTypeMismatch(pos, src_t, initialized));
+                    }
+                    verified_ops.push(Op2::MemCopy(src_t.size()));
+                    stack_type.push(Type::Ptr(Box::new(initialized), dest_r));
+                }
+                Op1::DropHandle => {
+                    let r = match stack_type.pop() {
+                        Some(Type::Handle(r)) => r,
+                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    if rgn_vars.iter().any(|r2| r2.id == r.id && r2.unique) {
+                        return Err(Error::UniqueHandleDropped(pos, *op, r));
+                    }
+                    verified_ops.push(Op2::Pop(Type::Handle(r).size()));
+                }
+                Op1::ResetRgn => {
+                    let r = match stack_type.pop() {
+                        Some(Type::Handle(r)) => r,
+                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    match rgn_vars.iter().find(|r2| r.id == r2.id) {
+                        Some(r2) if r2.unique => {} // success
+                        Some(_r2) => return Err(Error::UniquenessError(pos, *op, r)),
+                        None => return Err(Error::RegionAccessError(pos, *op, r)),
+                    };
+                    // the region stays live, so other handles to it are fine, but everything in it is gone
+                    let in_rgn = |t: &Type| matches!(t, Type::Ptr(_, r2) | Type::Array(_, r2) if r2.id == r.id);
+                    if stack_type.iter().any(|t| can_hold(t, &in_rgn)) {
+                        return Err(Error::RegionResetWhileReferenced(pos, *op, r));
+                    }
+                    stack_type.push(Type::Handle(r));
+                    verified_ops.push(Op2::ResetRgn);
+                }
                 Op1::Deref => {
                     let (t, r) = match stack_type.pop() {
                         Some(Type::Ptr(t, r)) => (t, r),
                         Some(t) => return Err(Error::TypeErrorPtrExpected(pos, *op, t)),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    if rgn_vars.iter().all(|r2| r.id != r2.id) {
+                    if !rgn_accessible(&r, &rgn_vars) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
+                    check_stack_size(pos, op, &t)?;
                     let size = t.size();
-                    if size > 4096 {
-                        return Err(Error::TooBigForStack(pos, *op, *t));
-                    }
                     stack_type.push(*t);
                     verified_ops.push(Op2::Deref(size));
                 }
-                Op1::Arr => handle_arr(pos, op, &mut compile_time_stack)?,
                 Op1::ArrMut => {
                     match stack_type.pop() {
                         Some(Type::I32) => {} // success
@@ -612,7 +1228,7 @@ pub fn definition_pass(
                         Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    if rgn_vars.iter().all(|r2| r2.id != r.id) {
+                    if !rgn_accessible(&r, &rgn_vars) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
                     let size = t.size();
@@ -630,10 +1246,11 @@ pub fn definition_pass(
                         Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    if rgn_vars.iter().all(|r2| r2.id != r.id) {
+                    if !rgn_accessible(&r, &rgn_vars) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
                     let t = *t;
+                    check_stack_size(pos, op, &t)?;
                     stack_type.push(t.clone());
                     if r.id == DataSection {
                         verified_ops.push(Op2::DataIndex(t.size()))
@@ -641,72 +1258,78 @@ pub fn definition_pass(
                         verified_ops.push(Op2::ArrProj(t.size()))
                     }
                 }
-                Op1::Add => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        };
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::AddI32);
-                    }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        };
-                        stack_type.push(Type::U8);
-                        verified_ops.push(Op2::AddU8);
+                Op1::ArrLen => {
+                    let (t, r) = match stack_type.pop() {
+                        Some(Type::Array(t, r)) => (t, r),
+                        Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    if !rgn_accessible(&r, &rgn_vars) {
+                        return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                },
-                Op1::Mul => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        }
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::MulI32);
+                    // the runtime only knows the length in bytes
+                    let size = t.size();
+                    if size == 0 {
+                        return Err(Error::ZeroSizedArrayElement(pos, *op, *t));
                     }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        }
-                        stack_type.push(Type::U8);
-                        verified_ops.push(Op2::MulU8);
+                    stack_type.push(Type::I32);
+                    verified_ops.push(Op2::ArrLen(size));
+                }
+                Op1::ArrAlloc => {
+                    let t = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(t)) => t,
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    match stack_type.pop() {
+                        Some(Type::I32) => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                },
-                Op1::Div => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        }
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::DivI32);
+                    let r = match stack_type.pop() {
+                        Some(Type::Handle(r)) => r,
+                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    if !rgn_accessible(&r, &rgn_vars) {
+                        return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
-                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                        }
-                        stack_type.push(Type::U8);
-                        verified_ops.push(Op2::DivU8);
+                    // as with `Malloc`, the runtime couldn't bounds-check an array of zero-sized elements
+                    let size = t.size();
+                    if size == 0 {
+                        return Err(Error::ZeroSizedArrayElement(pos, *op, t));
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                },
+                    stack_type.push(Type::Array(Box::new(t), r));
+                    verified_ops.push(Op2::NewArr(size));
+                }
+                Op1::Add => handle_arith(
+                    pos,
+                    op,
+                    &mut stack_type,
+                    &[(Type::I32, Op2::AddI32), (Type::U8, Op2::AddU8), (Type::I64, Op2::AddI64), (Type::F64, Op2::AddF64)],
+                    &mut verified_ops,
+                )?,
+                Op1::Sub => handle_arith(
+                    pos,
+                    op,
+                    &mut stack_type,
+                    &[(Type::I32, Op2::SubI32), (Type::U8, Op2::SubU8), (Type::I64, Op2::SubI64), (Type::F64, Op2::SubF64)],
+                    &mut verified_ops,
+                )?,
+                Op1::Mul => handle_arith(
+                    pos,
+                    op,
+                    &mut stack_type,
+                    &[(Type::I32, Op2::MulI32), (Type::U8, Op2::MulU8), (Type::I64, Op2::MulI64), (Type::F64, Op2::MulF64)],
+                    &mut verified_ops,
+                )?,
+                Op1::Div => handle_arith(
+                    pos,
+                    op,
+                    &mut stack_type,
+                    &[(Type::I32, Op2::DivI32), (Type::U8, Op2::DivU8), (Type::I64, Op2::DivI64), (Type::F64, Op2::DivF64)],
+                    &mut verified_ops,
+                )?,
                 Op1::CallNZ => {
                     let Some(t1) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
@@ -726,6 +1349,7 @@ pub fn definition_pass(
                         &t1,
                         &mut stack_type,
                         &mut compile_time_stack,
+                        &rgn_vars,
                         Op1::CallNZ,
                     )?;
                     verified_ops.push(Op2::CallNZ);
@@ -733,7 +1357,7 @@ pub fn definition_pass(
                 Op1::Data(loc) => match compile_time_stack.pop() {
                     Some(CTStackVal::Type(Type::Array(t, r))) if r.id == DataSection => {
                         let loc = *loc as usize;
-                        stack_type.push(Type::Array(t, r.clone()));
+                        stack_type.push(Type::Array(t, r));
                         verified_ops.push(Op2::Data(loc));
                     }
                     Some(CTStackVal::Type(t)) => {
@@ -763,15 +1387,6 @@ pub fn definition_pass(
                     Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
                     None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                 },
-                Op1::DataSec => {
-                    compile_time_stack.push(CTStackVal::Region(Region {
-                        unique: false,
-                        id: DataSection,
-                    }));
-                }
-                Op1::U8 => {
-                    compile_time_stack.push(CTStackVal::Type(Type::U8));
-                }
                 Op1::CopyN => {
                     match stack_type.pop() {
                         Some(Type::I32) => {} // success
@@ -794,10 +1409,10 @@ pub fn definition_pass(
                     if r2.id == DataSection {
                         return Err(Error::CannotMutateDataSection(pos, *op));
                     }
-                    if rgn_vars.iter().all(|r2| r.id != r2.id) {
+                    if !rgn_accessible(&r, &rgn_vars) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    if rgn_vars.iter().all(|r| r.id != r2.id) {
+                    if !rgn_accessible(&r2, &rgn_vars) {
                         return Err(Error::RegionAccessError(pos, *op, r2));
                     }
                     verified_ops.push(Op2::CopyN(t.size()));
@@ -815,24 +1430,27 @@ pub fn definition_pass(
                     Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::Modulo => match stack_type.pop() {
-                    Some(Type::I32) => match stack_type.pop() {
-                        Some(Type::I32) => {
-                            stack_type.push(Type::I32);
-                            verified_ops.push(Op2::ModuloI32);
-                        }
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
-                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                    },
-                    Some(Type::U8) => match stack_type.pop() {
-                        Some(Type::U8) => {
-                            stack_type.push(Type::U8);
-                            verified_ops.push(Op2::ModuloU8);
-                        }
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
-                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                    },
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                Op1::Modulo => handle_arith(
+                    pos,
+                    op,
+                    &mut stack_type,
+                    &[(Type::I32, Op2::ModuloI32), (Type::U8, Op2::ModuloU8), (Type::I64, Op2::ModuloI64)],
+                    &mut verified_ops,
+                )?,
+                Op1::I64ToI32 => match stack_type.pop() {
+                    Some(Type::I64) => {
+                        stack_type.push(Type::I32);
+                        verified_ops.push(Op2::I64ToI32);
+                    }
+                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I64, t)),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                },
+                Op1::F64ToI64 => match stack_type.pop() {
+                    Some(Type::F64) => {
+                        stack_type.push(Type::I64);
+                        verified_ops.push(Op2::F64ToI64);
+                    }
+                    Some(t) => return Err(Error::TypeError(pos, *op, Type::F64, t)),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
                 Op1::I32ToU8 => match stack_type.pop() {
@@ -863,7 +1481,7 @@ pub fn definition_pass(
                         (true, Type::Func(vec![t, Type::Var(a, 16)])),
                         (true, Type::Var(a, 16)),
                     ]);
-                    if type_eq(&*body, &body2) {
+                    if type_eq(&body, &body2) {
                         verified_ops.push(Op2::Read(*c));
                     } else {
                         return Err(Error::TypeError(pos, *op, body2, *body));
@@ -894,7 +1512,7 @@ pub fn definition_pass(
                         (true, Type::Func(vec![Type::Var(a, 16)])),
                         (true, Type::Var(a, 16)),
                     ]);
-                    if type_eq(&*body, &body2) {
+                    if type_eq(&body, &body2) {
                         match stack_type.pop() {
                             Some(t2) if type_eq(&t, &t2) => {
                                 verified_ops.push(Op2::Write(*c));
@@ -906,17 +1524,204 @@ pub fn definition_pass(
                         return Err(Error::TypeError(pos, *op, body2, *body));
                     }
                 }
+                // every compile-time op was handled by `handle_ct_op` above
+                Op1::Unique
+                | Op1::Handle
+                | Op1::I32
+                | Op1::Tuple(_)
+                | Op1::Sum(_)
+                | Op1::Some
+                | Op1::All
+                | Op1::Rec
+                | Op1::Rgn
+                | Op1::End
+                | Op1::Func(_)
+                | Op1::CTGet(_)
+                | Op1::Size(_)
+                | Op1::SizeOf
+                | Op1::SizeAdd
+                | Op1::SizeMul(_)
+                | Op1::Ptr
+                | Op1::Arr
+                | Op1::DataSec
+                | Op1::U8
+                | Op1::I64
+                | Op1::F64
+                | Op1::Tagged
+                | Op1::Nop
+                | Op1::Heap => unreachable!("{:?} is a compile-time op", op),
             },
         }
         pos += 1;
     }
-    if quantification_stack.len() > 0 {
+    if !quantification_stack.is_empty() {
         return Err(Error::TypeErrorNonEmptyQuantificationStack(*label));
     }
+    if !loop_stack.is_empty() {
+        return Err(Error::UnclosedLoop(*label));
+    }
+    if verified_ops.is_empty() {
+        return Err(Error::EmptyFunction(*label));
+    }
+    // The function's own binders have to be where they started. Above them, the regions it created
+    // and the witnesses it unpacked may stay, once each, but anything else was built and never used.
+    let (entry, rest) = compile_time_stack.split_at(entry_compile_time_stack.len().min(compile_time_stack.len()));
+    let mut left_rgns = HashSet::new();
+    let mut left_witnesses = HashSet::new();
+    let only_binders_left = ct_stack_eq(entry, &entry_compile_time_stack)
+        && rest.iter().all(|ctval| match ctval {
+            CTStackVal::Region(r) => new_rgns.contains(&r.id) && left_rgns.insert(r.id),
+            CTStackVal::Type(Type::Var(id, _)) => witnesses.contains(id) && left_witnesses.insert(*id),
+            _ => false,
+        });
+    if !only_binders_left {
+        return Err(Error::NonEmptyCompileTimeStackAtExit(*label));
+    }
+    // the parser splits bodies at terminators, but bodies built some other way might not end in one
+    if !verified_ops.last().is_some_and(is_terminator) {
+        return Err(Error::MissingTerminator(*label));
+    }
+    if opts.peephole {
+        verified_ops = peephole(verified_ops);
+    }
     // wrap t in the quantifiers from kind_context
     Ok(Stmt2::Func(*label, my_type, verified_ops))
 }
 
+/// Whether an op hands control away for good, so it has to end its function.
+fn is_terminator(op: &Op2) -> bool {
+    matches!(op, Op2::Call | Op2::CallNZ | Op2::Halt | Op2::CheckCast(..) | Op2::Unreachable | Op2::Case(_))
+}
+
+/// Remove ops that don't do anything, like a `Dup` followed by a `Pop` of the same size.
+/// An op a jump lands on is only removed if it's the first of its sequence,
+/// so every jump can be moved to the op that takes its target's place.
+pub fn peephole(ops: Vec<Op2>) -> Vec<Op2> {
+    let targets: HashSet<usize> = ops
+        .iter()
+        .filter_map(|op| match op {
+            Op2::Jump(i) | Op2::JumpNZ(i) => Some(*i as usize),
+            _ => None,
+        })
+        .collect();
+    let mut keep = vec![true; ops.len()];
+    let mut i = 0;
+    while i < ops.len() {
+        match (ops[i], ops.get(i + 1)) {
+            // moving a zero-sized value, or moving a value past nothing
+            (
+                Op2::Rotate(0, _)
+                | Op2::Rotate(_, 0)
+                | Op2::Pop(0)
+                | Op2::Clean(0, _)
+                | Op2::Get(_, 0)
+                | Op2::Dup(0)
+                | Op2::Swap(0, _)
+                | Op2::Swap(_, 0)
+                | Op2::Alloca(0)
+                | Op2::Init(_, 0, _),
+                _,
+            ) => {
+                keep[i] = false;
+                i += 1;
+            }
+            (Op2::Dup(size), Some(Op2::Pop(size2))) if size == *size2 && !targets.contains(&(i + 1)) => {
+                keep[i] = false;
+                keep[i + 1] = false;
+                i += 2;
+            }
+            // swapping back undoes the first swap
+            (Op2::Swap(size_a, size_b), Some(Op2::Swap(size_b2, size_a2)))
+                if size_a == *size_a2 && size_b == *size_b2 && !targets.contains(&(i + 1)) =>
+            {
+                keep[i] = false;
+                keep[i + 1] = false;
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    // where each op's index moves to, which for a removed op is the next op that's kept
+    let mut new_index = Vec::with_capacity(ops.len() + 1);
+    let mut kept = 0;
+    for k in &keep {
+        new_index.push(kept as u32);
+        kept += *k as usize;
+    }
+    new_index.push(kept as u32);
+    ops.into_iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(op, _)| match op {
+            Op2::Jump(i) => Op2::Jump(new_index[i as usize]),
+            Op2::JumpNZ(i) => Op2::JumpNZ(new_index[i as usize]),
+            op => op,
+        })
+        .collect()
+}
+
+/// What `Init` does once the component's type checks: push the updated tuple or pointer and the op.
+/// It gets the component's type, the tuple's components, and the stack and ops to push to.
+type InitFn<'a> = &'a dyn Fn(&Type, Vec<(bool, Type)>, &mut Vec<Type>, &mut Vec<Op2>);
+
+/// What `Proj` does once the component is known to be initialized: push its type and the op.
+/// It gets the component's type, the tuple's size, the stack and ops to push to, and the tuple's components.
+type ProjFn<'a> = &'a dyn Fn(&Type, usize, &mut Vec<Type>, &mut Vec<Op2>, Vec<(bool, Type)>);
+
+/// A loop being verified.
+/// The state at the loop header is the invariant every back edge has to restore,
+/// and the first `break` fixes the state every other `break` has to leave the loop in.
+struct LoopFrame {
+    header: usize,
+    entry: LoopState,
+    breaks: Vec<usize>,
+    exit: Option<LoopState>,
+}
+
+/// The stacks and live regions at a point where control enters or leaves a loop.
+struct LoopState {
+    stack_type: Vec<Type>,
+    compile_time_stack: Vec<CTStackVal>,
+    rgn_vars: Vec<Region>,
+}
+
+fn check_loop_state(
+    pos: u32,
+    op: Op1,
+    expected: &LoopState,
+    stack_type: &[Type],
+    compile_time_stack: &[CTStackVal],
+    rgn_vars: &[Region],
+) -> Result<(), Error> {
+    if expected.stack_type.len() != stack_type.len()
+        || !expected.stack_type.iter().zip(stack_type).all(|(t1, t2)| type_eq(t1, t2))
+    {
+        return Err(Error::TypeErrorLoopStackMismatch(
+            pos,
+            op,
+            expected.stack_type.clone(),
+            stack_type.to_vec(),
+        ));
+    }
+    if !ct_stack_eq(&expected.compile_time_stack, compile_time_stack) {
+        return Err(Error::TypeErrorLoopCTStackMismatch(
+            pos,
+            op,
+            expected.compile_time_stack.clone(),
+            compile_time_stack.to_vec(),
+        ));
+    }
+    if expected.rgn_vars.len() != rgn_vars.len() || !expected.rgn_vars.iter().all(|r| rgn_vars.iter().any(|r2| r2 == r && r2.unique == r.unique)) {
+        return Err(Error::LoopRegionsMismatch(
+            pos,
+            op,
+            expected.rgn_vars.clone(),
+            rgn_vars.to_vec(),
+        ));
+    }
+    Ok(())
+}
+
 fn valid_data_section_type(t: &Type) -> bool {
     match t {
         Type::I32 => true,
@@ -928,11 +1733,15 @@ fn valid_data_section_type(t: &Type) -> bool {
     }
 }
 
+/// Check a call to a function of type `t`, instantiating its quantifiers with compile-time arguments.
+/// The callee assumes access to every region it's instantiated with,
+/// so the caller has to actually have that access (and own the region, for a unique binder).
 fn handle_call(
     pos: u32,
     t: &Type,
     stack_type: &mut Vec<Type>,
     compile_time_stack: &mut Vec<CTStackVal>,
+    rgn_vars: &[Region],
     op1: Op1,
 ) -> Result<(), Error> {
     match t {
@@ -954,11 +1763,8 @@ fn handle_call(
             let types_match = arg_ts_present
                 .iter()
                 .zip(arg_ts_needed.iter())
-                .all(|(t1, t2)| type_eq(t1, t2));
+                .all(|(t1, t2)| type_sub(t1, t2));
             if !types_match {
-                for t in stack_type {
-                    println!("{}", t.pretty());
-                }
                 return Err(Error::TypeErrorCallArgTypesMismatch(
                     pos,
                     arg_ts_needed.to_vec(),
@@ -967,37 +1773,131 @@ fn handle_call(
             }
             Ok(())
         }
-        Type::Forall(var, size, body) => {
-            let mb_t = compile_time_stack.pop();
-            match mb_t {
-                Some(CTStackVal::Type(t)) => {
-                    if t.size() != *size {
-                        return Err(Error::SizeError(pos, op1, *size, t.size()));
-                    }
-                    let new_t = substitute_t(&*body, &HashMap::from([(*var, t)]), &HashMap::new());
-                    handle_call(pos, &new_t, stack_type, compile_time_stack, op1)
-                }
-                Some(ctval) => return Err(Error::KindError(pos, op1, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, op1)),
+        Type::Forall(..) | Type::ForallRegion(..) => {
+            // quantifiers are unwrapped outermost first, so its argument is the one on top
+            let Some(arg) = compile_time_stack.pop() else {
+                return Err(Error::TypeErrorEmptyCTStack(pos, op1));
+            };
+            let new_t = instantiate(pos, op1, t, arg, rgn_vars)?;
+            handle_call(pos, &new_t, stack_type, compile_time_stack, rgn_vars, op1)
+        }
+        _ => Err(Error::TypeErrorFunctionExpected(pos, op1, t.clone())),
+    }
+}
+
+/// The byte offset of the `i`th component of a tuple.
+/// `Init` and `Proj` both use this so that a component is read from where it was written.
+fn component_offset(component_types: &[(bool, Type)], i: u8) -> usize {
+    component_types[..usize::from(i)].iter().map(|(_, t)| t.size()).sum()
+}
+
+/// Instantiate one quantifier of a polymorphic type.
+/// `App` does this once, and a call does it until it reaches the function type,
+/// so both go through here and agree on what a legal instantiation is.
+fn instantiate(pos: u32, op: Op1, t: &Type, arg: CTStackVal, rgn_vars: &[Region]) -> Result<Type, Error> {
+    match (t, arg) {
+        (Type::Forall(var, size, body), CTStackVal::Type(t_arg)) => {
+            if t_arg.size() != *size {
+                return Err(Error::SizeError(pos, op, *size, t_arg.size()));
             }
+            Ok(substitute_t(body, &HashMap::from([(*var, t_arg)]), &HashMap::new()))
         }
-        Type::ForallRegion(var, body, captured_rgns) => {
-            let mb_r = compile_time_stack.pop();
-            match mb_r {
-                Some(CTStackVal::Region(r)) => {
-                    if var.unique && captured_rgns.iter().any(|r2| r2.id == r.id) {
-                        return Err(Error::RegionAccessError(pos, op1, r));
-                    }
-                    let new_t =
-                        substitute_t(&*body, &HashMap::new(), &HashMap::from([(var.id, r)]));
-                    handle_call(pos, &new_t, stack_type, compile_time_stack, op1)
-                }
-                Some(ctval) => return Err(Error::KindError(pos, op1, Kind::Region, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, op1)),
+        (Type::ForallRegion(var, body, captured_rgns), CTStackVal::Region(r)) => {
+            // Captured regions are unique, so handing one in again would give the body two names for it.
+            // It could free the region through one and read through the other,
+            // so this holds whether or not the binder is itself unique.
+            if captured_rgns.iter().any(|r2| r2.id == r.id) {
+                return Err(Error::RegionAccessError(pos, op, r));
+            }
+            match rgn_vars.iter().find(|r2| r2.id == r.id) {
+                Some(r2) if var.unique && !r2.unique => return Err(Error::UniquenessError(pos, op, r)),
+                Some(_r2) => {} // success
+                None => return Err(Error::InsufficientCapabilityForCall(pos, op, r)),
             }
+            Ok(substitute_t(body, &HashMap::new(), &HashMap::from([(var.id, r)])))
         }
-        _ => return Err(Error::TypeErrorFunctionExpected(pos, op1, t.clone())),
+        (Type::Forall(..), ctval) => Err(Error::KindError(pos, op, Kind::Type, ctval)),
+        (Type::ForallRegion(..), ctval) => Err(Error::KindError(pos, op, Kind::Region, ctval)),
+        (t, CTStackVal::Type(_)) => Err(Error::TypeErrorForallExpected(pos, op, t.clone())),
+        (t, CTStackVal::Region(_)) => Err(Error::TypeErrorForallRegionExpected(pos, op, t.clone())),
+        (_, ctval) => Err(Error::KindErrorBadApp(pos, op, ctval)),
+    }
+}
+
+/// The state the ops that only work at compile time can touch.
+/// Both passes handle those ops the same way, through `handle_ct_op`.
+struct CTContext<'a> {
+    label: Label,
+    compile_time_stack: &'a mut Vec<CTStackVal>,
+    quantification_stack: &'a mut Vec<Quantification>,
+    fresh_ids: &'a mut FreshIds,
+    next_region_is_unique: &'a mut bool,
+}
+
+/// Handle an op that only works on the compile-time stack, returning whether `op` was one.
+fn handle_ct_op(pos: u32, op: &Op1, ctx: &mut CTContext) -> Result<bool, Error> {
+    match op {
+        Op1::Unique => *ctx.next_region_is_unique = true,
+        Op1::Handle => handle_handle(pos, op, ctx.compile_time_stack)?,
+        Op1::I32 => ctx.compile_time_stack.push(CTStackVal::Type(Type::I32)),
+        Op1::Tuple(n) => handle_tuple(n, pos, op, ctx.compile_time_stack)?,
+        Op1::Sum(n) => handle_sum(n, pos, op, ctx.compile_time_stack)?,
+        Op1::Some => handle_some(
+            pos,
+            op,
+            ctx.compile_time_stack,
+            ctx.fresh_ids,
+            &ctx.label,
+            ctx.quantification_stack,
+        )?,
+        Op1::All => handle_all(
+            pos,
+            op,
+            ctx.compile_time_stack,
+            ctx.fresh_ids,
+            &ctx.label,
+            ctx.quantification_stack,
+        )?,
+        Op1::Rec => handle_rec(
+            pos,
+            op,
+            ctx.compile_time_stack,
+            ctx.fresh_ids,
+            &ctx.label,
+            ctx.quantification_stack,
+        )?,
+        Op1::Rgn => handle_rgn(
+            ctx.next_region_is_unique,
+            &ctx.label,
+            ctx.fresh_ids,
+            ctx.compile_time_stack,
+            ctx.quantification_stack,
+        )?,
+        Op1::End => handle_end(pos, op, ctx.compile_time_stack, ctx.quantification_stack)?,
+        Op1::Func(n) => handle_func(n, pos, op, ctx.compile_time_stack)?,
+        Op1::CTGet(i) => handle_ctget(pos, i, ctx.compile_time_stack)?,
+        Op1::Size(s) => ctx.compile_time_stack.push(CTStackVal::Size(check_size(pos, op, *s as usize)?)),
+        Op1::SizeOf => handle_size_of(pos, op, ctx.compile_time_stack)?,
+        Op1::SizeAdd | Op1::SizeMul(_) => handle_size_arith(pos, op, ctx.compile_time_stack)?,
+        Op1::Ptr => handle_ptr(pos, op, ctx.compile_time_stack)?,
+        Op1::Arr => handle_arr(pos, op, ctx.compile_time_stack)?,
+        Op1::DataSec => ctx.compile_time_stack.push(CTStackVal::Region(Region {
+            unique: false,
+            id: DataSection,
+        })),
+        Op1::U8 => ctx.compile_time_stack.push(CTStackVal::Type(Type::U8)),
+        Op1::I64 => ctx.compile_time_stack.push(CTStackVal::Type(Type::I64)),
+        Op1::F64 => ctx.compile_time_stack.push(CTStackVal::Type(Type::F64)),
+        Op1::Tagged => handle_tagged(pos, op, ctx.compile_time_stack)?,
+        // this doesn't touch the compile-time stack either, but it's allowed everywhere
+        Op1::Nop => {}
+        Op1::Heap => ctx.compile_time_stack.push(CTStackVal::Region(Region {
+            unique: false,
+            id: RgnId::Heap,
+        })),
+        _ => return Ok(false),
     }
+    Ok(true)
 }
 
 fn handle_handle(
@@ -1013,8 +1913,8 @@ fn handle_handle(
             compile_time_stack.push(CTStackVal::Type(Type::Handle(r)));
             Ok(())
         }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
@@ -1036,66 +1936,216 @@ fn handle_tuple(
     Ok(())
 }
 
-fn handle_some(
+/// Pop two numbers of the same type and push one more of that type, using the `Op2` for that type.
+/// The first type in `ops` is the one error messages ask for.
+fn handle_arith(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
-    fresh_id: &mut u32,
-    label: &u32,
-    quantification_stack: &mut Vec<Quantification>,
+    stack_type: &mut Vec<Type>,
+    ops: &[(Type, Op2)],
+    verified_ops: &mut Vec<Op2>,
 ) -> Result<(), Error> {
-    match compile_time_stack.pop() {
-        Some(CTStackVal::Size(s)) => {
-            let id = Id(*label, *fresh_id);
-            *fresh_id += 1;
-            compile_time_stack.push(CTStackVal::Type(Type::Var(id.clone(), s)));
-            quantification_stack.push(Quantification::Exist(id, s));
-            Ok(())
-        }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Size, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    let Some(t) = stack_type.pop() else {
+        return Err(Error::TypeErrorEmptyStack(pos, *op));
+    };
+    let Some((_, op2)) = ops.iter().find(|(t2, _)| *t2 == t) else {
+        return Err(Error::TypeError(pos, *op, ops[0].0.clone(), t));
+    };
+    match stack_type.pop() {
+        Some(t2) if t2 == t => {} // success
+        Some(t2) => return Err(Error::TypeError(pos, *op, t, t2)),
+        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
     }
+    stack_type.push(t);
+    verified_ops.push(*op2);
+    Ok(())
 }
 
-fn handle_all(
+fn handle_sum(
+    n: &u8,
     pos: u32,
     op: &Op1,
     compile_time_stack: &mut Vec<CTStackVal>,
-    fresh_id: &mut u32,
-    label: &u32,
-    quantification_stack: &mut Vec<Quantification>,
 ) -> Result<(), Error> {
-    match compile_time_stack.pop() {
-        Some(CTStackVal::Size(s)) => {
-            let id = Id(*label, *fresh_id);
-            *fresh_id += 1;
-            compile_time_stack.push(CTStackVal::Type(Type::Var(id.clone(), s)));
-            quantification_stack.push(Quantification::Forall(id, s));
-            Ok(())
+    let mut ts = vec![];
+    for _ in 0..*n {
+        match compile_time_stack.pop() {
+            Some(CTStackVal::Type(t)) => ts.push(t),
+            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Size, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
+    compile_time_stack.push(CTStackVal::Type(Type::Sum(ts)));
+    Ok(())
 }
 
-fn handle_rgn(
-    next_region_is_unique: &mut bool,
-    label: &u32,
-    fresh_id: &mut u32,
-    compile_time_stack: &mut Vec<CTStackVal>,
-    quantification_stack: &mut Vec<Quantification>,
+/// The largest size a type variable may be given. Sizes drive the offset and
+/// allocation arithmetic in both the verifier and the VM, so bounding them
+/// here keeps sums like `offset += t.size()` far from overflowing.
+pub const MAX_SIZE: usize = 1 << 24;
+
+/// The biggest value the stack can hold.
+/// The VM's stack is made of chunks this big, and a value is never split between two of them.
+pub const MAX_STACK_VALUE_SIZE: usize = 4096;
+
+/// Check that a value of type `t` isn't too big to push onto the stack.
+fn check_stack_size(pos: u32, op: &Op1, t: &Type) -> Result<(), Error> {
+    if t.size() > MAX_STACK_VALUE_SIZE {
+        return Err(Error::TooBigForStack(pos, *op, t.clone()));
+    }
+    Ok(())
+}
+
+fn handle_size_of(pos: u32, op: &Op1, compile_time_stack: &mut Vec<CTStackVal>) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Type(t)) => {
+            compile_time_stack.push(CTStackVal::Size(check_size(pos, op, t.size())?));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// `SizeAdd` or `SizeMul`.
+fn handle_size_arith(pos: u32, op: &Op1, compile_time_stack: &mut Vec<CTStackVal>) -> Result<(), Error> {
+    let mut pop_size = || match compile_time_stack.pop() {
+        Some(CTStackVal::Size(s)) => Ok(s),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    };
+    let s = match op {
+        Op1::SizeMul(n) => pop_size()? * usize::from(*n),
+        _ => pop_size()? + pop_size()?,
+    };
+    compile_time_stack.push(CTStackVal::Size(check_size(pos, op, s)?));
+    Ok(())
+}
+
+fn check_size(pos: u32, op: &Op1, s: usize) -> Result<usize, Error> {
+    if s > MAX_SIZE {
+        return Err(Error::SizeTooLarge(pos, *op, s, MAX_SIZE));
+    }
+    Ok(s)
+}
+
+fn handle_some(
+    pos: u32,
+    op: &Op1,
+    compile_time_stack: &mut Vec<CTStackVal>,
+    fresh_ids: &mut FreshIds,
+    label: &u32,
+    quantification_stack: &mut Vec<Quantification>,
+) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Size(s)) => {
+            let s = check_size(pos, op, s)?;
+            let id = fresh_ids.id(*label)?;
+            compile_time_stack.push(CTStackVal::Type(Type::Var(id, s)));
+            quantification_stack.push(Quantification::Exist(id, s));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+fn handle_all(
+    pos: u32,
+    op: &Op1,
+    compile_time_stack: &mut Vec<CTStackVal>,
+    fresh_ids: &mut FreshIds,
+    label: &u32,
+    quantification_stack: &mut Vec<Quantification>,
+) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Size(s)) => {
+            let s = check_size(pos, op, s)?;
+            let id = fresh_ids.id(*label)?;
+            compile_time_stack.push(CTStackVal::Type(Type::Var(id, s)));
+            quantification_stack.push(Quantification::Forall(id, s));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+fn handle_rec(
+    pos: u32,
+    op: &Op1,
+    compile_time_stack: &mut Vec<CTStackVal>,
+    fresh_ids: &mut FreshIds,
+    label: &u32,
+    quantification_stack: &mut Vec<Quantification>,
 ) -> Result<(), Error> {
-    let id = Id(*label, *fresh_id);
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Size(s)) => {
+            let s = check_size(pos, op, s)?;
+            let id = fresh_ids.id(*label)?;
+            compile_time_stack.push(CTStackVal::Type(Type::Var(id, s)));
+            quantification_stack.push(Quantification::Rec(id, s));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// The body of a recursive type with the recursive type substituted for its variable.
+fn unfold(rec: &Type) -> Type {
+    let Type::Rec(id, _s, body) = rec else {
+        unreachable!("only recursive types unfold");
+    };
+    let mut sub = HashMap::new();
+    sub.insert(*id, rec.clone());
+    substitute_t(body, &sub, &HashMap::new())
+}
+
+/// Whether ops in a function may touch memory in the given region.
+/// Every region the function can name goes through this one check,
+/// so regions that are live everywhere only need special-casing here.
+fn rgn_accessible(r: &Region, rgn_vars: &[Region]) -> bool {
+    r.id == RgnId::Heap || rgn_vars.iter().any(|r2| r.id == r2.id)
+}
+
+fn handle_rgn(
+    next_region_is_unique: &mut bool,
+    label: &u32,
+    fresh_ids: &mut FreshIds,
+    compile_time_stack: &mut Vec<CTStackVal>,
+    quantification_stack: &mut Vec<Quantification>,
+) -> Result<(), Error> {
+    let id = fresh_ids.id(*label)?;
     let r = Region {
         unique: *next_region_is_unique,
         id: RgnId::Var(id),
     };
-    *fresh_id += 1;
-    compile_time_stack.push(CTStackVal::Region(r.clone()));
+    // `unique` only applies to the region right after it
+    *next_region_is_unique = false;
+    compile_time_stack.push(CTStackVal::Region(r));
     quantification_stack.push(Quantification::Region(r));
     Ok(())
 }
 
+/// Find a use of the type variable `id` at a size other than the `s` it was bound with.
+/// Every use starts as a copy of the binder's own variable, so this is a sanity check on the verifier.
+fn var_size_mismatch(t: &Type, id: Id, s: usize) -> Option<usize> {
+    match t {
+        Type::Var(id2, s2) if *id2 == id && *s2 != s => Some(*s2),
+        Type::Tuple(ts) => ts.iter().find_map(|(_, t)| var_size_mismatch(t, id, s)),
+        Type::Func(ts) | Type::Sum(ts) => ts.iter().find_map(|t| var_size_mismatch(t, id, s)),
+        Type::Ptr(t, _) | Type::Array(t, _) | Type::ForallRegion(_, t, _) => var_size_mismatch(t, id, s),
+        // an inner binder of the same id would shadow this one
+        Type::Exists(id2, _, t) | Type::Forall(id2, _, t) | Type::TaggedExists(id2, _, t) | Type::Rec(id2, _, t)
+            if *id2 != id =>
+        {
+            var_size_mismatch(t, id, s)
+        }
+        _ => None,
+    }
+}
+
 fn handle_end(
     pos: u32,
     op: &Op1,
@@ -1106,38 +2156,70 @@ fn handle_end(
         Some(Quantification::Exist(id, s)) => match compile_time_stack.pop() {
             Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
                 Some(CTStackVal::Type(Type::Var(id2, _))) if id == id2 => {
+                    if let Some(s2) = var_size_mismatch(&t, id, s) {
+                        return Err(Error::TypeVarSizeInconsistent(pos, *op, id, s, s2));
+                    }
                     compile_time_stack.push(CTStackVal::Type(Type::Exists(id, s, Box::new(t))));
                     Ok(())
                 }
                 Some(CTStackVal::Type(Type::Var(id2, _))) => {
-                    return Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
+                    Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
                 }
                 Some(CTStackVal::Type(t)) => {
-                    return Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
+                    Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
                 }
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
         Some(Quantification::Forall(id, s)) => match compile_time_stack.pop() {
             Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
                 Some(CTStackVal::Type(Type::Var(id2, _))) if id == id2 => {
+                    if let Some(s2) = var_size_mismatch(&t, id, s) {
+                        return Err(Error::TypeVarSizeInconsistent(pos, *op, id, s, s2));
+                    }
                     compile_time_stack.push(CTStackVal::Type(Type::Forall(id, s, Box::new(t))));
                     Ok(())
                 }
                 Some(CTStackVal::Type(Type::Var(id2, _))) => {
-                    return Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
+                    Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
                 }
                 Some(CTStackVal::Type(t)) => {
-                    return Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
+                    Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
                 }
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        },
+        Some(Quantification::Rec(id, s)) => match compile_time_stack.pop() {
+            Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
+                Some(CTStackVal::Type(Type::Var(id2, _))) if id == id2 => {
+                    if let Some(s2) = var_size_mismatch(&t, id, s) {
+                        return Err(Error::TypeVarSizeInconsistent(pos, *op, id, s, s2));
+                    }
+                    // the variable stands for the whole type, so a body of any other size
+                    // would mean the variable appears unboxed inside itself
+                    if t.size() != s {
+                        return Err(Error::SizeError(pos, *op, s, t.size()));
+                    }
+                    compile_time_stack.push(CTStackVal::Type(Type::Rec(id, s, Box::new(t))));
+                    Ok(())
+                }
+                Some(CTStackVal::Type(Type::Var(id2, _))) => {
+                    Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
+                }
+                Some(CTStackVal::Type(t)) => {
+                    Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
+                }
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            },
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
         Some(Quantification::Region(r)) => match compile_time_stack.pop() {
             Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
@@ -1149,14 +2231,14 @@ fn handle_end(
                     )));
                     Ok(())
                 }
-                Some(CTStackVal::Region(r2)) => return Err(Error::RegionError(pos, *op, r, r2)),
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(CTStackVal::Region(r2)) => Err(Error::RegionError(pos, *op, r, r2)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
-        None => return Err(Error::TypeErrorEmptyQuantificationStack(pos, *op)),
+        None => Err(Error::TypeErrorEmptyQuantificationStack(pos, *op)),
     }
 }
 
@@ -1178,17 +2260,19 @@ fn handle_func(
     Ok(())
 }
 
+// `i` counts down from the top of the compile-time stack, like `Get` does for the runtime stack.
+// The copy is pushed on top, so a following `CTGet(0)` fetches the copy.
 fn handle_ctget(pos: u32, i: &u8, compile_time_stack: &mut Vec<CTStackVal>) -> Result<(), Error> {
-    if compile_time_stack.len() - 1 < *i as usize {
+    if compile_time_stack.is_empty() {
         return Err(Error::TypeErrorEmptyCTStack(pos, Op1::CTGet(*i)));
     }
-    match compile_time_stack.get(compile_time_stack.len() - 1 - (*i) as usize) {
+    match compile_time_stack.len().checked_sub(1 + *i as usize).and_then(|j| compile_time_stack.get(j)) {
         Some(ctval) => {
             compile_time_stack.push(ctval.clone());
             Ok(())
         }
         None => {
-            return Err(Error::TypeErrorCTGetOutOfRange(
+            Err(Error::TypeErrorCTGetOutOfRange(
                 pos,
                 *i,
                 compile_time_stack.len(),
@@ -1204,11 +2288,11 @@ fn handle_ptr(pos: u32, op: &Op1, compile_time_stack: &mut Vec<CTStackVal>) -> R
                 compile_time_stack.push(CTStackVal::Type(Type::Ptr(Box::new(t), r)));
                 Ok(())
             }
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
@@ -1223,20 +2307,96 @@ pub fn handle_arr(
                 compile_time_stack.push(CTStackVal::Type(Type::Array(Box::new(t), r)));
                 Ok(())
             }
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
+fn handle_tagged(
+    pos: u32,
+    op: &Op1,
+    compile_time_stack: &mut Vec<CTStackVal>,
+) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Type(Type::Exists(id, s, t))) => {
+            compile_time_stack.push(CTStackVal::Type(Type::TaggedExists(id, s, t)));
+            Ok(())
+        }
+        Some(CTStackVal::Type(t)) => Err(Error::TypeErrorExistentialExpected(pos, *op, t)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// Check if a value of the given type can hold a value of a type that satisfies `f`,
+/// including through pointers and arrays.
+/// Like the rest of the region checks, this can't see through type variables,
+/// so anything hidden in an existential is the responsibility of the region's owner.
+fn can_hold(t: &Type, f: &impl Fn(&Type) -> bool) -> bool {
+    f(t) || match t {
+        Type::Tuple(ts) => ts.iter().any(|(_, t)| can_hold(t, f)),
+        Type::Sum(ts) => ts.iter().any(|t| can_hold(t, f)),
+        Type::Ptr(t, _) | Type::Array(t, _) => can_hold(t, f),
+        Type::Exists(_, _, t) | Type::TaggedExists(_, _, t) | Type::Rec(_, _, t) => can_hold(t, f),
+        _ => false,
+    }
+}
+
+/// Check if a value of the given type can hold a pointer into the given region or a handle to it.
+fn points_into(t: &Type, r: RgnId) -> bool {
+    can_hold(t, &|t| matches!(t, Type::Ptr(_, r2) | Type::Array(_, r2) | Type::Handle(r2) if r2.id == r))
+}
+
+/// Find a unique region that dropping a value of the given type would lose the handle to.
+fn unique_handle_in(t: &Type, rgn_vars: &[Region]) -> Option<Region> {
+    let holds_handle = |r: &Region| can_hold(t, &|t| matches!(t, Type::Handle(r2) if r2.id == r.id));
+    rgn_vars.iter().find(|r| r.unique && holds_handle(r)).copied()
+}
+
+/// The runtime tag of a type, used by tagged existentials.
+/// Only types that mean the same thing in every function have tags,
+/// so anything mentioning regions or type variables is untaggable.
+/// Tags are handed out in the order types are first tagged, so two types share a tag only if they're the same type.
+pub fn type_tag(t: &Type) -> Option<u64> {
+    fn encode(t: &Type, out: &mut Vec<u8>) -> Option<()> {
+        match t {
+            Type::I32 => out.push(0),
+            Type::U8 => out.push(1),
+            Type::I64 => out.push(3),
+            Type::F64 => out.push(4),
+            Type::Tuple(ts) => {
+                out.push(2);
+                out.extend((ts.len() as u32).to_le_bytes());
+                // a tuple with uninitialized components isn't the same type as the initialized one
+                for (init, t) in ts {
+                    out.push(*init as u8);
+                    encode(t, out)?;
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+    // every program in the process shares one table, so tags agree across separately verified modules
+    static TAGS: OnceLock<Mutex<HashMap<Vec<u8>, u64>>> = OnceLock::new();
+    let mut bytes = vec![];
+    encode(t, &mut bytes)?;
+    let mut tags = TAGS.get_or_init(Default::default).lock().unwrap();
+    let next = tags.len() as u64;
+    Some(*tags.entry(bytes).or_insert(next))
+}
+
 /// Perform some variable substitutions within a type.
 /// This does not modify the original.
 pub fn substitute_t(typ: &Type, tsubs: &HashMap<Id, Type>, rsubs: &HashMap<RgnId, Region>) -> Type {
     match typ {
         Type::I32 => Type::I32,
         Type::U8 => Type::U8,
+        Type::I64 => Type::I64,
+        Type::F64 => Type::F64,
         Type::Handle(r) => Type::Handle(substitute_r(r, rsubs)),
         Type::Tuple(ts) => Type::Tuple(
             ts.iter()
@@ -1249,20 +2409,22 @@ pub fn substitute_t(typ: &Type, tsubs: &HashMap<Id, Type>, rsubs: &HashMap<RgnId
         ),
         Type::Var(id, repr) => match tsubs.get(id) {
             Some(new) => new.clone(),
-            None => Type::Var(*id, repr.clone()),
+            None => Type::Var(*id, *repr),
         },
         Type::Func(args) => {
             Type::Func(args.iter().map(|t| substitute_t(t, tsubs, rsubs)).collect())
         }
-        Type::Exists(id, s, t) => Type::Exists(*id, *s, Box::new(substitute_t(t, tsubs, rsubs))),
-        Type::Forall(id, s, t) => Type::Forall(*id, *s, Box::new(substitute_t(t, tsubs, rsubs))),
+        Type::Exists(id, s, t) => Type::Exists(*id, *s, substitute_under(*id, t, tsubs, rsubs)),
+        Type::Forall(id, s, t) => Type::Forall(*id, *s, substitute_under(*id, t, tsubs, rsubs)),
+        Type::TaggedExists(id, s, t) => Type::TaggedExists(*id, *s, substitute_under(*id, t, tsubs, rsubs)),
+        Type::Rec(id, s, t) => Type::Rec(*id, *s, substitute_under(*id, t, tsubs, rsubs)),
+        Type::Sum(ts) => Type::Sum(ts.iter().map(|t| substitute_t(t, tsubs, rsubs)).collect()),
         Type::ForallRegion(id, t, captured_rgns) => {
-            let mut captured_rgns = captured_rgns.clone();
-            for (_, r) in rsubs {
-                if r.unique {
-                    captured_rgns.push(*r);
-                }
-            }
+            // rsubs is a HashMap, so sort what it contributes to keep the result deterministic
+            let mut newly_captured: Vec<Region> = rsubs.values().filter(|r| r.unique).copied().collect();
+            newly_captured.sort_by_key(|r| r.id);
+            let mut captured_rgns: Vec<Region> = captured_rgns.iter().map(|r| substitute_r(r, rsubs)).collect();
+            captured_rgns.extend(newly_captured);
             Type::ForallRegion(*id, Box::new(substitute_t(t, tsubs, rsubs)), captured_rgns)
         }
         Type::Array(t, r) => Type::Array(
@@ -1272,6 +2434,17 @@ pub fn substitute_t(typ: &Type, tsubs: &HashMap<Id, Type>, rsubs: &HashMap<RgnId
     }
 }
 
+/// Substitute within the body of a quantifier that binds `id`, which shadows any substitution for `id`.
+fn substitute_under(id: Id, t: &Type, tsubs: &HashMap<Id, Type>, rsubs: &HashMap<RgnId, Region>) -> Box<Type> {
+    if tsubs.contains_key(&id) {
+        let mut tsubs = tsubs.clone();
+        tsubs.remove(&id);
+        Box::new(substitute_t(t, &tsubs, rsubs))
+    } else {
+        Box::new(substitute_t(t, tsubs, rsubs))
+    }
+}
+
 /// Perform some variable substitutions in a compile-time region value.
 /// This does not modify the original
 pub fn substitute_r(r: &Region, rsubs: &HashMap<RgnId, Region>) -> Region {
@@ -1282,71 +2455,1610 @@ pub fn substitute_r(r: &Region, rsubs: &HashMap<RgnId, Region>) -> Region {
 }
 
 /// Check if two types are equal, for typechecking purposes.
+/// Types are equal up to renaming their bound variables.
 pub fn type_eq(type1: &Type, type2: &Type) -> bool {
+    // not `==` first: that ignores the uniqueness of region binders, which `alpha_eq` checks
+    alpha_eq(type1, type2, &mut 0)
+}
+
+/// A variable no type mentions, for `alpha_eq` to rename both sides' bound variables to.
+/// Real ids have a function label as their position, and there are never `u32::MAX` functions.
+fn fresh_id(fresh: &mut u32) -> Id {
+    *fresh += 1;
+    Id(u32::MAX, *fresh)
+}
+
+/// Rename a type variable that's bound just outside `t`.
+fn rename_t(t: &Type, from: Id, to: Id, size: usize) -> Type {
+    substitute_t(t, &HashMap::from([(from, Type::Var(to, size))]), &HashMap::new())
+}
+
+/// `type_eq`, with a counter for generating fresh variables.
+/// Both sides of a quantifier are renamed to the same fresh variable,
+/// so neither side's renaming can capture a variable bound inside the other.
+fn alpha_eq(type1: &Type, type2: &Type, fresh: &mut u32) -> bool {
     match (type1, type2) {
         (Type::I32, Type::I32) => true,
         (Type::U8, Type::U8) => true,
-        (Type::Handle(r1), Type::Handle(r2)) => r1 == r2,
+        (Type::I64, Type::I64) => true,
+        (Type::F64, Type::F64) => true,
+        // uniqueness is a capability the function holds, not part of a region's identity,
+        // so handles, pointers, and arrays only compare region ids
+        (Type::Handle(r1), Type::Handle(r2)) => r1.id == r2.id,
         (Type::Tuple(ts1), Type::Tuple(ts2)) => {
             ts1.len() == ts2.len() && {
                 let mut ts2 = ts2.iter();
                 for (init1, t1) in ts1 {
                     let (init2, t2) = ts2.next().unwrap();
-                    if init1 != init2 || !type_eq(t1, t2) {
+                    if init1 != init2 || !alpha_eq(t1, t2, fresh) {
                         return false;
                     }
                 }
                 return true;
             }
         }
-        (Type::Ptr(t1, r1), Type::Ptr(t2, r2)) => r1 == r2 && type_eq(t1, t2),
+        (Type::Ptr(t1, r1), Type::Ptr(t2, r2)) => r1.id == r2.id && alpha_eq(t1, t2, fresh),
         (Type::Var(id1, repr1), Type::Var(id2, repr2)) => id1 == id2 && repr1 == repr2,
         (Type::Func(ts1), Type::Func(ts2)) => {
-            ts1.iter().zip(ts2.iter()).all(|(t1, t2)| type_eq(&t1, &t2))
+            ts1.len() == ts2.len() && ts1.iter().zip(ts2.iter()).all(|(t1, t2)| alpha_eq(t1, t2, fresh))
         }
-        (Type::Exists(id1, repr1, t1), Type::Exists(id2, repr2, t2)) => {
-            let mut sub = HashMap::new();
-            sub.insert(*id2, Type::Var(*id1, repr1.clone()));
-            let t2_subbed = substitute_t(t2, &sub, &HashMap::new());
-            repr1 == repr2 && type_eq(t1, &t2_subbed)
+        (Type::Exists(id1, repr1, t1), Type::Exists(id2, repr2, t2))
+        | (Type::TaggedExists(id1, repr1, t1), Type::TaggedExists(id2, repr2, t2))
+        | (Type::Forall(id1, repr1, t1), Type::Forall(id2, repr2, t2))
+        | (Type::Rec(id1, repr1, t1), Type::Rec(id2, repr2, t2)) => {
+            let id = fresh_id(fresh);
+            repr1 == repr2 && alpha_eq(&rename_t(t1, *id1, id, *repr1), &rename_t(t2, *id2, id, *repr2), fresh)
+        }
+        (Type::Sum(ts1), Type::Sum(ts2)) => {
+            ts1.len() == ts2.len() && ts1.iter().zip(ts2).all(|(t1, t2)| alpha_eq(t1, t2, fresh))
+        }
+        (
+            Type::ForallRegion(r1, body1, captured_rgns1),
+            Type::ForallRegion(r2, body2, captured_rgns2),
+        ) => {
+            // a non-unique replacement, so the renaming itself doesn't add to any captured regions
+            let r = Region { unique: false, id: RgnId::Var(fresh_id(fresh)) };
+            let sub1 = HashMap::from([(r1.id, r)]);
+            let sub2 = HashMap::from([(r2.id, r)]);
+            let body1_subbed = substitute_t(body1, &HashMap::new(), &sub1);
+            let body2_subbed = substitute_t(body2, &HashMap::new(), &sub2);
+            let captured1: HashSet<RgnId> = captured_rgns1.iter().map(|r| substitute_r(r, &sub1).id).collect();
+            let captured2: HashSet<RgnId> = captured_rgns2.iter().map(|r| substitute_r(r, &sub2).id).collect();
+            // unlike a use of a region, a binder's uniqueness is what the function may do with it
+            r1.unique == r2.unique && captured1 == captured2 && alpha_eq(&body1_subbed, &body2_subbed, fresh)
+        }
+        (Type::Array(t1, r1), Type::Array(t2, r2)) => r1.id == r2.id && alpha_eq(t1, t2, fresh),
+        (_, _) => false,
+    }
+}
+
+/// The steps down into two unequal types to the first place they differ,
+/// each with the two types found there.
+/// Under a quantifier both sides' bound variables are renamed to the same fresh variable, like `type_eq` does,
+/// so renaming one side can't capture a variable that's free in the other.
+pub(crate) fn diff_path(expected: &Type, actual: &Type) -> Vec<(String, Type, Type)> {
+    diff_path_from(expected, actual, &mut 0)
+}
+
+/// `diff_path`, with the counter `alpha_eq` uses too, so the variables the comparisons along the way
+/// rename to are never the ones the path has already renamed to.
+fn diff_path_from(expected: &Type, actual: &Type, fresh: &mut u32) -> Vec<(String, Type, Type)> {
+    let step = |place: String, t1: &Type, t2: &Type, fresh: &mut u32| {
+        let mut path = vec![(place, t1.clone(), t2.clone())];
+        path.extend(diff_path_from(t1, t2, fresh));
+        path
+    };
+    let first_difference = |ts1: &[Type], ts2: &[Type], what: &str, fresh: &mut u32| {
+        match ts1.iter().zip(ts2).enumerate().find(|(_, (t1, t2))| !alpha_eq(t1, t2, fresh)) {
+            Some((i, (t1, t2))) => step(format!("at {} {}", what, i), t1, t2, fresh),
+            None => vec![],
+        }
+    };
+    match (expected, actual) {
+        (Type::Tuple(ts1), Type::Tuple(ts2)) if ts1.len() == ts2.len() => {
+            let Some((i, ((init1, t1), (init2, t2)))) =
+                ts1.iter().zip(ts2).enumerate().find(|(_, ((init1, t1), (init2, t2)))| init1 != init2 || !alpha_eq(t1, t2, fresh))
+            else {
+                return vec![];
+            };
+            let place = format!("at component {} of the tuple", i);
+            if init1 != init2 {
+                let init = |init: bool| if init { "initialized" } else { "uninitialized" };
+                vec![(place + " (" + init(*init1) + " vs " + init(*init2) + ")", t1.clone(), t2.clone())]
+            } else {
+                step(place, t1, t2, fresh)
+            }
+        }
+        (Type::Ptr(t1, r1), Type::Ptr(t2, r2)) | (Type::Array(t1, r1), Type::Array(t2, r2)) if r1.id == r2.id => {
+            step("in the pointee".to_string(), t1, t2, fresh)
+        }
+        (Type::Func(ts1), Type::Func(ts2)) if ts1.len() == ts2.len() => first_difference(ts1, ts2, "parameter", fresh),
+        (Type::Sum(ts1), Type::Sum(ts2)) if ts1.len() == ts2.len() => first_difference(ts1, ts2, "variant", fresh),
+        (Type::Exists(id1, s1, t1), Type::Exists(id2, s2, t2))
+        | (Type::TaggedExists(id1, s1, t1), Type::TaggedExists(id2, s2, t2))
+        | (Type::Forall(id1, s1, t1), Type::Forall(id2, s2, t2))
+        | (Type::Rec(id1, s1, t1), Type::Rec(id2, s2, t2))
+            if s1 == s2 =>
+        {
+            let id = fresh_id(fresh);
+            step("under the quantifier".to_string(), &rename_t(t1, *id1, id, *s1), &rename_t(t2, *id2, id, *s2), fresh)
+        }
+        (Type::ForallRegion(r1, t1, _), Type::ForallRegion(r2, t2, _)) => {
+            let r = Region { unique: false, id: RgnId::Var(fresh_id(fresh)) };
+            let t1 = substitute_t(t1, &HashMap::new(), &HashMap::from([(r1.id, r)]));
+            let t2 = substitute_t(t2, &HashMap::new(), &HashMap::from([(r2.id, r)]));
+            // they might only differ in their captured regions
+            if alpha_eq(&t1, &t2, fresh) {
+                return vec![];
+            }
+            step("under the region quantifier".to_string(), &t1, &t2, fresh)
+        }
+        _ => vec![],
+    }
+}
+
+/// Whether two compile-time stacks hold the same things, comparing types with `type_eq`.
+fn ct_stack_eq(ct1: &[CTStackVal], ct2: &[CTStackVal]) -> bool {
+    ct1.len() == ct2.len()
+        && ct1.iter().zip(ct2).all(|(v1, v2)| match (v1, v2) {
+            (CTStackVal::Type(t1), CTStackVal::Type(t2)) => type_eq(t1, t2),
+            (CTStackVal::Region(r1), CTStackVal::Region(r2)) => r1.id == r2.id,
+            (CTStackVal::Size(s1), CTStackVal::Size(s2)) => s1 == s2,
+            _ => false,
+        })
+}
+
+/// Hash a type up to renaming of its bound variables, so `type_eq(a, b)` implies
+/// `type_hash(a) == type_hash(b)`. Bound variables and regions are hashed by the
+/// distance to their binder rather than by id. Anything `type_eq` ignores (the size
+/// annotation on a bound occurrence, the uniqueness of a region) is left out of the hash too.
+/// Captured regions are also left out, which only means more types share a hash.
+pub fn type_hash(t: &Type) -> u64 {
+    enum Binder {
+        T(Id),
+        R(RgnId),
+    }
+    fn encode_r(r: &Region, binders: &[Binder], out: &mut Vec<u8>) {
+        match binders.iter().rev().position(|b| matches!(b, Binder::R(id) if *id == r.id)) {
+            Some(i) => {
+                out.push(0);
+                out.extend((i as u32).to_le_bytes());
+            }
+            None => {
+                out.push(1);
+                match r.id {
+                    RgnId::Var(Id(l, n)) => {
+                        out.push(0);
+                        out.extend(l.to_le_bytes());
+                        out.extend(n.to_le_bytes());
+                    }
+                    DataSection => out.push(1),
+                    RgnId::Heap => out.push(2),
+                }
+            }
+        }
+    }
+    fn encode(t: &Type, binders: &mut Vec<Binder>, out: &mut Vec<u8>) {
+        match t {
+            Type::I32 => out.push(0),
+            Type::U8 => out.push(1),
+            Type::Handle(r) => {
+                out.push(2);
+                encode_r(r, binders, out);
+            }
+            Type::Tuple(ts) => {
+                out.push(3);
+                out.extend((ts.len() as u32).to_le_bytes());
+                for (init, t) in ts {
+                    out.push(*init as u8);
+                    encode(t, binders, out);
+                }
+            }
+            Type::Ptr(t, r) => {
+                out.push(4);
+                encode(t, binders, out);
+                encode_r(r, binders, out);
+            }
+            Type::Var(id, size) => {
+                match binders.iter().rev().position(|b| matches!(b, Binder::T(id2) if id2 == id)) {
+                    Some(i) => {
+                        out.push(5);
+                        out.extend((i as u32).to_le_bytes());
+                    }
+                    None => {
+                        out.push(6);
+                        out.extend(id.0.to_le_bytes());
+                        out.extend(id.1.to_le_bytes());
+                        out.extend((*size as u64).to_le_bytes());
+                    }
+                }
+            }
+            Type::Func(param_ts) => {
+                out.push(7);
+                out.extend((param_ts.len() as u32).to_le_bytes());
+                for t in param_ts {
+                    encode(t, binders, out);
+                }
+            }
+            Type::Forall(id, size, body)
+            | Type::Exists(id, size, body)
+            | Type::TaggedExists(id, size, body)
+            | Type::Rec(id, size, body) => {
+                out.push(match t {
+                    Type::Forall(..) => 8,
+                    Type::Exists(..) => 9,
+                    Type::TaggedExists(..) => 10,
+                    _ => 13,
+                });
+                out.extend((*size as u64).to_le_bytes());
+                binders.push(Binder::T(*id));
+                encode(body, binders, out);
+                binders.pop();
+            }
+            Type::ForallRegion(r, t, _captured_rgns) => {
+                out.push(11);
+                binders.push(Binder::R(r.id));
+                encode(t, binders, out);
+                binders.pop();
+            }
+            Type::Array(t, r) => {
+                out.push(12);
+                encode(t, binders, out);
+                encode_r(r, binders, out);
+            }
+            Type::I64 => out.push(15),
+            Type::F64 => out.push(16),
+            Type::Sum(ts) => {
+                out.push(14);
+                out.extend((ts.len() as u32).to_le_bytes());
+                for t in ts {
+                    encode(t, binders, out);
+                }
+            }
+        }
+    }
+    let mut bytes = vec![];
+    encode(t, &mut vec![], &mut bytes);
+    // FNV-1a over the (prefix-free) encoding
+    bytes
+        .iter()
+        .fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Check if a value of the first type can be used where the second type is expected.
+/// This is `type_eq`, except that a region-polymorphic function may capture fewer regions than expected:
+/// whoever uses it at the expected type already avoids instantiating it with any of those regions.
+/// Likewise a function whose region binder isn't unique can stand in for one whose binder is.
+/// Function parameters are compared the other way around, as usual.
+/// A function may also take fewer parameters than expected. The ones it takes are the top of the stack,
+/// so the extra arguments just stay below them like any other values the callee doesn't use.
+pub fn type_sub(type1: &Type, type2: &Type) -> bool {
+    match (type1, type2) {
+        (Type::Func(ts1), Type::Func(ts2)) => {
+            ts1.len() <= ts2.len() && ts1.iter().zip(ts2.iter()).all(|(t1, t2)| type_sub(t2, t1))
         }
         (Type::Forall(id1, size1, body1), Type::Forall(id2, size2, body2)) => {
             let mut sub = HashMap::new();
-            sub.insert(*id2, Type::Var(*id1, size1.clone()));
-            let body2_subbed = substitute_t(&body2, &sub, &HashMap::new());
-            size1 == size2 && type_eq(body1, &body2_subbed)
+            sub.insert(*id2, Type::Var(*id1, *size1));
+            let body2_subbed = substitute_t(body2, &sub, &HashMap::new());
+            size1 == size2 && type_sub(body1, &body2_subbed)
         }
         (
-            Type::ForallRegion(r1, body1, _captured_rgns1),
-            Type::ForallRegion(r2, body2, _captured_rgns2),
+            Type::ForallRegion(r1, body1, captured_rgns1),
+            Type::ForallRegion(r2, body2, captured_rgns2),
         ) => {
             let mut sub = HashMap::new();
             sub.insert(r2.id, *r1);
-            let body2_subbed = substitute_t(&body2, &HashMap::new(), &sub);
-            type_eq(body1, &body2_subbed)
+            let body2_subbed = substitute_t(body2, &HashMap::new(), &sub);
+            (!r1.unique || r2.unique)
+                && captured_rgns1.iter().all(|c1| captured_rgns2.iter().any(|c2| c1.id == c2.id))
+                && type_sub(body1, &body2_subbed)
         }
-        (Type::Array(t1, r1), Type::Array(t2, r2)) => r1 == r2 && type_eq(t1, t2),
-        (_, _) => false,
+        (_, _) => type_eq(type1, type2),
     }
 }
 
-fn setup_verifier(t: &Type) -> Result<(Vec<CTStackVal>, Vec<Type>), Error> {
+// Every binder of the function's type is seeded onto the compile-time stack, innermost on top.
+// There are only type and region binders: a function's capability is the set of regions it's
+// quantified over (and their uniqueness), so there's no separate capability kind to seed.
+fn setup_verifier(label: Label, t: &Type) -> Result<(Vec<CTStackVal>, Vec<Type>), Error> {
     match t {
         Type::Forall(id, s, t) => {
-            let (mut ct_stack, param_types) = setup_verifier(t)?;
+            let (mut ct_stack, param_types) = setup_verifier(label, t)?;
             ct_stack.push(CTStackVal::Type(Type::Var(*id, *s)));
             Ok((ct_stack, param_types))
         }
         Type::ForallRegion(r, t, _captured_rgns) => {
-            let (mut ct_stack, param_types) = setup_verifier(t)?;
+            let (mut ct_stack, param_types) = setup_verifier(label, t)?;
             ct_stack.push(CTStackVal::Region(*r));
             Ok((ct_stack, param_types))
         }
         Type::Func(param_ts) => {
             let mut param_ts = param_ts.to_vec();
             param_ts.reverse();
-            Ok((vec![], param_ts.into()))
+            Ok((vec![], param_ts))
+        }
+        t => Err(Error::ForwardDeclNotType(label, t.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+
+    /// Verify a program given as each function's declaration ops and body ops, in label order.
+    fn verify_funcs(funcs: Vec<(Vec<Op1>, Vec<Op1>)>) -> Result<IRProgram, Error> {
+        let forward_decs: Vec<_> =
+            funcs.iter().enumerate().map(|(label, (decl, _))| ForwardDec::Func(label as u32, 0, Visibility::Local, decl.clone())).collect();
+        let stmts = funcs.into_iter().enumerate().map(|(label, (_, body))| Stmt1::Func(label as u32, 0, body));
+        go_default(vec![], &forward_decs, stmts)
+    }
+
+    /// A fresh id counter starting at `next`, with no limit.
+    fn fresh_ids(next: u32) -> FreshIds {
+        FreshIds { next, end: u32::MAX, max: u32::MAX }
+    }
+
+    #[test]
+    fn type_pass_returns_the_fresh_id_counter() {
+        // one quantifier, so one fresh id, however many ops the declaration has or whatever its label
+        let decl = ForwardDec::Func(3, 0, Visibility::Local, vec![Op1::Size(4), Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End]);
+        let (_, _, _, counter) = type_pass(&decl, fresh_ids(10)).unwrap();
+        assert_eq!(counter.next, 11);
+    }
+
+    #[test]
+    fn fresh_id_cap_is_per_function() {
+        // each polymorphic function's type needs one id, for its region variable
+        let poly = vec![Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End];
+        let body = vec![Op1::DropHandle, Op1::U8Lit(0), Op1::Halt];
+        let funcs = [(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (poly.clone(), body.clone()), (poly, body)];
+        let with_max = |max_fresh_ids| {
+            let forward_decs: Vec<_> =
+                (0..).zip(&funcs).map(|(label, (decl, _))| ForwardDec::Func(label, 0, Visibility::Local, decl.clone())).collect();
+            let stmts = (0..).zip(&funcs).map(|(label, (_, body))| Stmt1::Func(label, 0, body.clone()));
+            go(vec![], &forward_decs, stmts, &VerifyOptions { max_fresh_ids, ..VerifyOptions::default() })
+        };
+        assert!(with_max(1).is_ok());
+        assert!(matches!(with_max(0), Err(Error::FreshIdExhausted(1, 0))));
+    }
+
+    #[test]
+    fn only_a_main_program_has_a_main() {
+        let forward_decs = vec![ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::I32, Op1::Func(1)])];
+        let stmts = || vec![Stmt1::Func(0, 0, vec![Op1::I32ToU8, Op1::Halt])];
+        let opts = VerifyOptions::default();
+        assert!(matches!(go(vec![], &forward_decs, stmts(), &opts), Err(Error::TypeErrorMainHasArgs)));
+        assert!(library(vec![], &forward_decs, stmts(), &opts).is_ok());
+    }
+
+    #[test]
+    fn main_is_monomorphic_and_terminated() {
+        let halts = vec![Op1::U8Lit(0), Op1::Halt];
+        let poly_main = vec![Op1::Rgn, Op1::Func(0), Op1::End];
+        assert!(matches!(
+            verify_funcs(vec![(poly_main, halts.clone())]),
+            Err(Error::TypeErrorMainPolymorphic(Type::ForallRegion(..)))
+        ));
+        // main can end in a call instead of a halt
+        let calls = vec![Op1::GlobalFunc(1), Op1::Call];
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], calls), (vec![Op1::Func(0)], halts)]).is_ok());
+        // a body that falls off the end doesn't assemble in the first place
+        let falls_off = crate::asm::go("func 0:\nfunc 0\nlced\nu8_lit 0\n");
+        assert!(matches!(falls_off, Err(Error::SyntaxErrorUnterminatedFunc(0))));
+    }
+
+    #[test]
+    fn zero_sized_values_move_no_bytes() {
+        // an empty tuple beneath an i32, copied to the top
+        let body = vec![Op1::Tuple(0), Op1::Malloc, Op1::Lit(1), Op1::Get(1), Op1::Pop, Op1::I32ToU8, Op1::Halt];
+        let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        let verify_with = |peephole| {
+            let opts = VerifyOptions { peephole, ..VerifyOptions::default() };
+            let program = go(vec![], &forward_decs, vec![Stmt1::Func(0, 0, body.clone())], &opts).unwrap();
+            let Stmt2::Func(_, _, ops) = &program.funcs[0];
+            ops.clone()
+        };
+        assert!(verify_with(false).contains(&Op2::Get(4, 0)));
+        assert!(!verify_with(true).iter().any(|op| matches!(op, Op2::Get(..))));
+    }
+
+    #[test]
+    fn asserts_need_a_taggable_target() {
+        // an i64 packed with a tag, asserted to be a handle of the same size
+        let tagged = [Op1::Size(8), Op1::Some, Op1::CTGet(0), Op1::End, Op1::Tagged, Op1::I64, Op1::LitI64(1), Op1::PackTagged];
+        let main = [&[Op1::NewRgn(64)][..], &tagged, &[Op1::CTGet(0), Op1::Handle, Op1::Assert, Op1::Unreachable]].concat();
+        assert!(matches!(
+            verify_funcs(vec![(vec![Op1::Func(0)], main)]),
+            Err(Error::TypeErrorUntaggable(_, Op1::Assert, Type::Handle(_)))
+        ));
+    }
+
+    #[test]
+    fn bodies_end_in_terminators() {
+        let main = |body| verify_funcs(vec![(vec![Op1::Func(0)], body)]);
+        assert!(main(vec![Op1::U8Lit(0), Op1::Halt]).is_ok());
+        assert!(matches!(main(vec![Op1::U8Lit(0), Op1::Lit(1)]), Err(Error::MissingTerminator(0))));
+    }
+
+    #[test]
+    fn code_after_a_terminator_is_unreachable() {
+        let main = |body| verify_funcs(vec![(vec![Op1::Func(0)], body)]);
+        // unreachable ends a function whatever is on the stack
+        assert!(main(vec![Op1::Lit(5), Op1::Unreachable]).is_ok());
+        assert!(matches!(main(vec![Op1::Unreachable, Op1::U8Lit(0), Op1::Halt]), Err(Error::UnreachableCode(_, Op1::U8Lit(0)))));
+        // compile-time ops after a terminator are flagged too
+        assert!(matches!(main(vec![Op1::Unreachable, Op1::I32]), Err(Error::UnreachableCode(_, Op1::I32))));
+        assert!(matches!(main(vec![Op1::U8Lit(0), Op1::Halt, Op1::Unreachable]), Err(Error::UnreachableCode(_, Op1::Unreachable))));
+        assert!(matches!(
+            main(vec![Op1::GlobalFunc(0), Op1::Call, Op1::U8Lit(0), Op1::Halt]),
+            Err(Error::UnreachableCode(_, Op1::U8Lit(0)))
+        ));
+    }
+
+    #[test]
+    fn nested_unique_handles_are_tracked() {
+        // a unique region's handle, and a tuple holding a copy of it
+        let handle_in_tuple =
+            [Op1::NewRgn(64), Op1::CTGet(0), Op1::Handle, Op1::Tuple(1), Op1::Malloc, Op1::Get(1), Op1::Init(0)];
+        let main = |rest: &[Op1]| {
+            let body = [&handle_in_tuple[..], rest, &[Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        assert!(matches!(main(&[Op1::Pop]), Err(Error::UniqueHandleDropped(_, Op1::Pop, _))));
+        assert!(matches!(
+            main(&[Op1::Lit(0), Op1::Clean(1), Op1::Pop]),
+            Err(Error::UniqueHandleDropped(_, Op1::Clean(1), _))
+        ));
+        assert!(matches!(
+            main(&[Op1::Swap, Op1::FreeRgn]),
+            Err(Error::RegionFreedButStillReferenced(_, Op1::FreeRgn, _))
+        ));
+        // a pointer into a unique region isn't a handle, so it can be dropped
+        let pointer = vec![
+            Op1::NewRgn(64), Op1::Get(0), Op1::CTGet(0), Op1::I32, Op1::Tuple(1), Op1::Ptr, Op1::Malloc,
+            Op1::Pop, Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt,
+        ];
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], pointer)]).is_ok());
+    }
+
+    #[test]
+    fn unique_handles_cant_be_popped() {
+        let main = |ops: &[Op1]| verify_funcs(vec![(vec![Op1::Func(0)], [&[Op1::NewRgn(64)], ops, &[Op1::U8Lit(0), Op1::Halt]].concat())]);
+        assert!(matches!(main(&[Op1::Pop]), Err(Error::UniqueHandleDropped(_, Op1::Pop, _))));
+        // even a copy, since pop can't tell which copy is the last one
+        assert!(matches!(main(&[Op1::Get(0), Op1::Pop, Op1::FreeRgn]), Err(Error::UniqueHandleDropped(_, Op1::Pop, _))));
+        assert!(main(&[Op1::FreeRgn]).is_ok());
+        // a function given a handle to a region it doesn't own can pop it
+        let decl = vec![Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End];
+        let pops = vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt];
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl, pops)]).is_ok());
+    }
+
+    #[test]
+    fn definitions_are_verified_as_they_arrive() {
+        // main passes 5 to function 1, which halts with it
+        let forward_decs = vec![
+            ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)]),
+            ForwardDec::Func(1, 0, Visibility::Local, vec![Op1::I32, Op1::Func(1)]),
+        ];
+        let stmts = vec![
+            Stmt1::Func(0, 0, vec![Op1::Lit(5), Op1::GlobalFunc(1), Op1::Call]),
+            Stmt1::Func(1, 0, vec![Op1::I32ToU8, Op1::Halt]),
+        ];
+        let opts = VerifyOptions::default();
+        let decls = declarations(&forward_decs, &opts).unwrap();
+        let pulled = std::cell::Cell::new(0);
+        let stream = stmts.into_iter().inspect(|_| pulled.set(pulled.get() + 1));
+        let mut verified = definitions(0, stream, &decls, &opts);
+        assert_eq!(pulled.get(), 0);
+        assert!(matches!(verified.next(), Some(Ok(Stmt2::Func(0, ..)))));
+        assert_eq!(pulled.get(), 1);
+        assert!(matches!(verified.next(), Some(Ok(Stmt2::Func(1, ..)))));
+        assert!(verified.next().is_none());
+    }
+
+    #[test]
+    fn captured_regions_cant_be_aliased() {
+        // function 1 is polymorphic over two regions, and main instantiates both with the same unique region
+        let aliased = |inner_binder: Vec<Op1>, instantiate: Vec<Op1>| {
+            let decl = [vec![Op1::Rgn], inner_binder, vec![Op1::Func(0), Op1::End, Op1::End]].concat();
+            let main = [vec![Op1::NewRgn(16), Op1::CTGet(0), Op1::GlobalFunc(1)], instantiate].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::U8Lit(0), Op1::Halt])])
+        };
+        let by_app = vec![Op1::App, Op1::App, Op1::Call];
+        let by_call = vec![Op1::Call];
+        for instantiate in [by_app, by_call] {
+            for inner_binder in [vec![Op1::Rgn], vec![Op1::Unique, Op1::Rgn]] {
+                assert!(matches!(aliased(inner_binder, instantiate.clone()), Err(Error::RegionAccessError(..))));
+            }
+        }
+        // a unique region bound by an app is captured by the type the call instantiates next
+        let decl = |inner_binder: Vec<Op1>| [vec![Op1::Unique, Op1::Rgn], inner_binder, vec![Op1::Func(0), Op1::End, Op1::End]].concat();
+        let main = vec![Op1::NewRgn(16), Op1::CTGet(0), Op1::GlobalFunc(1), Op1::App, Op1::Call];
+        for inner_binder in [vec![Op1::Rgn], vec![Op1::Unique, Op1::Rgn]] {
+            let funcs = vec![(vec![Op1::Func(0)], main.clone()), (decl(inner_binder), vec![Op1::U8Lit(0), Op1::Halt])];
+            assert!(matches!(verify_funcs(funcs), Err(Error::RegionAccessError(_, Op1::Call, _))));
+        }
+    }
+
+    #[test]
+    fn app_and_call_agree_on_uniqueness() {
+        // function 1 has a shared region, and hands it to function 2 with the given binder
+        let pass_shared = |binder: Vec<Op1>, instantiate: Vec<Op1>| {
+            let decl = [binder, vec![Op1::Func(0), Op1::End]].concat();
+            let body = [vec![Op1::CTGet(0), Op1::GlobalFunc(2)], instantiate].concat();
+            verify_funcs(vec![
+                (vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]),
+                (vec![Op1::Rgn, Op1::Func(0), Op1::End], body),
+                (decl, vec![Op1::U8Lit(0), Op1::Halt]),
+            ])
+        };
+        for (op, instantiate) in [(Op1::App, vec![Op1::App, Op1::Call]), (Op1::Call, vec![Op1::Call])] {
+            assert!(pass_shared(vec![Op1::Rgn], instantiate.clone()).is_ok());
+            assert!(matches!(
+                pass_shared(vec![Op1::Unique, Op1::Rgn], instantiate),
+                Err(Error::UniquenessError(_, op2, _)) if op2 == op
+            ));
+        }
+    }
+
+    #[test]
+    fn callers_need_the_regions_they_pass() {
+        let call_with = |region: Op1| {
+            let main = vec![region, Op1::GlobalFunc(1), Op1::Call];
+            let decl = vec![Op1::Rgn, Op1::Func(0), Op1::End];
+            verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::U8Lit(0), Op1::Halt])])
+        };
+        assert!(call_with(Op1::NewRgn(16)).is_ok());
+        // a region variable only names a region for building types; main has no access to it
+        assert!(matches!(call_with(Op1::Rgn), Err(Error::InsufficientCapabilityForCall(..))));
+    }
+
+    #[test]
+    fn sizes_are_bounded() {
+        let poly = |size: usize| {
+            let decl = ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Size(size as u32), Op1::All, Op1::Func(0), Op1::End]);
+            type_pass(&decl, fresh_ids(0))
+        };
+        assert!(poly(MAX_SIZE).is_ok());
+        assert!(matches!(poly(MAX_SIZE + 1), Err(Error::SizeTooLarge(_, _, s, MAX_SIZE)) if s == MAX_SIZE + 1));
+    }
+
+    #[test]
+    fn uninitialized_reads_show_the_tuple() {
+        let main = vec![Op1::U8, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(5), Op1::Init(0), Op1::Proj(1)];
+        let e = verify_funcs(vec![(vec![Op1::Func(0)], main)]).err().unwrap();
+        assert!(matches!(
+            &e,
+            Error::TypeErrorUninitializedRead(_, _, 1, ts) if *ts == vec![(true, Type::I32), (false, Type::U8)]
+        ));
+        assert!(e.to_string().ends_with("component 1 has not been initialized in {i32✓, u8✗}"));
+    }
+
+    #[test]
+    fn captured_regions_dont_depend_on_hashmap_order() {
+        let r = Region { unique: false, id: RgnId::Var(Id(0, 0)) };
+        let poly = Type::ForallRegion(r, Box::new(Type::Func(vec![Type::Handle(r)])), vec![]);
+        let unique = |i| Region { unique: true, id: RgnId::Var(Id(0, i)) };
+        // every HashMap gets its own random iteration order
+        let substituted = || {
+            let rsubs: HashMap<RgnId, Region> = (1..20).map(|i| (RgnId::Var(Id(0, i + 20)), unique(i))).collect();
+            substitute_t(&poly, &HashMap::new(), &rsubs)
+        };
+        assert_eq!(substituted(), Type::ForallRegion(r, Box::new(Type::Func(vec![Type::Handle(r)])), (1..20).map(unique).collect()));
+        for _ in 0..10 {
+            assert_eq!(substituted(), substituted());
+        }
+        // so verifying the same program gives the same ops every time
+        let verified = || {
+            let main = vec![Op1::NewRgn(64), Op1::Get(0), Op1::GlobalFunc(1), Op1::CTGet(0), Op1::App, Op1::Call];
+            let decl = vec![Op1::Unique, Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End];
+            let body = vec![Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt];
+            format!("{:?}", verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, body)]).unwrap().funcs)
+        };
+        let funcs = verified();
+        for _ in 0..10 {
+            assert_eq!(verified(), funcs);
+        }
+    }
+
+    #[test]
+    fn freed_regions_are_inaccessible() {
+        let init_after = |free: Vec<Op1>| {
+            let malloc = vec![Op1::NewRgn(64), Op1::Get(0), Op1::CTGet(0), Op1::I32, Op1::Tuple(1), Op1::Ptr, Op1::Malloc];
+            let main = [malloc, free, vec![Op1::Lit(5), Op1::Init(0), Op1::Pop, Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(init_after(vec![]).is_ok());
+        // the pointer is still on the stack, so the region can't be freed in the first place
+        assert!(matches!(
+            init_after(vec![Op1::Get(1), Op1::FreeRgn]),
+            Err(Error::RegionFreedButStillReferenced(_, Op1::FreeRgn, _))
+        ));
+    }
+
+    #[test]
+    fn every_init_checks_its_region() {
+        // two inits through one pointer, optionally freeing the region between them
+        let second_init_after = |free: Vec<Op1>| {
+            let malloc = vec![Op1::NewRgn(64), Op1::Get(0), Op1::CTGet(0), Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Malloc];
+            let first = vec![Op1::Lit(1), Op1::Init(0)];
+            let second = vec![Op1::Lit(2), Op1::Init(1), Op1::Pop, Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt];
+            verify_funcs(vec![(vec![Op1::Func(0)], [malloc, first, free, second].concat())])
+        };
+        assert!(second_init_after(vec![]).is_ok());
+        // the pointer is still on the stack, so the free is rejected before the second init is checked
+        assert!(matches!(
+            second_init_after(vec![Op1::Get(1), Op1::FreeRgn]),
+            Err(Error::RegionFreedButStillReferenced(_, Op1::FreeRgn, _))
+        ));
+    }
+
+    #[test]
+    fn get_local_stays_in_the_frame() {
+        let get_local = |i| {
+            let body = vec![Op1::Lit(1), Op1::LitI64(2), Op1::GetLocal(i), Op1::Pop, Op1::Pop, Op1::Pop, Op1::U8Lit(0), Op1::Halt];
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        assert!(get_local(1).is_ok());
+        assert!(matches!(get_local(2), Err(Error::TypeErrorGetOutOfRange(_, 2, 2))));
+    }
+
+    #[test]
+    fn get_and_ctget_count_from_the_top() {
+        let halt_on = |i| verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(5), Op1::LitF64(0), Op1::Get(i), Op1::Halt])]);
+        assert!(halt_on(1).is_ok());
+        assert!(matches!(halt_on(0), Err(Error::TypeErrorHaltStatus(_, Op1::Halt, Type::F64))));
+        // the function's top parameter is a copy of the `i`th type from the top
+        let param = |i| {
+            let decl = vec![Op1::U8, Op1::F64, Op1::CTGet(i), Op1::Func(3)];
+            verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl, vec![Op1::Halt])])
+        };
+        assert!(param(1).is_ok());
+        assert!(matches!(param(0), Err(Error::TypeErrorHaltStatus(_, Op1::Halt, Type::F64))));
+    }
+
+    #[test]
+    fn select_needs_matching_values() {
+        let select = |operands: Vec<Op1>| {
+            let main = [operands, vec![Op1::Select, Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(select(vec![Op1::Lit(1), Op1::Lit(2), Op1::Lit(0)]).is_ok());
+        assert!(matches!(
+            select(vec![Op1::U8Lit(1), Op1::Lit(2), Op1::Lit(0)]),
+            Err(Error::TypeError(_, Op1::Select, Type::I32, Type::U8))
+        ));
+        assert!(matches!(
+            select(vec![Op1::Lit(1), Op1::Lit(2), Op1::U8Lit(0)]),
+            Err(Error::TypeError(_, Op1::Select, Type::I32, Type::U8))
+        ));
+        assert!(matches!(select(vec![Op1::Lit(2), Op1::Lit(0)]), Err(Error::TypeErrorEmptyStack(_, Op1::Select))));
+    }
+
+    #[test]
+    fn select_between_tuples() {
+        let select = |below: Vec<Op1>, above: Vec<Op1>| {
+            let main = [below, above, vec![Op1::Lit(0), Op1::Select, Op1::Proj(0), Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let ints = vec![Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::Lit(2), Op1::Init(1)];
+        assert!(select(ints.clone(), ints.clone()).is_ok());
+        let mixed = vec![Op1::U8, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::U8Lit(2), Op1::Init(1)];
+        assert!(matches!(
+            select(ints.clone(), mixed),
+            Err(Error::TypeError(_, Op1::Select, Type::Tuple(_), Type::Tuple(_)))
+        ));
+        // a component left uninitialized makes it a different type too
+        let half = vec![Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(1), Op1::Init(0)];
+        assert!(matches!(select(ints, half), Err(Error::TypeError(_, Op1::Select, Type::Tuple(_), Type::Tuple(_)))));
+    }
+
+    #[test]
+    fn select_keeps_region_binders_unique() {
+        // function 1 needs its region to be unique, and function 2 doesn't
+        let takes_handle = |binder: &[Op1]| [binder, &[Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End]].concat();
+        let main = vec![Op1::GlobalFunc(1), Op1::GlobalFunc(2), Op1::Lit(1), Op1::Select, Op1::Pop, Op1::U8Lit(0), Op1::Halt];
+        let res = verify_funcs(vec![
+            (vec![Op1::Func(0)], main),
+            (takes_handle(&[Op1::Unique]), vec![Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt]),
+            (takes_handle(&[]), vec![Op1::DropHandle, Op1::U8Lit(0), Op1::Halt]),
+        ]);
+        // otherwise the result would have function 2's type, and function 1 could be handed a shared region
+        assert!(matches!(res, Err(Error::TypeError(_, Op1::Select, Type::ForallRegion(..), Type::ForallRegion(..)))));
+    }
+
+    #[test]
+    fn stack_depth_counts_values() {
+        // the depth is the size of an existential's type variable, which has to fit an i32
+        let pack_under = |n| {
+            let exists = vec![Op1::StackDepth, Op1::Some, Op1::CTGet(0), Op1::End, Op1::I32, Op1::Lit(42), Op1::Pack];
+            let main = [vec![Op1::U8Lit(0); n], exists, vec![Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(pack_under(4).is_ok());
+        assert!(matches!(pack_under(3), Err(Error::SizeError(_, Op1::Pack, 3, 4))), "{:?}", pack_under(3).err());
+    }
+
+    #[test]
+    fn calls_take_the_outermost_instantiation_on_top() {
+        // forall a: 4. forall b: 1. (b, a) -> 0, with b the top parameter
+        let decl = vec![
+            Op1::Size(4), Op1::All, Op1::Size(1), Op1::All,
+            Op1::CTGet(1), Op1::CTGet(1), Op1::Func(2), Op1::End, Op1::End,
+        ];
+        let call = |instantiate: Vec<Op1>| {
+            let main = [vec![Op1::Lit(1), Op1::U8Lit(2)], instantiate].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main), (decl.clone(), vec![Op1::U8Lit(0), Op1::Halt])])
+        };
+        assert!(call(vec![Op1::U8, Op1::I32, Op1::GlobalFunc(1), Op1::Call]).is_ok());
+        assert!(call(vec![Op1::GlobalFunc(1), Op1::I32, Op1::App, Op1::U8, Op1::App, Op1::Call]).is_ok());
+        assert!(matches!(
+            call(vec![Op1::I32, Op1::U8, Op1::GlobalFunc(1), Op1::Call]),
+            Err(Error::SizeError(_, Op1::Call, 4, 1))
+        ));
+    }
+
+    #[test]
+    fn calls_take_region_arguments_in_order_too() {
+        // forall r. forall a: 4. forall b: 1. (handle r, a, b) -> 0, with b the top parameter
+        let decl = vec![
+            Op1::Rgn, Op1::Size(4), Op1::All, Op1::Size(1), Op1::All,
+            Op1::CTGet(2), Op1::Handle, Op1::CTGet(2), Op1::CTGet(2), Op1::Func(3), Op1::End, Op1::End, Op1::End,
+        ];
+        // the region main creates is at the bottom of its compile-time stack
+        let call = |instantiate: Vec<Op1>| {
+            let main = [vec![Op1::NewRgn(64), Op1::Lit(1), Op1::U8Lit(2)], instantiate].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main), (decl.clone(), vec![Op1::U8Lit(0), Op1::Halt])])
+        };
+        assert!(call(vec![Op1::U8, Op1::I32, Op1::CTGet(2), Op1::GlobalFunc(1), Op1::Call]).is_ok());
+        let apps = vec![Op1::GlobalFunc(1), Op1::CTGet(0), Op1::App, Op1::I32, Op1::App, Op1::U8, Op1::App, Op1::Call];
+        assert!(call(apps).is_ok());
+        // the region where a type belongs, and a type where the region belongs
+        assert!(matches!(
+            call(vec![Op1::U8, Op1::CTGet(1), Op1::I32, Op1::GlobalFunc(1), Op1::Call]),
+            Err(Error::KindError(_, Op1::Call, Kind::Region, CTStackVal::Type(Type::I32)))
+        ));
+    }
+
+    #[test]
+    fn explicit_apps_verify_like_an_implicit_call() {
+        // forall r. forall a: 4. (a) -> 0
+        let decl = vec![Op1::Rgn, Op1::Size(4), Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End, Op1::End];
+        let call = |free: Vec<Op1>, instantiate: Vec<Op1>| {
+            let main = [vec![Op1::NewRgn(64)], free, vec![Op1::Lit(1)], instantiate].concat();
+            let res = verify_funcs(vec![(vec![Op1::Func(0)], main), (decl.clone(), vec![Op1::U8Lit(0), Op1::Halt])]);
+            res.map(|program| {
+                let Stmt2::Func(_, _, ops) = &program.funcs[0];
+                ops.clone()
+            })
+        };
+        let implicit = vec![Op1::I32, Op1::CTGet(1), Op1::GlobalFunc(1), Op1::Call];
+        let explicit = vec![Op1::GlobalFunc(1), Op1::CTGet(0), Op1::App, Op1::I32, Op1::App, Op1::Call];
+        // the apps don't leave anything behind at runtime
+        assert_eq!(call(vec![], implicit.clone()).unwrap(), call(vec![], explicit.clone()).unwrap());
+        // and with the region freed first, both are rejected for the same reason
+        assert!(matches!(
+            call(vec![Op1::FreeRgn], implicit),
+            Err(Error::InsufficientCapabilityForCall(_, Op1::Call, _))
+        ));
+        assert!(matches!(
+            call(vec![Op1::FreeRgn], explicit),
+            Err(Error::InsufficientCapabilityForCall(_, Op1::App, _))
+        ));
+    }
+
+    #[test]
+    fn only_shared_handles_can_be_dropped() {
+        let drop_in = |binder: Vec<Op1>| {
+            let decl = [binder, vec![Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl, vec![Op1::DropHandle, Op1::U8Lit(0), Op1::Halt])])
+        };
+        assert!(drop_in(vec![Op1::Rgn]).is_ok());
+        assert!(matches!(drop_in(vec![Op1::Unique, Op1::Rgn]), Err(Error::UniqueHandleDropped(_, Op1::DropHandle, _))));
+    }
+
+    #[test]
+    fn unpacked_packages_hide_different_types() {
+        let package = |n| vec![Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::I32, Op1::Lit(n), Op1::Pack, Op1::Unpack];
+        let select_from = |values: Vec<Op1>| {
+            let main = [values, vec![Op1::Lit(1), Op1::Select, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(select_from([package(1), vec![Op1::Get(0)]].concat()).is_ok());
+        assert!(matches!(
+            select_from([package(1), package(2)].concat()),
+            Err(Error::TypeError(_, Op1::Select, Type::Var(..), Type::Var(..)))
+        ));
+    }
+
+    #[test]
+    fn halt_takes_an_integer_or_zero_size_status() {
+        let halt_on = |status: &[Op1]| {
+            let program = verify_funcs(vec![(vec![Op1::Func(0)], [status, &[Op1::Halt]].concat())])?;
+            let Stmt2::Func(_, _, ops) = &program.funcs[0];
+            Ok(ops.clone())
+        };
+        // the status's type decides how it becomes a byte
+        assert!(matches!(&halt_on(&[Op1::U8Lit(0)]).unwrap()[..], [Op2::U8Lit(0), Op2::Halt]));
+        assert!(matches!(&halt_on(&[Op1::Lit(0)]).unwrap()[..], [Op2::Lit(0), Op2::I32ToU8, Op2::Halt]));
+        assert!(matches!(&halt_on(&[Op1::LitI64(0)]).unwrap()[..], [_, Op2::I64ToI32, Op2::I32ToU8, Op2::Halt]));
+        // a unit has nothing to exit with, so the verifier adds the exit code
+        assert!(matches!(&halt_on(&[Op1::Tuple(0), Op1::Malloc]).unwrap()[..], [.., Op2::U8Lit(0), Op2::Halt]));
+        let Err(e) = halt_on(&[Op1::LitF64(0)]) else { panic!("halting on an f64 verified") };
+        assert!(matches!(e, Error::TypeErrorHaltStatus(1, Op1::Halt, Type::F64)));
+        assert!(e.to_string().contains("halt at pos 1 expects an integer or a zero-size exit status but the top of the stack is f64"));
+    }
+
+    #[test]
+    fn mem_copy_needs_matching_initialized_tuples() {
+        let pair = |get_handle: Op1, ts: Vec<Op1>, inits: Vec<Op1>| {
+            [vec![get_handle, Op1::CTGet(0)], ts, vec![Op1::Tuple(2), Op1::Ptr, Op1::Malloc], inits].concat()
+        };
+        let copy = |dest: Vec<Op1>, src: Vec<Op1>| {
+            let main = [vec![Op1::NewRgn(64)], dest, src, vec![Op1::MemCopy, Op1::Proj(0), Op1::I32ToU8, Op1::Swap, Op1::FreeRgn, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let i32s = || vec![Op1::I32, Op1::I32];
+        let full = pair(Op1::Get(1), i32s(), vec![Op1::Lit(1), Op1::Init(0), Op1::Lit(2), Op1::Init(1)]);
+        assert!(copy(pair(Op1::Get(0), i32s(), vec![]), full.clone()).is_ok());
+        assert!(matches!(
+            copy(pair(Op1::Get(0), i32s(), vec![]), pair(Op1::Get(1), i32s(), vec![Op1::Lit(1), Op1::Init(0)])),
+            Err(Error::TypeErrorUninitializedRead(_, Op1::MemCopy, 1, _))
+        ));
+        assert!(matches!(
+            copy(pair(Op1::Get(0), vec![Op1::U8, Op1::I32], vec![]), full.clone()),
+            Err(Error::MemCopyTypeMismatch(..))
+        ));
+        let not_a_pointer = [vec![Op1::Lit(1)], full].concat();
+        assert!(matches!(
+            verify_funcs(vec![(vec![Op1::Func(0)], [vec![Op1::NewRgn(64)], not_a_pointer, vec![Op1::MemCopy, Op1::Halt]].concat())]),
+            Err(Error::TypeErrorPtrExpected(_, Op1::MemCopy, Type::I32))
+        ));
+    }
+
+    #[test]
+    fn both_passes_build_the_same_types() {
+        // function 1 takes a value of the type the prefix builds in its declaration,
+        // and main packs function 1 with the type the same prefix builds in a body as the witness,
+        // which only verifies if the two function types are the same
+        let check = |prefix: Vec<Op1>| {
+            let decl = [prefix.clone(), vec![Op1::Func(1)]].concat();
+            // exists a: size(t). a, then t again as the witness
+            let exists = [decl.clone(), vec![Op1::SizeOf, Op1::Some, Op1::CTGet(0), Op1::End]].concat();
+            let pack = vec![Op1::GlobalFunc(1), Op1::Pack, Op1::Pop, Op1::U8Lit(0), Op1::Halt];
+            let main = [exists, decl.clone(), pack].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt])])
+        };
+        let prefixes = [
+            vec![Op1::I32, Op1::U8, Op1::Tuple(2)],
+            vec![Op1::I32, Op1::I64, Op1::Sum(2)],
+            vec![Op1::Size(4), Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End],
+            vec![Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::CTGet(0), Op1::Tuple(2), Op1::End],
+            vec![Op1::Size(4), Op1::Size(4), Op1::SizeAdd, Op1::Rec, Op1::I64, Op1::End],
+            vec![Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End],
+        ];
+        for prefix in prefixes {
+            assert!(check(prefix.clone()).is_ok(), "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn end_closes_quantifiers_in_bodies() {
+        // function 1 takes `exists a: 4. a`, which main builds with its own some...end
+        let exists = vec![Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End];
+        let main = [exists.clone(), vec![Op1::I32, Op1::Lit(7), Op1::Pack, Op1::GlobalFunc(1), Op1::Call]].concat();
+        let decl = [exists, vec![Op1::Func(1)]].concat();
+        let program = verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::U8Lit(0), Op1::Halt])]);
+        let Ok(program) = program else { panic!("packing an i32 as `exists a: 4. a` didn't verify") };
+        let Stmt2::Func(1, Type::Func(param_ts), _) = &program.funcs[1] else { panic!("function 1 is missing") };
+        assert!(matches!(param_ts[..], [Type::Exists(_, 4, _)]));
+    }
+
+    #[test]
+    fn sizeof_a_tuple_bounds_quantifiers() {
+        // (i32, i64), and a value of it on the stack
+        let pair_t = || vec![Op1::I64, Op1::I32, Op1::Tuple(2)];
+        let pair = [pair_t(), vec![Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::LitI64(2), Op1::Init(1)]].concat();
+        // exists a: sizeof((i32, i64)). a, packing the given witness
+        let pack = |size: Vec<Op1>, value: Vec<Op1>, witness: Vec<Op1>| {
+            let exists = [size, vec![Op1::Some, Op1::CTGet(0), Op1::End]].concat();
+            let main = [exists, value, witness, vec![Op1::Pack, Op1::Pop, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let sizeof_pair = || [pair_t(), vec![Op1::SizeOf]].concat();
+        assert!(pack(sizeof_pair(), pair.clone(), pair_t()).is_ok());
+        assert!(matches!(pack(sizeof_pair(), vec![Op1::Lit(7)], vec![Op1::I32]), Err(Error::SizeError(_, Op1::Pack, 12, 4))));
+        assert!(matches!(
+            pack(vec![Op1::Size(4), Op1::SizeOf], vec![Op1::Lit(7)], vec![Op1::I32]),
+            Err(Error::KindError(_, Op1::SizeOf, Kind::Type, CTStackVal::Size(4)))
+        ));
+        assert!(matches!(
+            pack(vec![Op1::Heap, Op1::SizeOf], vec![Op1::Lit(7)], vec![Op1::I32]),
+            Err(Error::KindError(_, Op1::SizeOf, Kind::Type, CTStackVal::Region(_)))
+        ));
+        // forall a: sizeof((i32, i64)). (a) -> 0, called with a pair
+        let decl = [sizeof_pair(), vec![Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End]].concat();
+        let main = [pair, pair_t(), vec![Op1::GlobalFunc(1), Op1::Call]].concat();
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt])]).is_ok());
+    }
+
+    #[test]
+    fn size_arithmetic_bounds_existentials() {
+        let pack_at = |size: Vec<Op1>, value: Vec<Op1>| {
+            let main = [size, vec![Op1::Some, Op1::CTGet(0), Op1::End], value, vec![Op1::Pack, Op1::Pop, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let i64_value = || vec![Op1::I64, Op1::LitI64(7)];
+        // exists a: 4 + 4. a
+        assert!(pack_at(vec![Op1::Size(4), Op1::Size(4), Op1::SizeAdd], i64_value()).is_ok());
+        // exists a: 4 * 2. a
+        assert!(pack_at(vec![Op1::Size(4), Op1::SizeMul(2)], i64_value()).is_ok());
+        assert!(pack_at(vec![Op1::I32, Op1::SizeOf, Op1::Size(4), Op1::SizeAdd], i64_value()).is_ok());
+        assert!(matches!(
+            pack_at(vec![Op1::Size(4), Op1::Size(4), Op1::SizeAdd], vec![Op1::I32, Op1::Lit(7)]),
+            Err(Error::SizeError(_, Op1::Pack, 8, 4))
+        ));
+        assert!(matches!(
+            pack_at(vec![Op1::Size(4), Op1::I32, Op1::SizeAdd], i64_value()),
+            Err(Error::KindError(_, Op1::SizeAdd, Kind::Size, CTStackVal::Type(Type::I32)))
+        ));
+        assert!(matches!(
+            pack_at(vec![Op1::Size(4), Op1::SizeAdd], i64_value()),
+            Err(Error::TypeErrorEmptyCTStack(_, Op1::SizeAdd))
+        ));
+        assert!(matches!(
+            pack_at(vec![Op1::I32, Op1::SizeMul(2)], i64_value()),
+            Err(Error::KindError(_, Op1::SizeMul(2), Kind::Size, CTStackVal::Type(Type::I32)))
+        ));
+    }
+
+    #[test]
+    fn ctget_out_of_range() {
+        let mut compile_time_stack = vec![CTStackVal::Type(Type::I32), CTStackVal::Type(Type::U8)];
+        assert!(matches!(
+            handle_ctget(3, &5, &mut compile_time_stack),
+            Err(Error::TypeErrorCTGetOutOfRange(3, 5, 2))
+        ));
+        assert!(matches!(handle_ctget(3, &0, &mut vec![]), Err(Error::TypeErrorEmptyCTStack(3, Op1::CTGet(0)))));
+        assert!(handle_ctget(3, &1, &mut compile_time_stack).is_ok());
+        assert!(matches!(compile_time_stack[..], [_, _, CTStackVal::Type(Type::I32)]));
+    }
+
+    #[test]
+    fn declaration_errors_name_the_function() {
+        let declare = |decl| verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl, vec![Op1::U8Lit(0), Op1::Halt])]);
+        assert!(matches!(declare(vec![Op1::Lit(1), Op1::Func(0)]), Err(Error::ForwardDeclRuntimeOp(1, Op1::Lit(1)))));
+        assert!(matches!(declare(vec![Op1::Func(0), Op1::I32]), Err(Error::ForwardDeclBadStack(1, _))));
+        // ops that end a declaration are an error in a body, not a panic
+        for op in [Op1::Lced, Op1::Export(1, 2), Op1::Import(1, 2)] {
+            let body = verify_funcs(vec![(vec![Op1::Func(0)], vec![op, Op1::U8Lit(0), Op1::Halt])]);
+            assert!(matches!(body, Err(Error::DeclarationOpInBody(0, op2)) if op2 == op));
+        }
+    }
+
+    #[test]
+    fn forall_regions_compare_captured_regions() {
+        let r = |unique, n| Region { unique, id: RgnId::Var(Id(0, n)) };
+        let forall = |binder, captured| Type::ForallRegion(binder, Box::new(Type::Handle(binder)), captured);
+        // renaming the binder renames it in the captured regions too
+        assert!(type_eq(&forall(r(true, 1), vec![r(true, 1), r(true, 3)]), &forall(r(true, 2), vec![r(true, 3), r(true, 2)])));
+        assert!(!type_eq(&forall(r(true, 1), vec![r(true, 3)]), &forall(r(true, 1), vec![])));
+        assert!(!type_eq(&forall(r(true, 1), vec![r(true, 3)]), &forall(r(true, 1), vec![r(true, 4)])));
+    }
+
+    #[test]
+    fn arithmetic_needs_matching_numbers() {
+        let arith = |operands: Vec<Op1>, op: Op1| {
+            let body = [operands, vec![op, Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        assert!(arith(vec![Op1::Lit(6), Op1::Lit(3)], Op1::Div).is_ok());
+        assert!(arith(vec![Op1::Lit(6), Op1::Lit(3)], Op1::Sub).is_ok());
+        assert!(matches!(
+            arith(vec![Op1::Lit(6), Op1::I32, Op1::Tuple(1), Op1::Malloc], Op1::Add),
+            Err(Error::TypeError(_, Op1::Add, Type::I32, Type::Tuple(_)))
+        ));
+        assert!(matches!(
+            arith(vec![Op1::U8Lit(6), Op1::Lit(3)], Op1::Mul),
+            Err(Error::TypeError(_, Op1::Mul, Type::I32, Type::U8))
+        ));
+        assert!(matches!(arith(vec![Op1::Lit(3)], Op1::Div), Err(Error::TypeErrorEmptyStack(_, Op1::Div))));
+    }
+
+    #[test]
+    fn unknown_global_funcs() {
+        let res = verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::GlobalFunc(3), Op1::Call])]);
+        assert!(matches!(res, Err(Error::UnknownGlobalFunc(0, Op1::GlobalFunc(3), 3))));
+    }
+
+    #[test]
+    fn declarations_must_be_function_types() {
+        let main = (vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]);
+        let Err(e) = verify_funcs(vec![main, (vec![Op1::I32], vec![Op1::U8Lit(0), Op1::Halt])]) else {
+            panic!("an i32 declaration verified")
+        };
+        assert!(matches!(e, Error::ForwardDeclNotType(1, Type::I32)));
+        assert!(e.to_string().contains("Function 1 is declared with type"), "{}", e);
+    }
+
+    #[test]
+    fn fold_and_unfold_need_recursive_types() {
+        let main = |ops: Vec<Op1>| {
+            let body = [vec![Op1::Lit(3)], ops, vec![Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        // rec a: 4. i32
+        let rec_i32 = || vec![Op1::Size(4), Op1::Rec, Op1::I32, Op1::End];
+        assert!(main([rec_i32(), vec![Op1::Fold, Op1::Unfold]].concat()).is_ok());
+        assert!(matches!(main(vec![Op1::I32, Op1::Fold]), Err(Error::TypeErrorRecExpected(_, Op1::Fold, Type::I32))));
+        assert!(matches!(main(vec![Op1::Unfold]), Err(Error::TypeErrorRecExpected(_, Op1::Unfold, Type::I32))));
+        // a u8 isn't the unfolding of rec a: 4. i32
+        let u8_fold = [vec![Op1::I32ToU8], rec_i32(), vec![Op1::Fold, Op1::Unfold, Op1::U8ToI32]].concat();
+        assert!(matches!(main(u8_fold), Err(Error::TypeError(_, Op1::Fold, Type::I32, Type::U8))));
+        // the body has to be the size of the variable
+        let too_big = vec![Op1::Size(4), Op1::Rec, Op1::I32, Op1::I32, Op1::Tuple(2), Op1::End];
+        assert!(matches!(main(too_big), Err(Error::SizeError(_, Op1::End, 4, 8))));
+    }
+
+    #[test]
+    fn recursive_types_unfold_one_level_at_a_time() {
+        // forall r. (rec a: 16. ptr (i32, a) @ r) -> 0, which reads the i32 of the node after the one it's given
+        let decl = vec![
+            Op1::Rgn, Op1::Size(16), Op1::Rec, Op1::CTGet(1), Op1::CTGet(1), Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::End,
+            Op1::Func(1), Op1::End,
+        ];
+        let walk = |ops: Vec<Op1>| {
+            let body = [ops, vec![Op1::Proj(0), Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl.clone(), body)])
+        };
+        assert!(walk(vec![Op1::Unfold, Op1::Proj(1), Op1::Unfold]).is_ok());
+        // the next node is folded again, so it has to be unfolded before it can be read
+        assert!(matches!(walk(vec![Op1::Unfold, Op1::Proj(1)]), Err(Error::TypeErrorTupleExpected(_, Op1::Proj(0), Type::Rec(..)))));
+        assert!(matches!(walk(vec![]), Err(Error::TypeErrorTupleExpected(_, Op1::Proj(0), Type::Rec(..)))));
+        // unfolding the node's pointer instead of the node
+        assert!(matches!(walk(vec![Op1::Unfold, Op1::Unfold]), Err(Error::TypeErrorRecExpected(_, Op1::Unfold, Type::Ptr(..)))));
+    }
+
+    /// Main passes 5 to function 1, which halts with it.
+    const TWO_FUNCS: &str = "func 0:\nfunc 0\nlced\nlit 5\nglobal_func 1\ncall\n\
+                             func 1:\ni32\nfunc 1\nlced\ni32_to_u8\nhalt\n";
+
+    #[test]
+    fn functions_verify_one_at_a_time() {
+        let (_, forward_decs, stmts, _) = crate::asm::go(TWO_FUNCS).unwrap();
+        let opts = VerifyOptions::default();
+        let decls = declarations(&forward_decs, &opts).unwrap();
+        let whole = go_default(vec![], &forward_decs, stmts.clone()).unwrap();
+        // each function verifies the same on its own as in the whole program
+        for (stmt, verified) in stmts.iter().zip(&whole.funcs) {
+            assert_eq!(&verify_function(0, stmt, &decls, &opts).unwrap(), verified);
+        }
+        // an edited body is checked against the declarations that are already there
+        let Stmt1::Func(label, pos, _) = &stmts[1];
+        let edited = Stmt1::Func(*label, *pos, vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt]);
+        assert!(verify_function(0, &edited, &decls, &opts).is_ok());
+        let broken = Stmt1::Func(*label, *pos, vec![Op1::Pop, Op1::Halt]);
+        assert!(matches!(verify_function(0, &broken, &decls, &opts), Err(Error::TypeErrorEmptyStack(_, Op1::Halt))));
+        let calls_undeclared = Stmt1::Func(*label, *pos, vec![Op1::GlobalFunc(2), Op1::Call]);
+        assert!(matches!(
+            verify_function(0, &calls_undeclared, &decls, &opts),
+            Err(Error::UnknownGlobalFunc(_, Op1::GlobalFunc(2), 2))
+        ));
+        // a definition needs a declaration to be checked against
+        let undeclared = Stmt1::Func(2, *pos, vec![Op1::U8Lit(0), Op1::Halt]);
+        assert!(matches!(verify_function(0, &undeclared, &decls, &opts), Err(Error::UndeclaredFunction(2))));
+    }
+
+    #[test]
+    fn case_branches_take_the_same_compile_time_arguments() {
+        let case = |first: u32, second: u32| {
+            let main = vec![
+                Op1::I32,
+                Op1::Lit(5),
+                Op1::I32,
+                Op1::I32,
+                Op1::Sum(2),
+                Op1::Inj(0),
+                Op1::GlobalFunc(first),
+                Op1::GlobalFunc(second),
+                Op1::Case(2),
+            ];
+            let takes_i32 = (vec![Op1::I32, Op1::Func(1)], vec![Op1::I32ToU8, Op1::Halt]);
+            let takes_any = (
+                vec![Op1::Size(4), Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End],
+                vec![Op1::U8Lit(0), Op1::Halt],
+            );
+            verify_funcs(vec![(vec![Op1::Func(0)], main), takes_i32, takes_any])
+        };
+        assert!(case(2, 2).is_ok());
+        // function 2 takes the i32 from the compile-time stack, but function 1 leaves it there
+        assert!(matches!(case(2, 1), Err(Error::TypeErrorCaseBranchesDiffer(_, 1))));
+    }
+
+    #[test]
+    fn sums_are_checked() {
+        let main = |ops: Vec<Op1>| {
+            let takes_i32 = (vec![Op1::I32, Op1::Func(1)], vec![Op1::I32ToU8, Op1::Halt]);
+            verify_funcs(vec![(vec![Op1::Func(0)], ops), takes_i32])
+        };
+        let case = |n| [vec![Op1::GlobalFunc(1); n], vec![Op1::Case(n as u8)]].concat();
+        let inj = |value: Op1, i| vec![value, Op1::I32, Op1::I32, Op1::Sum(2), Op1::Inj(i)];
+        assert!(main([inj(Op1::Lit(5), 0), case(2)].concat()).is_ok());
+        assert!(matches!(main([inj(Op1::U8Lit(5), 0), case(2)].concat()), Err(Error::TypeError(_, Op1::Inj(0), Type::I32, Type::U8))));
+        assert!(matches!(main([inj(Op1::Lit(5), 2), case(2)].concat()), Err(Error::TypeErrorInjOutOfRange(_, 2, 2))));
+        assert!(matches!(
+            main(vec![Op1::Lit(5), Op1::I32, Op1::Inj(0), Op1::U8Lit(0), Op1::Halt]),
+            Err(Error::TypeErrorSumExpected(_, Op1::Inj(0), Type::I32))
+        ));
+        assert!(matches!(main([vec![Op1::Lit(5)], case(2)].concat()), Err(Error::TypeErrorSumExpected(_, Op1::Case(2), Type::I32))));
+        assert!(matches!(main([inj(Op1::Lit(5), 0), case(3)].concat()), Err(Error::TypeErrorCaseArity(_, 3, 2))));
+    }
+
+    #[test]
+    fn values_fit_in_a_stack_chunk() {
+        // a tuple of 255 i32s, `copies` times over, and then `extra` more i32s
+        let big = |copies: usize, extra: usize| {
+            let mut ops = vec![Op1::I32; 255];
+            ops.push(Op1::Tuple(255));
+            ops.extend(vec![Op1::CTGet(0); copies - 1]);
+            ops.extend(vec![Op1::I32; extra]);
+            ops.push(Op1::Tuple((copies + extra) as u8));
+            ops
+        };
+        let inj = |big: Vec<Op1>| {
+            let main = [vec![Op1::Lit(1), Op1::I32], big, vec![Op1::Sum(2), Op1::Inj(1), Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        // the sum is 8 bytes bigger than its biggest variant
+        assert!(inj(big(4, MAX_STACK_VALUE_SIZE / 4 - 4 * 255 - 2)).is_ok());
+        let res = inj(big(4, MAX_STACK_VALUE_SIZE / 4 - 4 * 255 - 1));
+        assert!(matches!(res, Err(Error::TooBigForStack(_, Op1::Inj(1), Type::Sum(_)))), "{:?}", res.err());
+        // the tuple can be an array element in a region, but it can't be loaded onto the stack
+        let arr_proj = [
+            vec![Op1::NewRgn(8192), Op1::Get(0), Op1::Lit(1), Op1::CTGet(0)],
+            big(5, 0),
+            vec![Op1::Arr, Op1::Malloc, Op1::Lit(0), Op1::ArrProj, Op1::Halt],
+        ];
+        let res = verify_funcs(vec![(vec![Op1::Func(0)], arr_proj.concat())]);
+        assert!(matches!(res, Err(Error::TooBigForStack(_, Op1::ArrProj, _))), "{:?}", res.err());
+    }
+
+    #[test]
+    fn unique_applies_to_the_next_region_only() {
+        let decl = ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Unique, Op1::Rgn, Op1::Rgn, Op1::Func(0), Op1::End, Op1::End]);
+        let (_, _, t, _) = type_pass(&decl, fresh_ids(0)).unwrap();
+        let Type::ForallRegion(outer, body, _) = t else { panic!("{:?}", t) };
+        let Type::ForallRegion(inner, _, _) = *body else { panic!("{:?}", body) };
+        assert!(outer.unique);
+        assert!(!inner.unique);
+    }
+
+    #[test]
+    fn wide_numbers_only_mix_with_themselves() {
+        let f64 = |x: f64| Op1::LitF64(x.to_bits());
+        let arith = |operands: Vec<Op1>, op: Op1, to_i32: Vec<Op1>| {
+            let body = [operands, vec![op], to_i32, vec![Op1::I32ToU8, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        assert!(arith(vec![Op1::LitI64(6), Op1::LitI64(3)], Op1::Modulo, vec![Op1::I64ToI32]).is_ok());
+        assert!(arith(vec![f64(6.5), f64(0.5)], Op1::Div, vec![Op1::F64ToI64, Op1::I64ToI32]).is_ok());
+        assert!(matches!(
+            arith(vec![Op1::Lit(6), Op1::LitI64(3)], Op1::Add, vec![Op1::I64ToI32]),
+            Err(Error::TypeError(_, Op1::Add, Type::I64, Type::I32))
+        ));
+        assert!(matches!(
+            arith(vec![Op1::LitI64(6), f64(3.0)], Op1::Sub, vec![Op1::F64ToI64, Op1::I64ToI32]),
+            Err(Error::TypeError(_, Op1::Sub, Type::F64, Type::I64))
+        ));
+        // there's no floating point remainder
+        assert!(matches!(
+            arith(vec![f64(6.0), f64(3.0)], Op1::Modulo, vec![Op1::F64ToI64, Op1::I64ToI32]),
+            Err(Error::TypeError(_, Op1::Modulo, Type::I32, Type::F64))
+        ));
+        assert_eq!(Type::I64.size(), 8);
+        assert_eq!(Type::F64.size(), 8);
+    }
+
+    #[test]
+    fn dup_and_swap_need_values() {
+        let run = |body: Vec<Op1>| {
+            let body = [body, vec![Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], body)])
+        };
+        assert!(matches!(run(vec![Op1::Dup]), Err(Error::TypeErrorEmptyStack(_, Op1::Dup))));
+        assert!(matches!(run(vec![Op1::Swap]), Err(Error::TypeErrorEmptyStack(_, Op1::Swap))));
+        assert!(matches!(run(vec![Op1::Lit(1), Op1::Swap]), Err(Error::TypeErrorEmptyStack(_, Op1::Swap))));
+        assert!(run(vec![Op1::Lit(1), Op1::Dup, Op1::Add]).is_ok());
+        // the swapped types go with the values
+        let swapped = run(vec![Op1::Lit(1), Op1::U8Lit(2), Op1::Swap, Op1::U8ToI32]);
+        assert!(matches!(swapped, Err(Error::TypeError(_, Op1::U8ToI32, Type::U8, Type::I32))), "{:?}", swapped.err());
+    }
+
+    #[test]
+    fn pop_needs_a_value() {
+        let pop = |body: Vec<Op1>| verify_funcs(vec![(vec![Op1::Func(0)], [body, vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt]].concat())]);
+        assert!(matches!(pop(vec![]), Err(Error::TypeErrorEmptyStack(_, Op1::Pop))));
+        assert!(pop(vec![Op1::Lit(1)]).is_ok());
+    }
+
+    #[test]
+    fn clean_needs_values_beneath_the_top() {
+        let clean = |body: Vec<Op1>, n| verify_funcs(vec![(vec![Op1::Func(0)], [body, vec![Op1::Clean(n), Op1::I32ToU8, Op1::Halt]].concat())]);
+        assert!(matches!(clean(vec![], 0), Err(Error::TypeErrorEmptyStack(_, Op1::Clean(0)))));
+        assert!(matches!(clean(vec![Op1::U8Lit(1), Op1::Lit(2)], 2), Err(Error::TypeErrorEmptyStack(_, Op1::Clean(2)))));
+        assert!(clean(vec![Op1::Lit(2)], 0).is_ok());
+        // the top value keeps its type
+        assert!(clean(vec![Op1::U8Lit(1), Op1::LitI64(2), Op1::Lit(3)], 2).is_ok());
+        assert!(matches!(clean(vec![Op1::Lit(1), Op1::U8Lit(2)], 1), Err(Error::TypeError(_, Op1::I32ToU8, Type::I32, Type::U8))));
+    }
+
+    #[test]
+    fn unique_regions_are_freed_before_halting() {
+        let halt_after = |ops: Vec<Op1>| verify_funcs(vec![(vec![Op1::Func(0)], [ops, vec![Op1::U8Lit(0), Op1::Halt]].concat())]);
+        assert!(matches!(halt_after(vec![Op1::NewRgn(64)]), Err(Error::RegionLeak(_, _))));
+        assert!(halt_after(vec![Op1::NewRgn(64), Op1::FreeRgn]).is_ok());
+        // a region passed in by the caller isn't this function's to free
+        let decl = vec![Op1::Unique, Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End];
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl, vec![Op1::U8Lit(0), Op1::Halt])]).is_ok());
+    }
+
+    #[test]
+    fn nested_binders_are_alpha_equivalent() {
+        let (a, b) = (Id(0, 1), Id(0, 2));
+        // forall x: 4. forall y: 4. (x, y) -> 0
+        let poly = |x, y, params: [Id; 2]| {
+            let params = params.iter().map(|id| Type::Var(*id, 4)).collect();
+            Type::Forall(x, 4, Box::new(Type::Forall(y, 4, Box::new(Type::Func(params)))))
+        };
+        assert!(type_eq(&poly(a, b, [a, b]), &poly(Id(1, 1), Id(1, 2), [Id(1, 1), Id(1, 2)])));
+        // the same binders in the other order, so renaming either side to the other's ids would capture
+        assert!(type_eq(&poly(a, b, [a, b]), &poly(b, a, [b, a])));
+        assert!(!type_eq(&poly(a, b, [a, b]), &poly(a, b, [b, a])));
+        assert!(!type_eq(&poly(a, b, [a, b]), &poly(b, a, [a, b])));
+    }
+
+    #[test]
+    fn check_reports_every_error() {
+        let funcs = vec![
+            (vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]),
+            (vec![Op1::Func(0)], vec![Op1::Halt]),
+            (vec![Op1::Func(0)], vec![Op1::LitF64(1), Op1::Halt]),
+            // a declaration that isn't a function type, so its body isn't checked
+            (vec![Op1::I32], vec![Op1::Halt]),
+            (vec![Op1::Rgn, Op1::Func(0), Op1::End], vec![Op1::U8Lit(0), Op1::Halt]),
+        ];
+        let forward_decs: Vec<_> =
+            funcs.iter().enumerate().map(|(label, (decl, _))| ForwardDec::Func(label as u32, 0, Visibility::Local, decl.clone())).collect();
+        let stmts = funcs.into_iter().enumerate().map(|(label, (_, body))| Stmt1::Func(label as u32, 0, body));
+        let result = check(vec![], &forward_decs, stmts, &VerifyOptions::default());
+        assert!(result.program.is_none());
+        assert!(matches!(
+            &result.errors[..],
+            [Error::TypeErrorEmptyStack(_, Op1::Halt), Error::TypeErrorHaltStatus(_, Op1::Halt, Type::F64), Error::ForwardDeclNotType(3, _)]
+        ), "{:?}", result.errors);
+        assert!(matches!(&result.warnings[..], [Warning::UnusedRegion(4, _)]));
+    }
+
+    #[test]
+    fn rotate_stays_in_the_stack() {
+        let rotate = |i| {
+            let main = vec![Op1::Lit(1), Op1::U8Lit(2), Op1::Rotate(i), Op1::U8Lit(0), Op1::Halt];
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(rotate(0).is_ok());
+        assert!(rotate(1).is_ok());
+        assert!(matches!(rotate(2), Err(Error::TypeErrorRotateOutOfRange(_, 2, 2))));
+        // the rotated value ends up on top
+        let halts_with = |body: Vec<Op1>| {
+            let main = [vec![Op1::U8Lit(2), Op1::LitF64(1)], body, vec![Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(halts_with(vec![Op1::Rotate(1)]).is_ok());
+        assert!(matches!(halts_with(vec![]), Err(Error::TypeErrorHaltStatus(_, Op1::Halt, Type::F64))));
+    }
+
+    #[test]
+    fn degenerate_bodies_are_errors() {
+        let main = |body: Vec<Op1>| verify_funcs(vec![(vec![Op1::Func(0)], body)]);
+        assert!(matches!(main(vec![Op1::CTGet(0)]), Err(Error::TypeErrorEmptyCTStack(0, Op1::CTGet(0)))));
+        assert!(matches!(main(vec![]), Err(Error::EmptyFunction(0))));
+        assert!(matches!(main(vec![Op1::I32]), Err(Error::EmptyFunction(0))));
+    }
+
+    #[test]
+    fn built_compile_time_values_must_be_used() {
+        let main = |body: Vec<Op1>| {
+            let main = [body, vec![Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(matches!(main(vec![Op1::I32]), Err(Error::NonEmptyCompileTimeStackAtExit(0))));
+        assert!(matches!(main(vec![Op1::I32, Op1::Tuple(1)]), Err(Error::NonEmptyCompileTimeStackAtExit(0))));
+        assert!(matches!(main(vec![Op1::Size(4)]), Err(Error::NonEmptyCompileTimeStackAtExit(0))));
+        // the region a function creates stays on the compile-time stack, but a copy of it doesn't
+        assert!(main(vec![Op1::NewRgn(64), Op1::FreeRgn]).is_ok());
+        assert!(matches!(
+            main(vec![Op1::NewRgn(64), Op1::CTGet(0), Op1::FreeRgn]),
+            Err(Error::NonEmptyCompileTimeStackAtExit(0))
+        ));
+        assert!(matches!(main(vec![Op1::Heap]), Err(Error::NonEmptyCompileTimeStackAtExit(0))));
+        // and so do a function's own binders
+        let decl = vec![Op1::Rgn, Op1::Func(0), Op1::End];
+        let binders = |body: Vec<Op1>| verify_funcs(vec![(vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]), (decl.clone(), body)]);
+        assert!(binders(vec![Op1::U8Lit(0), Op1::Halt]).is_ok());
+        assert!(matches!(
+            binders(vec![Op1::CTGet(0), Op1::Handle, Op1::U8Lit(0), Op1::Halt]),
+            Err(Error::NonEmptyCompileTimeStackAtExit(1))
+        ));
+        assert!(matches!(binders(vec![Op1::CTGet(0), Op1::U8Lit(0), Op1::Halt]), Err(Error::NonEmptyCompileTimeStackAtExit(1))));
+    }
+
+    #[test]
+    fn loops_keep_their_state() {
+        let main = |body: Vec<Op1>| {
+            let main = [vec![Op1::Lit(0)], body, vec![Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let breaks_out = vec![Op1::Lit(1), Op1::Break, Op1::LoopEnd];
+        // count to 5, continuing until the count minus 5 is zero
+        let count = vec![Op1::Loop, Op1::Lit(1), Op1::Add, Op1::Get(0), Op1::Lit(-5), Op1::Add, Op1::Continue];
+        assert!(main([count, breaks_out.clone()].concat()).is_ok());
+        assert!(matches!(
+            main([vec![Op1::Loop, Op1::Lit(1), Op1::Lit(1), Op1::Continue], breaks_out.clone()].concat()),
+            Err(Error::TypeErrorLoopStackMismatch(_, Op1::Continue, ..))
+        ));
+        assert!(matches!(
+            main([vec![Op1::Loop, Op1::I32, Op1::Lit(1), Op1::Continue], breaks_out.clone()].concat()),
+            Err(Error::TypeErrorLoopCTStackMismatch(_, Op1::Continue, ..))
+        ));
+        assert!(matches!(
+            main([vec![Op1::Loop, Op1::Lit(1), Op1::Break, Op1::I32], breaks_out].concat()),
+            Err(Error::TypeErrorLoopCTStackMismatch(_, Op1::Break, ..))
+        ));
+        assert!(matches!(
+            main(vec![Op1::Loop, Op1::Lit(1), Op1::Break, Op1::I32, Op1::LoopEnd]),
+            Err(Error::TypeErrorLoopCTStackMismatch(_, Op1::LoopEnd, ..))
+        ));
+        // the break consumes the tuple type that the rest of the body puts back,
+        // so there's no compile-time value left after the loop
+        let add_a_tuple = vec![Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::Proj(0), Op1::Add];
+        let breaks_without_the_type = [
+            vec![Op1::I32, Op1::Tuple(1), Op1::Loop],
+            add_a_tuple,
+            vec![Op1::Lit(1), Op1::Break, Op1::I32, Op1::Tuple(1), Op1::LoopEnd],
+        ]
+        .concat();
+        assert!(main(breaks_without_the_type.clone()).is_ok());
+        assert!(matches!(
+            main([breaks_without_the_type, vec![Op1::Malloc]].concat()),
+            Err(Error::TypeErrorEmptyCTStack(_, Op1::Malloc))
+        ));
+    }
+
+    #[test]
+    fn check_cast_branches_take_the_same_compile_time_arguments() {
+        let tagged = [Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::Tagged];
+        let check_cast = |else_type: Vec<Op1>| {
+            let main = [vec![Op1::I32], tagged.to_vec(), vec![Op1::I32, Op1::Lit(42), Op1::PackTagged, Op1::I32, Op1::CheckCast(1, 2)]];
+            let then_type = vec![Op1::Size(4), Op1::All, Op1::CTGet(0), Op1::Func(1), Op1::End];
+            verify_funcs(vec![
+                (vec![Op1::Func(0)], main.concat()),
+                (then_type, vec![Op1::U8Lit(1), Op1::Halt]),
+                (else_type, vec![Op1::U8Lit(2), Op1::Halt]),
+            ])
+        };
+        assert!(check_cast([&[Op1::Size(4), Op1::All][..], &tagged, &[Op1::Func(1), Op1::End]].concat()).is_ok());
+        // function 1 takes the i32 from the compile-time stack, but function 2 leaves it there
+        assert!(matches!(
+            check_cast([&tagged[..], &[Op1::Func(1)]].concat()),
+            Err(Error::TypeErrorCheckCastBranchesDiffer(_))
+        ));
+    }
+
+    #[test]
+    fn type_tags_include_init_flags() {
+        let pair = |init0, init1| Type::Tuple(vec![(init0, Type::I32), (init1, Type::U8)]);
+        assert_eq!(type_tag(&pair(true, true)), type_tag(&pair(true, true)));
+        assert_ne!(type_tag(&pair(true, true)), type_tag(&pair(true, false)));
+        assert_ne!(type_tag(&pair(true, false)), type_tag(&pair(false, true)));
+        assert_ne!(type_tag(&pair(true, true)), type_tag(&Type::Tuple(vec![(true, Type::U8), (true, Type::I32)])));
+        assert_eq!(type_tag(&Type::Handle(Region { unique: false, id: RgnId::DataSection })), None);
+    }
+
+    #[test]
+    fn distinct_types_get_distinct_tags() {
+        // the scalars, every tuple of up to three of them, and each of those tuples nested in a one-tuple
+        let scalars = [Type::I32, Type::U8, Type::I64, Type::F64];
+        let mut types = vec![Type::Tuple(vec![])];
+        for len in 1..=3 {
+            let mut shapes = vec![vec![]];
+            for _ in 0..len {
+                shapes = shapes
+                    .into_iter()
+                    .flat_map(|shape: Vec<(bool, Type)>| {
+                        scalars.iter().map(move |t| [shape.clone(), vec![(true, t.clone())]].concat())
+                    })
+                    .collect();
+            }
+            types.extend(shapes.into_iter().map(Type::Tuple));
         }
-        t => return Err(Error::ForwardDeclNotType(t.clone())),
+        let nested: Vec<_> = types.iter().map(|t| Type::Tuple(vec![(true, t.clone())])).collect();
+        types.extend(nested);
+        types.extend(scalars);
+        let tags: HashSet<_> = types.iter().map(|t| type_tag(t).unwrap()).collect();
+        assert_eq!(tags.len(), types.len());
+    }
+
+    #[test]
+    fn nested_handles_keep_their_region_alive() {
+        // a tuple on the stack holding a copy of the region's handle, or just an i32
+        let free_under = |component: Vec<Op1>, value: Op1| {
+            let tuple = [vec![Op1::NewRgn(64)], component, vec![Op1::Tuple(1), Op1::Malloc, value, Op1::Init(0)]].concat();
+            let main = [tuple, vec![Op1::Rotate(1), Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(free_under(vec![Op1::I32], Op1::Lit(1)).is_ok());
+        assert!(matches!(
+            free_under(vec![Op1::CTGet(0), Op1::Handle], Op1::Get(1)),
+            Err(Error::RegionFreedButStillReferenced(_, Op1::FreeRgn, _))
+        ));
+    }
+
+    #[test]
+    fn zero_sized_array_elements() {
+        // an array parameter can have zero-sized elements, but its length can't be taken
+        let decl = vec![Op1::Rgn, Op1::CTGet(0), Op1::Tuple(0), Op1::Arr, Op1::Func(1), Op1::End];
+        let len = verify_funcs(vec![
+            (vec![Op1::Func(0)], vec![Op1::U8Lit(0), Op1::Halt]),
+            (decl, vec![Op1::ArrLen, Op1::I32ToU8, Op1::Halt]),
+        ]);
+        assert!(matches!(len, Err(Error::ZeroSizedArrayElement(_, Op1::ArrLen, Type::Tuple(ts))) if ts.is_empty()));
+        // nor can one be allocated
+        let malloc = vec![Op1::NewRgn(64), Op1::Lit(3), Op1::CTGet(0), Op1::Tuple(0), Op1::Arr, Op1::Malloc, Op1::Unreachable];
+        let res = verify_funcs(vec![(vec![Op1::Func(0)], malloc)]);
+        assert!(matches!(res, Err(Error::ZeroSizedArrayElement(_, Op1::Malloc, Type::Tuple(ts))) if ts.is_empty()));
+    }
+
+    #[test]
+    fn arr_alloc_takes_its_region_from_the_handle() {
+        let alloc = |operands: Vec<Op1>, elem: Vec<Op1>| {
+            let main = [operands, elem, vec![Op1::ArrAlloc, Op1::ArrLen, Op1::I32ToU8, Op1::Swap, Op1::FreeRgn, Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        let operands = vec![Op1::NewRgn(64), Op1::Get(0), Op1::Lit(3)];
+        assert!(alloc(operands.clone(), vec![Op1::I32]).is_ok());
+        assert!(matches!(
+            alloc(operands.clone(), vec![Op1::Tuple(0)]),
+            Err(Error::ZeroSizedArrayElement(_, Op1::ArrAlloc, _))
+        ));
+        assert!(matches!(
+            alloc(operands.clone(), vec![Op1::Size(4)]),
+            Err(Error::KindError(_, Op1::ArrAlloc, Kind::Type, CTStackVal::Size(4)))
+        ));
+        let u8_len = vec![Op1::NewRgn(64), Op1::Get(0), Op1::U8Lit(3)];
+        assert!(matches!(alloc(u8_len, vec![Op1::I32]), Err(Error::TypeError(_, Op1::ArrAlloc, Type::I32, Type::U8))));
+        let no_handle = vec![Op1::NewRgn(64), Op1::Lit(0), Op1::Lit(3)];
+        assert!(matches!(
+            alloc(no_handle, vec![Op1::I32]),
+            Err(Error::TypeErrorRegionHandleExpected(_, Op1::ArrAlloc, Type::I32))
+        ));
+    }
+
+    #[test]
+    fn app_needs_access_to_the_region() {
+        let main = vec![Op1::NewRgn(64), Op1::FreeRgn, Op1::GlobalFunc(1), Op1::CTGet(0), Op1::App, Op1::U8Lit(0), Op1::Halt];
+        let decl = vec![Op1::Rgn, Op1::CTGet(0), Op1::Handle, Op1::Func(1), Op1::End];
+        let Err(e) = verify_funcs(vec![(vec![Op1::Func(0)], main), (decl, vec![Op1::U8Lit(0), Op1::Halt])]) else {
+            panic!("instantiated a function with a freed region");
+        };
+        assert!(matches!(e, Error::InsufficientCapabilityForCall(_, Op1::App, _)), "{:?}", e);
+        let msg = e.to_string();
+        assert!(msg.contains("needs access to region"), "{}", msg);
+    }
+
+    #[test]
+    fn alpha_equal_types_hash_equal() {
+        let mut funcs = 0;
+        for seed in 0..20 {
+            for (a, b) in crate::testgen::type_pairs(seed, 500) {
+                if type_eq(&a, &b) {
+                    assert_eq!(type_hash(&a), type_hash(&b), "{} and {}", a.pretty(), b.pretty());
+                    funcs += matches!(a, Type::Func(_)) as usize;
+                }
+            }
+        }
+        // the property says something about function types too
+        assert!(funcs > 0);
+    }
+
+    #[test]
+    fn region_types_compare_ids_only() {
+        let r = |unique, n| Region { unique, id: RgnId::Var(Id(0, n)) };
+        let ptr = |r| Type::Ptr(Box::new(Type::Tuple(vec![(true, Type::I32)])), r);
+        let arr = |r| Type::Array(Box::new(Type::I32), r);
+        assert!(type_eq(&Type::Handle(r(true, 1)), &Type::Handle(r(false, 1))));
+        assert!(type_eq(&ptr(r(true, 1)), &ptr(r(false, 1))));
+        assert!(type_eq(&arr(r(false, 1)), &arr(r(true, 1))));
+        assert!(!type_eq(&Type::Handle(r(true, 1)), &Type::Handle(r(true, 2))));
+        assert!(!type_eq(&ptr(r(false, 1)), &ptr(r(false, 2))));
+        assert!(!type_eq(&arr(r(true, 1)), &arr(r(true, 2))));
+        assert_eq!(type_hash(&ptr(r(true, 1))), type_hash(&ptr(r(false, 1))));
+    }
+
+    #[test]
+    fn binders_check_the_sizes_of_their_uses() {
+        // every use made through the compile-time stack has the binder's size
+        let pair_of_var = vec![Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::CTGet(0), Op1::Tuple(2), Op1::End];
+        let pair = vec![Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::Lit(2), Op1::Init(1)];
+        let pack = [pair_of_var, pair, vec![Op1::I32, Op1::Pack, Op1::U8Lit(0), Op1::Halt]].concat();
+        assert!(verify_funcs(vec![(vec![Op1::Func(0)], pack)]).is_ok());
+        // so a mismatched use can only come from a bug in the verifier, which closing the binder catches
+        let id = Id(0, 0);
+        let misused = Type::Tuple(vec![(true, Type::Var(id, 4)), (true, Type::Var(id, 8))]);
+        for binder in [Quantification::Exist(id, 4), Quantification::Forall(id, 4)] {
+            let mut compile_time_stack = vec![CTStackVal::Type(Type::Var(id, 4)), CTStackVal::Type(misused.clone())];
+            assert!(matches!(
+                handle_end(0, &Op1::End, &mut compile_time_stack, &mut vec![binder]),
+                Err(Error::TypeVarSizeInconsistent(0, Op1::End, _, 4, 8))
+            ));
+        }
+        assert_eq!(var_size_mismatch(&misused, id, 4), Some(8));
+        assert_eq!(var_size_mismatch(&misused, id, 8), Some(4));
+        // an inner binder of the same variable shadows it
+        let shadowed = Type::Exists(id, 8, Box::new(Type::Var(id, 8)));
+        assert_eq!(var_size_mismatch(&shadowed, id, 4), None);
+    }
+
+    #[test]
+    fn unpack_pushes_the_witness() {
+        let repack = |hidden: Vec<Op1>| {
+            // pack 5 as some a: 4. a
+            let package = vec![Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::I32, Op1::Lit(5), Op1::Pack];
+            // unpacking pushes the witness, under the existential that's rebuilt to repack it
+            let rebuilt = vec![Op1::Unpack, Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End];
+            let main = [package, rebuilt, hidden, vec![Op1::Pop, Op1::U8Lit(0), Op1::Halt]].concat();
+            verify_funcs(vec![(vec![Op1::Func(0)], main)])
+        };
+        assert!(repack(vec![Op1::CTGet(1), Op1::Pack]).is_ok());
+        // the witness is abstract, so the contents can't be repacked as an i32
+        assert!(matches!(repack(vec![Op1::I32, Op1::Pack]), Err(Error::TypeError(_, Op1::Pack, Type::I32, Type::Var(..)))));
+    }
+
+    #[test]
+    fn func_subtyping() {
+        let r = Region { unique: false, id: RgnId::Var(Id(0, 1)) };
+        let captured = Region { unique: false, id: RgnId::Var(Id(0, 2)) };
+        let poly = |captured_rgns| Type::ForallRegion(r, Box::new(Type::Func(vec![Type::Handle(r)])), captured_rgns);
+        // capturing fewer regions than expected is fine, but not more
+        assert!(type_sub(&poly(vec![]), &poly(vec![captured])));
+        assert!(!type_sub(&poly(vec![captured]), &poly(vec![])));
+        // parameters go the other way
+        let takes = |t| Type::Func(vec![t]);
+        assert!(type_sub(&takes(poly(vec![captured])), &takes(poly(vec![]))));
+        assert!(!type_sub(&takes(poly(vec![])), &takes(poly(vec![captured]))));
+        // a function may take fewer parameters than expected, but not more
+        let unary = Type::Func(vec![Type::I32]);
+        let binary = Type::Func(vec![Type::I32, Type::I32]);
+        assert!(type_sub(&unary, &binary));
+        assert!(type_sub(&Type::Func(vec![]), &unary));
+        assert!(!type_sub(&binary, &unary));
+        assert!(!type_sub(&unary, &Type::Func(vec![])));
+        assert!(!type_sub(&takes(unary.clone()), &takes(binary.clone())));
+        assert!(type_sub(&takes(binary), &takes(unary.clone())));
+        // the parameters it does take still have to match
+        assert!(!type_sub(&unary, &Type::Func(vec![Type::U8, Type::I32])));
+    }
+
+    #[test]
+    fn unique_binders_only_stand_in_for_unique_binders() {
+        let poly = |unique| {
+            let r = Region { unique, id: RgnId::Var(Id(0, 1)) };
+            Type::ForallRegion(r, Box::new(Type::Func(vec![Type::Handle(r)])), vec![])
+        };
+        assert!(type_sub(&poly(false), &poly(true)));
+        assert!(!type_sub(&poly(true), &poly(false)));
+        assert!(type_sub(&poly(true), &poly(true)));
     }
 }
@@ -18,16 +18,19 @@
 #define METADATA_OFFSET (sizeof(u64) + sizeof(u64))
 
 Region *new_region(size_t size) {
-    dbg("region size with metadata: %lu\n", sizeof(size_t) + sizeof(size_t) + sizeof(size_t) + size);
-    Region *r = malloc(sizeof(size_t) + sizeof(size_t) + sizeof(size_t) + size); // an extra sizeof(size_t) to be safe re: padding
+    dbg("region size with metadata: %lu\n", sizeof(Region) + size);
+    Region *r = malloc(sizeof(Region) + size);
     r->offset = 0;
     r->capacity = size;
+    r->generation = 1;
     return r;
 }
 
 Pointer alloc_object(Region *r, u64 size) {
     // I'd love to figure out how to have less conditionals in this function, but it's just a prototype.
     if (r->offset + METADATA_OFFSET + size > r->capacity) {
+        // No instruction frees a single object yet (regions are freed or reset as a whole),
+        // so this scan never finds anything, and per-size-class free lists would stay empty too.
         for (size_t offset = 0; offset < r->offset; offset += METADATA_OFFSET + r->data[offset]) {
             // negative generation means free
             // the absolute value of the generation is what the last generation was, then we add one to get the current generation
@@ -44,10 +47,10 @@ Pointer alloc_object(Region *r, u64 size) {
             }
         }
         dbg("r->offet: %lu, size: %lu, r->capacity: %lu\n", r->offset, size, r->capacity);
-        printf("Runtime Error! Allocation too big for region!\n");
-        exit(1); // this will jump to an exception handler eventually
+        runtime_error("Runtime Error! Allocation too big for region!\n");
+        return (Pointer){0, NULL}; // this will jump to an exception handler eventually
     } else {
-        i64 first_generation = 1;
+        i64 first_generation = r->generation;
         memcpy(r->data + r->offset, &first_generation, sizeof(first_generation));
         memcpy(r->data + r->offset + sizeof(first_generation), &size, sizeof(size));
         Pointer ptr = {first_generation, &(r->data[r->offset]) + METADATA_OFFSET};
@@ -61,7 +64,23 @@ Pointer alloc_object(Region *r, u64 size) {
     }
 }
 
-void check_ptr(Pointer ptr) {
+void reset_region(Region *r) {
+    for (size_t offset = 0; offset < r->offset;) {
+        i64 g;
+        memcpy(&g, r->data + offset, sizeof(g));
+        u64 size;
+        memcpy(&size, r->data + offset + sizeof(g), sizeof(size));
+        // a freed object's generation is negated, but pointers to it still carry the positive one
+        if (g < 0) g = -g;
+        if (g >= r->generation) r->generation = g + 1;
+        g = -g;
+        memcpy(r->data + offset, &g, sizeof(g));
+        offset += METADATA_OFFSET + size;
+    }
+    r->offset = 0;
+}
+
+u8 check_ptr(Pointer ptr) {
     dbg("check ptr:\n");
     for (int i = 0; i < 20; i++) {
         dbg(" %d",  *(u8*)(ptr.reference - METADATA_OFFSET - 16 + i));
@@ -70,24 +89,26 @@ void check_ptr(Pointer ptr) {
     if (ptr.generation < 0) {
         // negative generation in a pointer means the referent is unfreeable
         // and therefore doesn't have a generation tag in the preceding memory
-        return;
+        return 0;
     }
     i64 g;
     memcpy(&g, ptr.reference - METADATA_OFFSET, sizeof(g));
     dbg("check generation %ld\n", g);
     if (ptr.generation != g) {
         dbg("%ld != %ld\n", ptr.generation, g);
-        printf("Runtime Error! The program is trying to access memory that's already been freed!\n");
-        exit(1); // this will be a jump to exception handler soon
+        runtime_error("Runtime Error! The program is trying to access memory that's already been freed!\n");
+        return 1; // this will be a jump to exception handler soon
     }
+    return 0;
 }
 
-void free_object(Pointer ptr) {
-    check_ptr(ptr);
+u8 free_object(Pointer ptr) {
+    if (check_ptr(ptr)) return 1;
     i64 g;
     memcpy(&g, ptr.reference - METADATA_OFFSET, sizeof(g));
     g = -g;
     memcpy(ptr.reference - METADATA_OFFSET, &g, sizeof(g));
+    return 0;
 }
 
 #define INSTR_PARAM(t, name) \
@@ -97,7 +118,7 @@ void free_object(Pointer ptr) {
 
 #define POP(t, name) \
     t name; \
-    if (sp == 0 && stack->last != NULL) { stack = stack->last; sp = stack->saved_sp; } \
+    step_back(&stack, &sp); \
     sp -= sizeof(name); \
     memcpy(&name, stack->data + sp, sizeof(name));
 
@@ -122,6 +143,30 @@ void ensure_size(struct Stack **stack, u32 *sp, size_t size) {
     }
 }
 
+// if the current chunk is empty, move down to the chunk below it, so that the top value is just under `sp`.
+// A chunk's own top is saved in the chunk above it, which is why `saved_sp` is read before moving down.
+void step_back(struct Stack **stack, u32 *sp) {
+    if (*sp == 0 && (*stack)->last != NULL) {
+        *sp = (*stack)->saved_sp;
+        *stack = (*stack)->last;
+    }
+}
+
+// make room for `extra` more bytes on top of the `size`-byte value on top of the stack,
+// moving the value up to a new chunk if they wouldn't fit in its own,
+// so that the value and whatever is pushed onto it stay in one chunk.
+// The caller must guarantee that `size + extra` is at most STACK_CHUNK_SIZE.
+void ensure_size_above(struct Stack **stack, u32 *sp, size_t size, size_t extra) {
+    if (size > 0) step_back(stack, sp);
+    if (*sp + extra > STACK_CHUNK_SIZE) {
+        *sp -= size;
+        u8 *value = (*stack)->data + *sp;
+        ensure_size(stack, sp, size + extra);
+        memcpy((*stack)->data + *sp, value, size);
+        *sp += size;
+    }
+}
+
 Handler scheduler[255];
 u8 scheduler_len = 0;
 
@@ -134,9 +179,44 @@ int post_task(Handler h) {
 u8 waiting = 0;
 Handler stdin_handler = {0};
 Region *stdin_rgn = NULL;
+// the heap is one region that lives as long as the program, so it doesn't count toward `live_regions`
+#define HEAP_SIZE (1 << 20)
+Region *heap_rgn = NULL;
 Pointer stdin_str_ptr = {0, NULL};
 Handler stdout_handler = {0};
 Handler stderr_handler = {0};
+OutputFn output_fn = NULL;
+void *output_fn_ctx = NULL;
+u32 live_regions = 0;
+u32 max_live_regions = 0;
+// set by `runtime_error`, so a runtime error can be told apart from a program halting with a nonzero status;
+// it's one of the error codes in vm.h, which is what the entry point returns
+int failed = 0;
+
+// write program output through the embedder's sink, which returns nonzero on failure.
+u8 emit(u8 stream, const u8 *bytes, size_t len) {
+    if (output_fn(output_fn_ctx, stream, bytes, len)) {
+        runtime_error("Runtime Error! Failed to write program output.\n");
+        failed = IO_ERROR;
+        return 1;
+    }
+    return 0;
+}
+
+void runtime_error(const char *fmt, ...) {
+    char msg[256];
+    failed = RUNTIME_ERROR;
+    va_list args;
+    va_start(args, fmt);
+    int len = vsnprintf(msg, sizeof(msg), fmt, args);
+    va_end(args);
+    if (len < 0) return;
+    if ((size_t)len >= sizeof(msg)) len = sizeof(msg) - 1;
+    // if the sink is what failed, standard error is the only place left to say so
+    if (output_fn == NULL || output_fn(output_fn_ctx, 1, (const u8 *)msg, len)) {
+        fputs(msg, stderr);
+    }
+}
 
 void handle_stdin() {
     ssize_t bytes;
@@ -144,14 +224,16 @@ void handle_stdin() {
     // Read all available input
     while ((bytes = read(STDIN_FILENO, buffer, sizeof(buffer))) > 0) {
         Pointer ptr = alloc_object(stdin_rgn, bytes + sizeof(bytes));
+        // this runs in a signal handler, so there's no eval loop to hand an error back to
+        if (ptr.reference == NULL) exit(1);
         memcpy(ptr.reference, &bytes, sizeof(bytes));
         memcpy(ptr.reference + sizeof(bytes), buffer, bytes);
         Handler h;
         memcpy(&h, &stdin_handler, sizeof(h));
         memcpy(h.param, &ptr, sizeof(ptr));
         h.param_size = sizeof(ptr);
-        if (post_task(h)) {
-            printf("failed to post stdin handler to scheduler\n");
+        if (!post_task(h)) {
+            fputs("Runtime Error! Failed to post the stdin handler to the scheduler.\n", stderr);
             exit(1);
         }
         waiting &= 0b11111110;
@@ -159,26 +241,11 @@ void handle_stdin() {
     
 }
 
-u8 vm_function(u8 instrs[]) {
-    // for (u32 i = 0; i < instrs_len; i++) {
-    //     dbg(" %d", instrs[i]);
-    // }
-    // dbg("\n");
-    u32 data_section_size;
-    memcpy(&data_section_size, instrs, sizeof(data_section_size));
-    dbg("data section size: %lu\n", data_section_size);
-    u32 pc = sizeof(data_section_size) + data_section_size;
-    dbg("pc: %lu\n", pc);
+// run tasks from the scheduler until there are none left and none coming,
+// returning the status of the first one that halts nonzero, or the code for a runtime error
+int run_tasks(u8 instrs[], u32 data_section_size) {
     u32 sp = 0;
     struct Stack *stack = malloc(sizeof(struct Stack));
-
-    int flags = fcntl(STDIN_FILENO, F_GETFL, 0);
-    fcntl(STDIN_FILENO, F_SETFL, flags | O_NONBLOCK | O_ASYNC);
-    fcntl(STDIN_FILENO, __F_SETOWN, getpid());
-    signal(SIGIO, handle_stdin);
-
-    Handler on_start = (Handler){.f=pc};
-    post_task(on_start); // guaranteed to succeed; no failure check here
     while (1) {
         while (scheduler_len > 0) {
             Handler h = scheduler[--scheduler_len];
@@ -187,6 +254,7 @@ u8 vm_function(u8 instrs[]) {
             memcpy(stack->data + sp, &h.env, sizeof(h.env));
             sp += sizeof(h.env);
             u8 err = eval(instrs, h.f, sp + h.param_size + sizeof(h.env), data_section_size, stack);
+            if (failed) return failed;
             if (err) return err;
         }
         dbg("waiting: %d\nscheduler_len: %d\n", waiting);
@@ -197,6 +265,40 @@ u8 vm_function(u8 instrs[]) {
     }
 }
 
+int vm_function(u8 instrs[], OutputFn output, void *output_ctx, u32 max_regions) {
+    output_fn = output;
+    output_fn_ctx = output_ctx;
+    // an earlier run can leave tasks behind if it stopped with an error
+    scheduler_len = 0;
+    waiting = 0;
+    failed = 0;
+    live_regions = 0;
+    max_live_regions = max_regions;
+    heap_rgn = new_region(HEAP_SIZE);
+    // for (u32 i = 0; i < instrs_len; i++) {
+    //     dbg(" %d", instrs[i]);
+    // }
+    // dbg("\n");
+    u32 data_section_size;
+    memcpy(&data_section_size, instrs, sizeof(data_section_size));
+    dbg("data section size: %lu\n", data_section_size);
+    u32 pc = sizeof(data_section_size) + data_section_size;
+    dbg("pc: %lu\n", pc);
+
+    int flags = fcntl(STDIN_FILENO, F_GETFL, 0);
+    fcntl(STDIN_FILENO, F_SETFL, flags | O_NONBLOCK | O_ASYNC);
+    fcntl(STDIN_FILENO, __F_SETOWN, getpid());
+    signal(SIGIO, handle_stdin);
+
+    Handler on_start = (Handler){.f=pc};
+    post_task(on_start); // guaranteed to succeed; no failure check here
+    int status = run_tasks(instrs, data_section_size);
+    // the heap lives as long as this run, so the next run starts with an empty one
+    free(heap_rgn);
+    heap_rgn = NULL;
+    return status;
+}
+
 u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack) {
     while (1) {
         // dbg("pc: %d, sp: %d\n", pc, sp);
@@ -211,20 +313,21 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             ensure_size(&stack, &sp, size);
+            // find the chunk holding the value. A chunk's top is saved in the chunk above it,
+            // and anything past that top was never used, so it isn't counted in the offset
             struct Stack *stack2 = stack;
-            u32 sp2 = sp;
-            int i = 10;
-            while (sp2 < offset + size && i > 0) {
-                dbg(" sp2: %u\n offset: %lu\n size: %lu\n saved sp: %u\n\n", sp2, offset, size, stack2->saved_sp);
-                offset -= sp2 + (STACK_CHUNK_SIZE - stack2->saved_sp);
-                sp2 = stack2->saved_sp;
+            u32 top = sp;
+            while (top < offset + size) {
+                dbg(" top: %u\n offset: %lu\n size: %lu\n saved sp: %u\n\n", top, offset, size, stack2->saved_sp);
+                if (stack2->last == NULL) {
+                    runtime_error("Runtime Error! A get reached too far down the stack.\n");
+                    return 1;
+                }
+                offset -= top;
+                top = stack2->saved_sp;
                 stack2 = stack2->last;
-                i--;
             }
-            if (i == 0) {
-                return 1;
-            }
-            memcpy(stack->data + sp, stack2->data + sp2 - offset - size, size);
+            memcpy(stack->data + sp, stack2->data + top - offset - size, size);
             sp += size;
             break;
         }
@@ -234,8 +337,12 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             INSTR_PARAM(size_t, tpl_size);
+            step_back(&stack, &sp);
             sp -= size;
-            memcpy(stack->data + sp - tpl_size + offset, stack->data + sp, size);
+            u8 *value = stack->data + sp;
+            // the value can start a new chunk, leaving the tuple at the top of the one below
+            step_back(&stack, &sp);
+            memcpy(stack->data + sp - tpl_size + offset, value, size);
             break;
         }
         case 2: {
@@ -243,11 +350,12 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
-            Pointer ptr; 
-            sp -= size + sizeof(ptr);
-            memcpy(&ptr, stack->data + sp, sizeof(ptr));
-            check_ptr(ptr);
-            memcpy(ptr.reference + offset, stack->data + sp + sizeof(ptr), size);
+            step_back(&stack, &sp);
+            sp -= size;
+            u8 *value = stack->data + sp;
+            POP(Pointer, ptr);
+            if (check_ptr(ptr)) return 1;
+            memcpy(ptr.reference + offset, value, size);
             PUSH(Pointer, ptr);
             break;
         }
@@ -256,8 +364,10 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             INSTR_PARAM(size_t, size);
             POP(Region*, handle);
-            ensure_size(&stack, &sp, sizeof(handle));
-            PUSH(Pointer, alloc_object(handle, size));
+            Pointer ptr = alloc_object(handle, size);
+            if (ptr.reference == NULL) return 1;
+            ensure_size(&stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
             break;
         }
         case 4: {
@@ -285,7 +395,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             POP(Pointer, ptr);
-            check_ptr(ptr);
+            if (check_ptr(ptr)) return 1;
             ensure_size(&stack, &sp, size);
             memcpy(stack->data + sp, ptr.reference + offset, size);
             sp += size;
@@ -304,12 +414,12 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             if (ptr.generation == -1) {
                 // -1 generation means data section string
                 size_t size = (size_t)instrs + 4 + (size_t)data_section_size - (size_t)ptr.reference;
-                printf("%.*s", (int)size, ptr.reference);
+                if (emit(0, ptr.reference, size)) return 1;
             } else {
-                check_ptr(ptr);
+                if (check_ptr(ptr)) return 1;
                 size_t array_len;
                 memcpy(&array_len, ptr.reference, sizeof(array_len));
-                printf("%.*s", (int)array_len, ptr.reference + sizeof(array_len));
+                if (emit(0, ptr.reference + sizeof(array_len), array_len)) return 1;
             }
             break;
         }
@@ -339,6 +449,13 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             dbg("new region!\n");
             pc++;
             INSTR_PARAM(size_t, size);
+            // the verifier can't bound this, since a recursive function can create a region per call
+            if (live_regions == max_live_regions) {
+                runtime_error("Runtime Error! Too many live regions (the limit is %u).\n", max_live_regions);
+                failed = TOO_MANY_REGIONS;
+                return 1;
+            }
+            live_regions++;
             Region *r = new_region(size);
             ensure_size(&stack, &sp, sizeof(r));
             PUSH(Region*, r);
@@ -349,6 +466,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(Region*, r);
             free(r);
+            live_regions--;
             break;
         }
         case 14: {
@@ -356,7 +474,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             INSTR_PARAM(size_t, size);
             POP(Pointer, ptr);
-            check_ptr(ptr);
+            if (check_ptr(ptr)) return 1;
             ensure_size(&stack, &sp, size);
             memcpy(stack->data + sp, ptr.reference, size);
             sp += size;
@@ -368,9 +486,14 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             INSTR_PARAM(size_t, elem_size);
             POP(i32, len);
             POP(Region*, r);
+            if (len < 0) {
+                runtime_error("Runtime Error! Negative array length (%d) during an allocation.\n", len);
+                return 1;
+            }
             size_t size = elem_size * len;
             dbg("size: %ld\n", sizeof(size) + size);
             Pointer ptr = alloc_object(r, sizeof(size) + size);
+            if (ptr.reference == NULL) return 1;
             memcpy(ptr.reference, &size, sizeof(size));
             memset(ptr.reference + sizeof(size), 0, size);
             ensure_size(&stack, &sp, sizeof(ptr));
@@ -382,17 +505,20 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             INSTR_PARAM(size_t, elem_size);
             POP(i32, i);
-            Pointer ptr;
-            memcpy(&ptr, stack->data + sp - elem_size - sizeof(ptr), sizeof(ptr));
+            step_back(&stack, &sp);
+            sp -= elem_size;
+            u8 *value = stack->data + sp;
+            POP(Pointer, ptr);
+            if (check_ptr(ptr)) return 1;
             size_t n = elem_size * i;
             size_t array_len;
             memcpy(&array_len, ptr.reference, sizeof(array_len));
-            if (n + elem_size > array_len) {
-                printf("Runtime Error! Array index out of bounds during an initialization.\n");
+            // a negative index would wrap around to a huge one, and the sum past it back to a small one
+            if (i < 0 || n + elem_size > array_len) {
+                runtime_error("Runtime Error! Array index out of bounds during an initialization.\n");
                 return 1;
             }
-            memcpy(ptr.reference + sizeof(array_len) + n, stack->data + sp - elem_size, elem_size);
-            sp -= elem_size + sizeof(ptr);
+            memcpy(ptr.reference + sizeof(array_len) + n, value, elem_size);
             PUSH(Pointer, ptr);
             break;
         }
@@ -403,11 +529,11 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             POP(i32, i);
             size_t n = elem_size * i;
             POP(Pointer, ptr);
-            check_ptr(ptr);
+            if (check_ptr(ptr)) return 1;
             size_t array_len;
             memcpy(&array_len, ptr.reference, sizeof(array_len));
-            if (n + elem_size > array_len) {
-                printf("Runtime Error! Array index out of bounds during a projection.\n");
+            if (i < 0 || n + elem_size > array_len) {
+                runtime_error("Runtime Error! Array index out of bounds during a projection.\n");
                 return 1;
             }
             ensure_size(&stack, &sp, elem_size);
@@ -420,7 +546,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, a + b);
+            PUSH(i32, (i32)((u32)a + (u32)b));
             break;
         }
         case 19: {
@@ -428,7 +554,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, a * b);
+            PUSH(i32, (i32)((u32)a * (u32)b));
             break;
         }
         case 20: {
@@ -436,7 +562,13 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, b / a);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
+            // INT_MIN / -1 overflows, so it wraps like the other arithmetic instead
+            PUSH(i32, a == -1 ? (i32)(0 - (u32)b) : b / a);
             break;
         }
         case 21: {
@@ -461,6 +593,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                 // negative generation in a pointer means the referent is unfreeable. In this case, the referent is in the data section.
                 .generation = -1 
             };
+            ensure_size(&stack, &sp, sizeof(ptr));
             PUSH(Pointer, ptr);
             break;
         }
@@ -471,8 +604,10 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             POP(i32, i);
             size_t n = elem_size * i;
             POP(Pointer, ptr); // frontend ensures this is a data-section pointer, so we don't need to check it.
-            if (n + elem_size > data_section_size) {
-                printf("Runtime Error! Array index out of bounds during a projection from the data section.\n");
+            // the array runs from the pointer to the end of the data section, as in arr_len
+            size_t array_len = instrs + 4 + data_section_size - ptr.reference;
+            if (i < 0 || n + elem_size > array_len) {
+                runtime_error("Runtime Error! Array index out of bounds during a projection from the data section.\n");
                 return 1;
             }
             ensure_size(&stack, &sp, elem_size);
@@ -498,7 +633,7 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                 }
                 src_ref = src_array.reference;
             } else {
-                check_ptr(src_array);
+                if (check_ptr(src_array)) return 1;
                 size_t array_len;
                 memcpy(&array_len, src_array.reference, sizeof(array_len));
                 size = (size_t)n * elem_size;
@@ -510,10 +645,10 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             size_t dest_array_len;
             memcpy(&dest_array_len, dest_array.reference, sizeof(dest_array_len));
             if (n < 0) {
-                printf("Runtime Error! Negative size (%d) during a copy.\n", n);
+                runtime_error("Runtime Error! Negative size (%d) during a copy.\n", n);
                 return 1;
             } else if (dest_array_len < (u32)n) {
-                printf("Runtime Error! Copy (%d) out of bounds for array of size %lu.\n", n, dest_array_len);
+                runtime_error("Runtime Error! Copy (%d) out of bounds for array of size %lu.\n", n, dest_array_len);
                 return 1;
             }
             memcpy(dest_array.reference + sizeof(size), src_ref, size);
@@ -550,6 +685,11 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(u8, a);
             POP(u8, b);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
             PUSH(u8, b / a);
             break;
         }
@@ -557,6 +697,8 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             dbg("u8 to i32!\n");
             pc++;
             POP(u8, a);
+            // the i32 is bigger than the u8 it replaces, so it might not fit where the u8 was
+            ensure_size(&stack, &sp, sizeof(i32));
             PUSH(i32, a);
             break;
         }
@@ -565,7 +707,12 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, b % a);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
+            PUSH(i32, a == -1 ? 0 : b % a);
             break;
         }
         case 31: {
@@ -573,6 +720,11 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             pc++;
             POP(u8, a);
             POP(u8, b);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
             PUSH(u8, b % a);
             break;
         }
@@ -617,18 +769,18 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                         stdout_handler.env = env;
                         size_t len;
                         memcpy(&len, str_ptr.reference, sizeof(len));
-                        printf("%.*s", (int)len, str_ptr.reference + sizeof(len));
+                        if (emit(0, str_ptr.reference + sizeof(len), len)) return 1;
                         post_task(stdout_handler);
                     } else if (write_mode == 1) {
                         stderr_handler.f = handler;
                         stderr_handler.env = env;
                         size_t len;
                         memcpy(&len, str_ptr.reference, sizeof(len));
-                        fprintf(stderr, "%.*s", (int)len, str_ptr.reference + sizeof(len));
+                        if (emit(1, str_ptr.reference + sizeof(len), len)) return 1;
                         post_task(stderr_handler);
                     } else {
-                        printf("Internal SaberVM Error! Unknown write mode %d.\n", write_mode);
-                        exit(1);
+                        runtime_error("Internal SaberVM Error! Unknown write mode %d.\n", write_mode);
+                        return 1;
                     }
                     // waiting |= 0b10;
                     break;
@@ -636,8 +788,357 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             }
             break;
         }
+        case 35: {
+            dbg("pack tagged!\n");
+            pc++;
+            INSTR_PARAM(size_t, size);
+            INSTR_PARAM(u64, tag);
+            // the tag is part of the tagged value, so it has to go in the same chunk as the hidden value
+            ensure_size_above(&stack, &sp, size, sizeof(tag));
+            PUSH(u64, tag);
+            break;
+        }
+        case 36: {
+            dbg("check cast!\n");
+            pc++;
+            INSTR_PARAM(u64, tag);
+            INSTR_PARAM(u32, then_f);
+            INSTR_PARAM(u32, else_f);
+            POP(u64, actual_tag);
+            if (actual_tag == tag) {
+                // the tag is dropped so the hidden value is exposed
+                pc = then_f;
+            } else {
+                PUSH(u64, actual_tag);
+                pc = else_f;
+            }
+            break;
+        }
+        case 37: {
+            dbg("unreachable!\n");
+            runtime_error("Runtime Error! Reached code that was marked unreachable.\n");
+            failed = REACHED_UNREACHABLE;
+            return 1; // this will jump to an exception handler eventually
+        }
+        case 38: {
+            dbg("reset region!\n");
+            pc++;
+            POP(Region*, r);
+            // the type system ensures nothing still points into the region,
+            // and the generations catch anything that slips past it
+            reset_region(r);
+            PUSH(Region*, r);
+            break;
+        }
+        case 39: {
+            dbg("rotate!\n");
+            pc++;
+            INSTR_PARAM(size_t, offset);
+            INSTR_PARAM(size_t, size);
+            // find the chunk holding the value, and the chunk after it (whose saved_sp is its top)
+            struct Stack *stack2 = stack;
+            struct Stack *child = NULL;
+            u32 top = sp;
+            while (top < offset + size) {
+                offset -= top;
+                child = stack2;
+                top = stack2->saved_sp;
+                stack2 = stack2->last;
+            }
+            u8 tmp[STACK_CHUNK_SIZE];
+            u8 *start = stack2->data + top - offset - size;
+            memcpy(tmp, start, size);
+            // close the gap within its own chunk so that no value ends up spanning two chunks
+            memmove(start, start + size, offset);
+            if (child == NULL) {
+                sp -= size;
+            } else {
+                child->saved_sp -= size;
+            }
+            ensure_size(&stack, &sp, size);
+            memcpy(stack->data + sp, tmp, size);
+            sp += size;
+            break;
+        }
+        case 40: {
+            dbg("jump!\n");
+            pc++;
+            INSTR_PARAM(u32, target);
+            pc = target;
+            break;
+        }
+        case 41: {
+            dbg("jump if not zero!\n");
+            pc++;
+            INSTR_PARAM(u32, target);
+            POP(i32, cond);
+            if (cond != 0) {
+                pc = target;
+            }
+            break;
+        }
+        case 42: {
+            dbg("select!\n");
+            pc++;
+            INSTR_PARAM(size_t, size);
+            POP(i32, cond);
+            u8 tmp[STACK_CHUNK_SIZE];
+            // values never span two chunks, so each one is contiguous below some sp
+            step_back(&stack, &sp);
+            sp -= size;
+            memcpy(tmp, stack->data + sp, size);
+            step_back(&stack, &sp);
+            sp -= size;
+            // the second value is already in place, so only the first needs copying
+            if (cond != 0) {
+                memcpy(stack->data + sp, tmp, size);
+            }
+            sp += size;
+            break;
+        }
+        case 43: {
+            dbg("array length!\n");
+            pc++;
+            INSTR_PARAM(size_t, elem_size);
+            POP(Pointer, ptr);
+            size_t size;
+            if (ptr.generation == -1) {
+                // -1 generation means data section array, which runs to the end of the data section
+                size = instrs + 4 + data_section_size - ptr.reference;
+            } else {
+                if (check_ptr(ptr)) return 1;
+                memcpy(&size, ptr.reference, sizeof(size));
+            }
+            PUSH(i32, size / elem_size);
+            break;
+        }
+        case 44: {
+            dbg("pop!\n");
+            pc++;
+            INSTR_PARAM(size_t, size);
+            // go down a chunk at a time, like clean, rather than assuming the value starts at a chunk's edge
+            while (size > 0) {
+                step_back(&stack, &sp);
+                size_t n = size < sp ? size : sp;
+                sp -= n;
+                size -= n;
+            }
+            break;
+        }
+        case 45: {
+            dbg("mem copy!\n");
+            pc++;
+            INSTR_PARAM(size_t, size);
+            POP(Pointer, src);
+            POP(Pointer, dest);
+            if (check_ptr(src) || check_ptr(dest)) return 1;
+            memmove(dest.reference, src.reference, size);
+            PUSH(Pointer, dest);
+            break;
+        }
+        case 46: {
+            dbg("subtract two i32s!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            PUSH(i32, (i32)((u32)b - (u32)a));
+            break;
+        }
+        case 47: {
+            dbg("subtract u8!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            PUSH(u8, b - a);
+            break;
+        }
+        case 48: {
+            dbg("inject!\n");
+            pc++;
+            INSTR_PARAM(size_t, size);
+            INSTR_PARAM(size_t, pad);
+            INSTR_PARAM(u8, i);
+            // the padding and the two words are part of the sum, so they go in the same chunk as the value
+            ensure_size_above(&stack, &sp, size, pad + 2 * sizeof(u32));
+            memset(stack->data + sp, 0, pad);
+            sp += pad;
+            PUSH(u32, pad);
+            PUSH(u32, i);
+            break;
+        }
+        case 49: {
+            dbg("case!\n");
+            pc++;
+            INSTR_PARAM(u8, n);
+            u32 branches[256];
+            for (u8 j = 0; j < n; j++) {
+                POP(u32, f);
+                branches[j] = f;
+            }
+            POP(u32, i);
+            POP(u32, pad);
+            // the padding goes too, so the variant's value is on top
+            sp -= pad;
+            pc = branches[i];
+            break;
+        }
+        case 50: {
+            dbg("64-bit literal!\n");
+            pc++;
+            INSTR_PARAM(u64, lit);
+            ensure_size(&stack, &sp, sizeof(lit));
+            PUSH(u64, lit);
+            break;
+        }
+        case 51: {
+            dbg("add two i64s!\n");
+            pc++;
+            POP(i64, a);
+            POP(i64, b);
+            PUSH(i64, (i64)((u64)a + (u64)b));
+            break;
+        }
+        case 52: {
+            dbg("subtract two i64s!\n");
+            pc++;
+            POP(i64, a);
+            POP(i64, b);
+            PUSH(i64, (i64)((u64)b - (u64)a));
+            break;
+        }
+        case 53: {
+            dbg("multiply two i64s!\n");
+            pc++;
+            POP(i64, a);
+            POP(i64, b);
+            PUSH(i64, (i64)((u64)a * (u64)b));
+            break;
+        }
+        case 54: {
+            dbg("divide two i64s!\n");
+            pc++;
+            POP(i64, a);
+            POP(i64, b);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
+            PUSH(i64, a == -1 ? (i64)(0 - (u64)b) : b / a);
+            break;
+        }
+        case 55: {
+            dbg("modulo i64!\n");
+            pc++;
+            POP(i64, a);
+            POP(i64, b);
+            if (a == 0) {
+                runtime_error("Runtime Error! Division by zero.\n");
+                failed = DIV_BY_ZERO;
+                return 1;
+            }
+            PUSH(i64, a == -1 ? 0 : b % a);
+            break;
+        }
+        case 56: {
+            dbg("add two f64s!\n");
+            pc++;
+            POP(double, a);
+            POP(double, b);
+            PUSH(double, b + a);
+            break;
+        }
+        case 57: {
+            dbg("subtract two f64s!\n");
+            pc++;
+            POP(double, a);
+            POP(double, b);
+            PUSH(double, b - a);
+            break;
+        }
+        case 58: {
+            dbg("multiply two f64s!\n");
+            pc++;
+            POP(double, a);
+            POP(double, b);
+            PUSH(double, b * a);
+            break;
+        }
+        case 59: {
+            dbg("divide two f64s!\n");
+            pc++;
+            POP(double, a);
+            POP(double, b);
+            PUSH(double, b / a);
+            break;
+        }
+        case 60: {
+            dbg("i64 to i32!\n");
+            pc++;
+            POP(i64, a);
+            PUSH(i32, (i32)(u32)(u64)a);
+            break;
+        }
+        case 61: {
+            dbg("f64 to i64!\n");
+            pc++;
+            POP(double, a);
+            i64 n;
+            if (a != a) {
+                n = 0;
+            } else if (a >= 9223372036854775808.0) {
+                n = INT64_MAX;
+            } else if (a <= -9223372036854775808.0) {
+                n = INT64_MIN;
+            } else {
+                n = (i64)a;
+            }
+            PUSH(i64, n);
+            break;
+        }
+        case 62: {
+            dbg("clean!\n");
+            pc++;
+            INSTR_PARAM(size_t, removed);
+            INSTR_PARAM(size_t, size);
+            u8 tmp[STACK_CHUNK_SIZE];
+            step_back(&stack, &sp);
+            sp -= size;
+            memcpy(tmp, stack->data + sp, size);
+            // values never span two chunks, so the removed ones can be dropped a chunk at a time
+            while (removed > 0) {
+                step_back(&stack, &sp);
+                size_t n = removed < sp ? removed : sp;
+                sp -= n;
+                removed -= n;
+            }
+            ensure_size(&stack, &sp, size);
+            memcpy(stack->data + sp, tmp, size);
+            sp += size;
+            break;
+        }
+        case 63: {
+            dbg("heap handle!\n");
+            pc++;
+            ensure_size(&stack, &sp, sizeof(heap_rgn));
+            PUSH(Region*, heap_rgn);
+            break;
+        }
+        case 64: {
+            dbg("assert!\n");
+            pc++;
+            INSTR_PARAM(u64, tag);
+            POP(u64, actual_tag);
+            if (actual_tag != tag) {
+                runtime_error("Runtime Error! An assert found a value of a different type than it expected.\n");
+                failed = ASSERT_FAILED;
+                return 1;
+            }
+            // the tag is dropped so the hidden value is exposed, as in a check cast
+            break;
+        }
         default: {
-            printf("internal error!! Unknown IR op %d, please let the SaberVM team know!!", instrs[pc]);
+            runtime_error("internal error!! Unknown IR op %d, please let the SaberVM team know!!\n", instrs[pc]);
             return 1;
         }
         }
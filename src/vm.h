@@ -15,6 +15,7 @@
 #include <sys/select.h>
 #include <sys/file.h>
 #include <signal.h>
+#include <stdarg.h>
 
 typedef uint64_t u64;
 typedef int64_t i64;
@@ -24,6 +25,7 @@ typedef int32_t i32;
 
 /*
  * The size of each contiguous chunk of the stack.
+ * The verifier keeps every value at most this big, and a value is never split between two chunks.
  */
 #define STACK_CHUNK_SIZE 4096
 
@@ -45,6 +47,8 @@ typedef struct {
 typedef struct {
     size_t offset;
     size_t capacity;
+    // the generation new objects start at, which a reset moves past every generation handed out so far
+    i64 generation;
     u8 data[];
 } Region;
 
@@ -72,21 +76,36 @@ Region *new_region();
  * Allocate an object in a region 
  * The type system ensures it gets initialized before it is read,
  * so there's no need to initialize the memory.
+ * If the object doesn't fit, this reports a runtime error and returns a null reference.
  */
 Pointer alloc_object(Region *r, u64 size);
 
 /*
- * Crash if the given pointer is no longer valid.
+ * Empty a region so its memory can be reused.
+ * Every object in it is marked freed, so pointers from before the reset fail `check_ptr`
+ * even if a new object ends up where they point.
+ */
+void reset_region(Region *r);
+
+/*
+ * Report a runtime error and return nonzero if the given pointer is no longer valid.
  * This happens if the object it's pointing at has been freed.
- * In the future this will jump to the exception handler instead of crashing.
+ * In the future this will jump to the exception handler instead of ending the program.
  */
-void check_ptr(Pointer ptr);
+u8 check_ptr(Pointer ptr);
 
 /*
  * Free an object within a region. 
  * Generations are used to keep this safe, instead of static analysis.
+ * Returns nonzero if the pointer was already invalid.
+ */
+u8 free_object(Pointer ptr);
+
+/*
+ * Report a runtime error through the embedder's error stream, printf-style.
+ * This doesn't stop the program; the caller does that by returning nonzero.
  */
-void free_object(Pointer ptr);
+void runtime_error(const char *fmt, ...);
 
 /*
  * Free a region of memory.
@@ -94,10 +113,35 @@ void free_object(Pointer ptr);
  */
 void free_region(Region *r);
 
+/*
+ * Where the program's output goes.
+ * `stream` is 0 for standard output and 1 for standard error.
+ * Returns nonzero if the write failed.
+ */
+typedef u8 (*OutputFn)(void *ctx, u8 stream, const u8 *bytes, size_t len);
+
+/*
+ * What the entry point returns instead of an exit status when the program stops with a runtime error.
+ * Hitting the limit on live regions gets its own code, so embedders can tell a runaway recursion apart,
+ * and so does reaching an `unreachable`, which means the program's compiler was wrong about its control flow.
+ * A failed write to the embedder's output sink gets one too, since it's the embedder's problem rather than the program's.
+ * Integer division or modulo by zero does as well, since it's the one arithmetic error the verifier can't rule out,
+ * and so does an `assert` finding the wrong tag, since that's a failed check the program asked for.
+ */
+#define RUNTIME_ERROR -1
+#define TOO_MANY_REGIONS -2
+#define REACHED_UNREACHABLE -3
+#define IO_ERROR -4
+#define DIV_BY_ZERO -5
+#define ASSERT_FAILED -6
+
 /*
  * The entry point.
+ * All of the program's output is written through `output`, which gets `output_ctx` passed back to it.
+ * At most `max_regions` regions can be live at once.
+ * Returns the status the program halted with, or one of the codes above if it stopped with a runtime error.
  */
-extern uint8_t vm_function(u8 instrs[]);
+extern int vm_function(u8 instrs[], OutputFn output, void *output_ctx, u32 max_regions);
 
 /*
  * The actual VM implementation.
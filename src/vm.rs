@@ -8,15 +8,81 @@ use std::collections::HashMap;
 use std::vec;
 
 use crate::header::*;
-use crate::pretty::Pretty;
-use std::fs;
+use std::ffi::c_void;
+use std::io::Write;
+use std::sync::Mutex;
 
 extern "C" {
-    fn vm_function(bytes: *mut u8) -> u8;
+    fn vm_function(
+        bytes: *mut u8,
+        output: extern "C" fn(*mut c_void, u8, *const u8, usize) -> u8,
+        output_ctx: *mut c_void,
+        max_regions: u32,
+    ) -> i32;
 }
 
-pub fn go(ir_programs: Vec<IRProgram>) -> u8 {
-    let mut str = String::new();
+/// Why the program stopped without halting. The VM has already written the error to the error stream.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// Any error without a variant of its own, like a stale pointer or an out-of-bounds index.
+    Trapped,
+    /// More regions were live at once than `Limits::max_live_regions` allows.
+    TooManyRegions,
+    /// The program ran an `unreachable` op.
+    ReachedUnreachable,
+    /// Writing the program's output to the embedder's sink failed.
+    IoError,
+    /// An integer `Div` or `Modulo` had zero as its divisor.
+    DivByZero,
+    /// An `Assert` found a tagged existential whose tag wasn't the asserted type's.
+    AssertFailed,
+}
+
+/// What `vm_function` returns for each runtime error, matching `vm.h`.
+const RUNTIME_ERROR: i32 = -1;
+const TOO_MANY_REGIONS: i32 = -2;
+const REACHED_UNREACHABLE: i32 = -3;
+const IO_ERROR: i32 = -4;
+const DIV_BY_ZERO: i32 = -5;
+const ASSERT_FAILED: i32 = -6;
+
+/// The runtime keeps its scheduler and output sink in globals, so only one program can run at a time.
+static VM_LOCK: Mutex<()> = Mutex::new(());
+
+/// Bounds on the resources a running program can use.
+pub struct Limits {
+    /// How many regions can be live at once.
+    pub max_live_regions: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_live_regions: 1 << 16,
+        }
+    }
+}
+
+/// Where a running program's output goes, so embedders can capture or redirect it.
+pub struct Output<'a> {
+    pub out: &'a mut dyn Write,
+    pub err: &'a mut dyn Write,
+}
+
+/// The sink the VM writes program output through.
+/// Every write is flushed so output interleaves correctly with the VM's own messages.
+extern "C" fn write_output(ctx: *mut c_void, stream: u8, bytes: *const u8, len: usize) -> u8 {
+    let output = unsafe { &mut *(ctx as *mut Output) };
+    let bytes = unsafe { std::slice::from_raw_parts(bytes, len) };
+    let sink = if stream == 0 { &mut output.out } else { &mut output.err };
+    match sink.write_all(bytes).and_then(|_| sink.flush()) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Lay out the programs' code and run them, returning the status the program halted with.
+pub fn go(ir_programs: Vec<IRProgram>, output: &mut Output, limits: &Limits) -> Result<u8, RuntimeError> {
     let code_size = 4 + ir_programs.iter().map(program_size).sum::<usize>();
     let mut code = Vec::with_capacity(code_size);
     let mut import_map = HashMap::new();
@@ -59,20 +125,32 @@ pub fn go(ir_programs: Vec<IRProgram>) -> u8 {
             label_map.insert(*label, pos2);
             pos2 += ops.iter().map(op_len).sum::<usize>() as u32;
         }
-        for Stmt2::Func(l, t, ops) in &prog.funcs {
-            str += &("function ".to_string() + &l.to_string() + ": " + &t.pretty() + "\n");
+        for Stmt2::Func(_, _, ops) in &prog.funcs {
+            // jumps within a function refer to op indices, which become code positions here
+            let mut op_positions = vec![pos];
             for op in ops {
-                str += &(pos.to_string() + " " + &op.pretty() + "\n");
+                op_positions.push(op_positions.last().unwrap() + op_len(op) as u32);
+            }
+            for op in ops {
+                let func_pos = |label: &Label| match label_map.get(label) {
+                    Some(pos) => *pos,
+                    None => {
+                        let func_id = import_map.get(prog.imports.get(label).unwrap()).unwrap();
+                        *func_positions.get(func_id).unwrap()
+                    },
+                };
                 match op {
                     Op2::GlobalFunc(label) => {
-                        let func_pos = match label_map.get(label) {
-                            Some(pos) => *pos,
-                            None => {
-                                let func_id = import_map.get(prog.imports.get(label).unwrap()).unwrap();
-                                *func_positions.get(func_id).unwrap()
-                            },
-                        };
-                        code.extend(op_to_bytes(&Op2::GlobalFunc(func_pos as u32)));
+                        code.extend(op_to_bytes(&Op2::GlobalFunc(func_pos(label))));
+                    }
+                    Op2::CheckCast(tag, then_label, else_label) => {
+                        code.extend(op_to_bytes(&Op2::CheckCast(*tag, func_pos(then_label), func_pos(else_label))));
+                    }
+                    Op2::Jump(i) => {
+                        code.extend(op_to_bytes(&Op2::Jump(op_positions[*i as usize])));
+                    }
+                    Op2::JumpNZ(i) => {
+                        code.extend(op_to_bytes(&Op2::JumpNZ(op_positions[*i as usize])));
                     }
                     Op2::Data(data_pos) => {
                         let data_sec_pos = data_sec_positions.get(&prog_id).unwrap();
@@ -85,8 +163,25 @@ pub fn go(ir_programs: Vec<IRProgram>) -> u8 {
         }
         prog_id += 1;
     }
-    let _ = fs::write("t.txt", str);
-    unsafe { vm_function(code.as_mut_ptr()) }
+    // the runtime resets its globals on entry, so a run that panicked doesn't poison the next one
+    let _guard = VM_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let status = unsafe {
+        vm_function(
+            code.as_mut_ptr(),
+            write_output,
+            output as *mut Output as *mut c_void,
+            limits.max_live_regions,
+        )
+    };
+    match status {
+        RUNTIME_ERROR => Err(RuntimeError::Trapped),
+        TOO_MANY_REGIONS => Err(RuntimeError::TooManyRegions),
+        REACHED_UNREACHABLE => Err(RuntimeError::ReachedUnreachable),
+        IO_ERROR => Err(RuntimeError::IoError),
+        DIV_BY_ZERO => Err(RuntimeError::DivByZero),
+        ASSERT_FAILED => Err(RuntimeError::AssertFailed),
+        status => u8::try_from(status).map_err(|_| RuntimeError::Trapped),
+    }
 }
 
 fn op_to_bytes(op: &Op2) -> Vec<u8> {
@@ -153,6 +248,55 @@ fn op_to_bytes(op: &Op2) -> Vec<u8> {
         Op2::I32ToU8 => vec![32],
         Op2::Read(c) => vec![33, *c],
         Op2::Write(c) => vec![34, *c],
+        Op2::PackTagged(size, tag) => [vec![35], size.to_le_bytes().to_vec(), tag.to_le_bytes().to_vec()].concat(),
+        Op2::CheckCast(tag, then_label, else_label) => [
+            vec![36],
+            tag.to_le_bytes().to_vec(),
+            then_label.to_le_bytes().to_vec(),
+            else_label.to_le_bytes().to_vec(),
+        ]
+        .concat(),
+        Op2::Unreachable => vec![37],
+        Op2::ResetRgn => vec![38],
+        Op2::Rotate(offset, size) => [
+            vec![39],
+            offset.to_le_bytes().to_vec(),
+            size.to_le_bytes().to_vec(),
+        ]
+        .concat(),
+        Op2::Jump(target) => [vec![40], target.to_le_bytes().to_vec()].concat(),
+        Op2::JumpNZ(target) => [vec![41], target.to_le_bytes().to_vec()].concat(),
+        Op2::Select(s) => [vec![42], s.to_le_bytes().to_vec()].concat(),
+        Op2::ArrLen(s) => [vec![43], s.to_le_bytes().to_vec()].concat(),
+        Op2::Pop(s) => [vec![44], s.to_le_bytes().to_vec()].concat(),
+        Op2::MemCopy(s) => [vec![45], s.to_le_bytes().to_vec()].concat(),
+        Op2::SubI32 => vec![46],
+        Op2::SubU8 => vec![47],
+        Op2::Inj(size, pad, i) => [vec![48], size.to_le_bytes().to_vec(), pad.to_le_bytes().to_vec(), vec![*i]].concat(),
+        Op2::Case(n) => vec![49, *n],
+        Op2::Lit64(bits) => [vec![50], bits.to_le_bytes().to_vec()].concat(),
+        Op2::AddI64 => vec![51],
+        Op2::SubI64 => vec![52],
+        Op2::MulI64 => vec![53],
+        Op2::DivI64 => vec![54],
+        Op2::ModuloI64 => vec![55],
+        Op2::AddF64 => vec![56],
+        Op2::SubF64 => vec![57],
+        Op2::MulF64 => vec![58],
+        Op2::DivF64 => vec![59],
+        Op2::I64ToI32 => vec![60],
+        Op2::F64ToI64 => vec![61],
+        // these are special cases of get and rotate, so the runtime shares their code
+        Op2::Dup(size) => op_to_bytes(&Op2::Get(0, *size)),
+        Op2::Swap(size_a, size_b) => op_to_bytes(&Op2::Rotate(*size_a, *size_b)),
+        Op2::Clean(removed, size) => [
+            vec![62],
+            removed.to_le_bytes().to_vec(),
+            size.to_le_bytes().to_vec(),
+        ]
+        .concat(),
+        Op2::HeapHandle => vec![63],
+        Op2::Assert(tag) => [vec![64], tag.to_le_bytes().to_vec()].concat(),
     }
 }
 
@@ -194,6 +338,38 @@ fn op_len(op: &Op2) -> usize {
         Op2::I32ToU8 => 1,
         Op2::Read(_) => 1 + 1,
         Op2::Write(_) => 1 + 1,
+        Op2::PackTagged(_, _) => 1 + 8 + 8,
+        Op2::CheckCast(_, _, _) => 1 + 8 + 4 + 4,
+        Op2::Unreachable => 1,
+        Op2::ResetRgn => 1,
+        Op2::Rotate(_, _) => 1 + 8 + 8,
+        Op2::Jump(_) => 1 + 4,
+        Op2::JumpNZ(_) => 1 + 4,
+        Op2::Select(_) => 1 + 8,
+        Op2::ArrLen(_) => 1 + 8,
+        Op2::Pop(_) => 1 + 8,
+        Op2::MemCopy(_) => 1 + 8,
+        Op2::SubI32 => 1,
+        Op2::SubU8 => 1,
+        Op2::Inj(_, _, _) => 1 + 8 + 8 + 1,
+        Op2::Case(_) => 1 + 1,
+        Op2::Lit64(_) => 1 + 8,
+        Op2::AddI64
+        | Op2::SubI64
+        | Op2::MulI64
+        | Op2::DivI64
+        | Op2::ModuloI64
+        | Op2::AddF64
+        | Op2::SubF64
+        | Op2::MulF64
+        | Op2::DivF64
+        | Op2::I64ToI32
+        | Op2::F64ToI64 => 1,
+        Op2::Dup(_) => 1 + 8 + 8,
+        Op2::Swap(_, _) => 1 + 8 + 8,
+        Op2::Clean(_, _) => 1 + 8 + 8,
+        Op2::HeapHandle => 1,
+        Op2::Assert(_) => 1 + 8,
     }
 }
 
@@ -203,4 +379,713 @@ fn program_size(prog: &IRProgram) -> usize {
         out += ops.iter().map(op_len).sum::<usize>();
     }
     out
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify;
+
+    /// Verify and run a program given as each function's declaration ops and body ops, in label order,
+    /// and return the status it halted with.
+    fn run_funcs(funcs: Vec<(Vec<Op1>, Vec<Op1>)>) -> u8 {
+        let forward_decs: Vec<_> =
+            funcs.iter().enumerate().map(|(label, (decl, _))| ForwardDec::Func(label as u32, 0, Visibility::Local, decl.clone())).collect();
+        let stmts = funcs.into_iter().enumerate().map(|(label, (_, body))| Stmt1::Func(label as u32, 0, body));
+        let program = verify::go_default(vec![], &forward_decs, stmts).unwrap();
+        go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default()).unwrap()
+    }
+
+    /// Run functions of already-lowered ops, in label order, skipping the verifier,
+    /// and return the status and what was written to standard output and standard error.
+    fn run_capturing(data_section: Vec<u8>, funcs: Vec<Vec<Op2>>) -> (Result<u8, RuntimeError>, String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let status = go(vec![lowered(data_section, funcs)], &mut Output { out: &mut out, err: &mut err }, &Limits::default());
+        (status, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    /// A program of functions of already-lowered ops, in label order.
+    fn lowered(data_section: Vec<u8>, funcs: Vec<Vec<Op2>>) -> IRProgram {
+        IRProgram {
+            data_section,
+            imports: HashMap::new(),
+            exports: HashMap::new(),
+            funcs: funcs.into_iter().enumerate().map(|(label, ops)| Stmt2::Func(label as u32, Type::Func(vec![]), ops)).collect(),
+        }
+    }
+
+    /// Run a single function of already-lowered ops, skipping the verifier.
+    fn run_ops(ops: Vec<Op2>) -> Result<u8, RuntimeError> {
+        run_capturing(vec![], vec![ops]).0
+    }
+
+    #[test]
+    fn folds_are_erased() {
+        // rec a: 4. i32, folded and unfolded around a 7
+        let rec_i32 = vec![Op1::Size(4), Op1::Rec, Op1::I32, Op1::End];
+        let main = [vec![Op1::Lit(7)], rec_i32, vec![Op1::Fold, Op1::Unfold, Op1::I32ToU8, Op1::Halt]].concat();
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main)]), 7);
+    }
+
+    #[test]
+    fn loops_jump_back_and_out() {
+        // count to 5, continuing until the count minus 5 is zero, then break out with the count
+        let count = vec![Op1::Loop, Op1::Lit(1), Op1::Add, Op1::Get(0), Op1::Lit(-5), Op1::Add, Op1::Continue];
+        let main = [vec![Op1::Lit(0)], count, vec![Op1::Lit(1), Op1::Break, Op1::LoopEnd, Op1::I32ToU8, Op1::Halt]].concat();
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main)]), 5);
+    }
+
+    #[test]
+    fn get_local_counts_from_the_bottom() {
+        // function 1's parameters are an i32 3 and an i64 9, and whatever it pushes first doesn't move them
+        let main = vec![Op1::Lit(3), Op1::LitI64(9), Op1::GlobalFunc(1), Op1::Call];
+        for pushed in [vec![], vec![Op1::Lit(1)], vec![Op1::LitI64(1), Op1::Lit(2)]] {
+            let run = |read: &[Op1]| {
+                let body = [&pushed[..], read, &[Op1::I32ToU8, Op1::Halt]].concat();
+                run_funcs(vec![(vec![Op1::Func(0)], main.clone()), (vec![Op1::I32, Op1::I64, Op1::Func(2)], body)])
+            };
+            assert_eq!(run(&[Op1::GetLocal(0)]), 3, "after pushing {:?}", pushed);
+            assert_eq!(run(&[Op1::GetLocal(1), Op1::I64ToI32]), 9, "after pushing {:?}", pushed);
+        }
+    }
+
+    #[test]
+    fn dropping_a_handle_pops_only_the_handle() {
+        let main = vec![Op1::Lit(9), Op1::NewRgn(64), Op1::GlobalFunc(1), Op1::CTGet(0), Op1::App, Op1::Call];
+        let decl = vec![Op1::Rgn, Op1::I32, Op1::CTGet(1), Op1::Handle, Op1::Func(2), Op1::End];
+        let body = vec![Op1::DropHandle, Op1::I32ToU8, Op1::Halt];
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main), (decl, body)]), 9);
+    }
+
+    #[test]
+    fn mem_copy_copies_every_component() {
+        // (3, 8) is copied into an uninitialized pair, then read back as 8 * 10 + 3
+        let pair = |handle, inits: Vec<Op1>| [vec![Op1::Get(handle), Op1::CTGet(0), Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Malloc], inits].concat();
+        let copy = [pair(0, vec![]), pair(1, vec![Op1::Lit(3), Op1::Init(0), Op1::Lit(8), Op1::Init(1)]), vec![Op1::MemCopy]].concat();
+        let read = vec![Op1::Get(0), Op1::Proj(1), Op1::Rotate(1), Op1::Proj(0), Op1::Rotate(1), Op1::Lit(10), Op1::Mul, Op1::Add];
+        let main = [vec![Op1::NewRgn(64)], copy, read, vec![Op1::I32ToU8, Op1::Rotate(1), Op1::FreeRgn, Op1::Halt]].concat();
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main)]), 83);
+    }
+
+    #[test]
+    fn mem_copy_copies_between_regions() {
+        // (3, 8) is copied from the second region into the first, which still holds it after the second is freed
+        let dest = vec![Op1::Get(1), Op1::CTGet(1), Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Malloc];
+        let src = vec![Op1::Get(1), Op1::CTGet(0), Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Malloc];
+        let inits = vec![Op1::Lit(3), Op1::Init(0), Op1::Lit(8), Op1::Init(1)];
+        let read = vec![Op1::Get(0), Op1::Proj(1), Op1::Rotate(1), Op1::Proj(0), Op1::Rotate(1), Op1::Lit(10), Op1::Mul, Op1::Add];
+        let main = [
+            vec![Op1::NewRgn(64), Op1::NewRgn(64)],
+            dest,
+            src,
+            inits,
+            vec![Op1::MemCopy, Op1::Swap, Op1::FreeRgn],
+            read,
+            vec![Op1::I32ToU8, Op1::Rotate(1), Op1::FreeRgn, Op1::Halt],
+        ];
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main.concat())]), 83);
+    }
+
+    #[test]
+    fn mem_copy_checks_both_pointers() {
+        // an object made stale by a reset of its region, then an object allocated after the reset
+        let copy = |operands: Vec<Op2>| {
+            let main = [
+                vec![Op2::NewRgn(64), Op2::Get(0, 8), Op2::Malloc(4)],
+                vec![Op2::Get(16, 8), Op2::ResetRgn, Op2::Malloc(4)],
+                operands,
+                vec![Op2::MemCopy(4), Op2::Pop(16), Op2::U8Lit(0), Op2::Halt],
+            ];
+            run_capturing(vec![], vec![main.concat()])
+        };
+        // the stale object as the destination, then as the source
+        for operands in [vec![], vec![Op2::Get(16, 16)]] {
+            let (status, _, err) = copy(operands);
+            assert_eq!(status, Err(RuntimeError::Trapped));
+            assert!(err.contains("already been freed"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn live_regions_are_limited() {
+        // a freed region doesn't count toward the limit
+        let main = vec![Op1::NewRgn(64), Op1::FreeRgn, Op1::NewRgn(64), Op1::NewRgn(64), Op1::FreeRgn, Op1::FreeRgn, Op1::U8Lit(0), Op1::Halt];
+        let forward_decs = vec![ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        let run_with = |max_live_regions| {
+            let program = verify::go_default(vec![], &forward_decs, vec![Stmt1::Func(0, 0, main.clone())]).unwrap();
+            let mut err = Vec::new();
+            let output = &mut Output { out: &mut std::io::sink(), err: &mut err };
+            let status = go(vec![program], output, &Limits { max_live_regions });
+            (status, String::from_utf8(err).unwrap())
+        };
+        assert_eq!(run_with(2), (Ok(0), String::new()));
+        let (status, err) = run_with(1);
+        assert_eq!(status, Err(RuntimeError::TooManyRegions));
+        assert!(err.contains("Too many live regions (the limit is 1)"), "{}", err);
+    }
+
+    #[test]
+    fn recursion_that_never_frees_hits_the_region_limit() {
+        // function 1 creates a region and calls itself, so each call's region stays live
+        let funcs = [(vec![Op1::Func(0)], vec![Op1::GlobalFunc(1), Op1::Call]), (vec![Op1::Func(0)], vec![Op1::NewRgn(64), Op1::GlobalFunc(1), Op1::Call])];
+        let forward_decs: Vec<_> = (0..).zip(&funcs).map(|(label, (decl, _))| ForwardDec::Func(label, 0, Visibility::Local, decl.clone())).collect();
+        let stmts = (0..).zip(&funcs).map(|(label, (_, body))| Stmt1::Func(label, 0, body.clone()));
+        let program = verify::go_default(vec![], &forward_decs, stmts).unwrap();
+        let mut err = Vec::new();
+        let status = go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut err }, &Limits { max_live_regions: 100 });
+        assert_eq!(status, Err(RuntimeError::TooManyRegions));
+        assert!(String::from_utf8(err).unwrap().contains("Too many live regions (the limit is 100)"));
+    }
+
+    #[test]
+    fn select_picks_the_top_value_on_nonzero() {
+        let select = |padding, cond| {
+            let main = [vec![Op1::Lit(1); padding], vec![Op1::Lit(7), Op1::Lit(9), Op1::Lit(cond), Op1::Select, Op1::I32ToU8, Op1::Halt]];
+            run_funcs(vec![(vec![Op1::Func(0)], main.concat())])
+        };
+        for padding in [0].into_iter().chain(1000..1030) {
+            assert_eq!(select(padding, 1), 9, "with {} i32s below", padding);
+            assert_eq!(select(padding, -1), 9, "with {} i32s below", padding);
+            assert_eq!(select(padding, 0), 7, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn select_picks_whole_tuples() {
+        let pair = |a, b| vec![Op1::I32, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(a), Op1::Init(0), Op1::Lit(b), Op1::Init(1)];
+        let select = |cond, i| {
+            let main = [pair(1, 2), pair(3, 4), vec![Op1::Lit(cond), Op1::Select, Op1::Proj(i), Op1::I32ToU8, Op1::Halt]];
+            run_funcs(vec![(vec![Op1::Func(0)], main.concat())])
+        };
+        assert_eq!((select(1, 0), select(1, 1)), (3, 4));
+        assert_eq!((select(0, 0), select(0, 1)), (1, 2));
+    }
+
+    #[test]
+    fn components_are_read_from_where_they_were_written() {
+        let inits = vec![Op1::Lit(1), Op1::Init(0), Op1::U8Lit(2), Op1::Init(1), Op1::Lit(3), Op1::Init(2)];
+        let tuple = vec![Op1::I32, Op1::U8, Op1::I32, Op1::Tuple(3)];
+        let stack = ([tuple.clone(), vec![Op1::Malloc]].concat(), vec![]);
+        // the region is freed under the status
+        let heap = ([vec![Op1::NewRgn(64), Op1::Get(0), Op1::CTGet(0)], tuple, vec![Op1::Ptr, Op1::Malloc]].concat(), vec![Op1::Swap, Op1::FreeRgn]);
+        for (alloc, free) in [stack, heap] {
+            let proj = |i, to_u8: &[Op1]| {
+                let main = [alloc.clone(), inits.clone(), vec![Op1::Proj(i)], to_u8.to_vec(), free.clone(), vec![Op1::Halt]].concat();
+                run_funcs(vec![(vec![Op1::Func(0)], main)])
+            };
+            assert_eq!(proj(0, &[Op1::I32ToU8]), 1);
+            assert_eq!(proj(1, &[]), 2);
+            assert_eq!(proj(2, &[Op1::I32ToU8]), 3);
+        }
+    }
+
+    /// Pack 42 with a tag, under `padding` i32s, and check-cast it to `target`.
+    /// Function 1 gets the revealed value and halts with 1; function 2 gets the package back and halts with 2.
+    fn check_cast(padding: usize, target: &[Op1]) -> u8 {
+        let tagged = [Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::Tagged];
+        let main = [
+            vec![Op1::Lit(7); padding],
+            tagged.to_vec(),
+            vec![Op1::I32, Op1::Lit(42), Op1::PackTagged],
+            target.to_vec(),
+            vec![Op1::CheckCast(1, 2)],
+        ];
+        run_funcs(vec![
+            (vec![Op1::Func(0)], main.concat()),
+            ([target, &[Op1::Func(1)]].concat(), vec![Op1::U8Lit(1), Op1::Halt]),
+            ([&tagged[..], &[Op1::Func(1)]].concat(), vec![Op1::U8Lit(2), Op1::Halt]),
+        ])
+    }
+
+    #[test]
+    fn asserts_fail_on_the_wrong_tag() {
+        // 42 packed with a tag, asserted to be `target` and then halted with by `halt`
+        let assert = |target: &[Op1], halt: &[Op1]| {
+            let tagged = [Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::Tagged];
+            let main = [&tagged[..], &[Op1::I32, Op1::Lit(42), Op1::PackTagged], target, &[Op1::Assert], halt].concat();
+            let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+            let program = verify::go_default(vec![], &forward_decs, vec![Stmt1::Func(0, 0, main)]).unwrap();
+            go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default())
+        };
+        assert_eq!(assert(&[Op1::I32], &[Op1::I32ToU8, Op1::Halt]), Ok(42));
+        let bytes = [Op1::U8, Op1::U8, Op1::U8, Op1::U8, Op1::Tuple(4)];
+        assert_eq!(assert(&bytes, &[Op1::Proj(0), Op1::Halt]), Err(RuntimeError::AssertFailed));
+    }
+
+    #[test]
+    fn objects_allocated_after_a_reset_are_valid() {
+        let status = run_ops(vec![
+            Op2::NewRgn(64),
+            Op2::Get(0, 8),
+            Op2::Malloc(4),
+            // reset the region while the pointer is still on the stack
+            Op2::Get(16, 8),
+            Op2::ResetRgn,
+            // a new object takes the stale pointer's slot, in a newer generation
+            Op2::Malloc(4),
+            Op2::Deref(4),
+            Op2::U8Lit(3),
+            Op2::Halt,
+        ]);
+        assert_eq!(status, Ok(3));
+    }
+
+    #[test]
+    fn reset_invalidates_pointers() {
+        let (status, _, err) = run_capturing(
+            vec![],
+            vec![vec![
+                Op2::NewRgn(64),
+                Op2::Get(0, 8),
+                Op2::Malloc(4),
+                // reset the region while the pointer is still on the stack
+                Op2::Get(16, 8),
+                Op2::ResetRgn,
+                // the new object takes the stale pointer's slot, which still can't be read through
+                Op2::Malloc(4),
+                Op2::Pop(16),
+                Op2::Deref(4),
+                Op2::I32ToU8,
+                Op2::Halt,
+            ]],
+        );
+        assert_eq!(status, Err(RuntimeError::Trapped));
+        assert!(err.contains("already been freed"), "{}", err);
+    }
+
+    #[test]
+    fn writes_go_to_the_embedders_sinks() {
+        // the data section holds a length-prefixed string
+        let data_section = [8u64.to_le_bytes().to_vec(), b"written\n".to_vec()].concat();
+        let write = |mode| {
+            let ops = [
+                vec![Op2::Data(0), Op2::GlobalFunc(1)],
+                // the handler's environment, which isn't used
+                vec![Op2::Lit(0); 4],
+                vec![Op2::U8Lit(mode), Op2::NewRgn(16), Op2::Write(0), Op2::U8Lit(0), Op2::Halt],
+            ];
+            // main halts, and then the write's handler runs
+            run_capturing(data_section.clone(), vec![ops.concat(), vec![Op2::U8Lit(5), Op2::Halt]])
+        };
+        assert_eq!(write(0), (Ok(5), "written\n".to_string(), String::new()));
+        assert_eq!(write(1), (Ok(5), String::new(), "written\n".to_string()));
+    }
+
+    /// A sink that refuses every write, like a closed pipe.
+    struct ClosedSink;
+
+    impl Write for ClosedSink {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_writes_are_io_errors() {
+        // the same length-prefixed string and write as above, with standard output closed
+        let data_section = [8u64.to_le_bytes().to_vec(), b"written\n".to_vec()].concat();
+        let write = |mode| {
+            let ops = [
+                vec![Op2::Data(0), Op2::GlobalFunc(1)],
+                vec![Op2::Lit(0); 4],
+                vec![Op2::U8Lit(mode), Op2::NewRgn(16), Op2::Write(0), Op2::U8Lit(0), Op2::Halt],
+            ];
+            let program = lowered(data_section.clone(), vec![ops.concat(), vec![Op2::U8Lit(5), Op2::Halt]]);
+            let mut err = Vec::new();
+            let status = go(vec![program], &mut Output { out: &mut ClosedSink, err: &mut err }, &Limits::default());
+            (status, String::from_utf8(err).unwrap())
+        };
+        let (status, err) = write(0);
+        assert_eq!(status, Err(RuntimeError::IoError));
+        assert!(err.contains("Failed to write program output"), "{}", err);
+        // standard error is still open
+        assert_eq!(write(1), (Ok(5), "written\n".to_string()));
+    }
+
+    #[test]
+    fn reaching_unreachable_is_a_runtime_error() {
+        // the stack can hold anything, since unreachable makes no demands on it
+        let (status, _, err) = run_capturing(vec![], vec![vec![Op2::Lit(5), Op2::Unreachable]]);
+        assert_eq!(status, Err(RuntimeError::ReachedUnreachable));
+        assert!(err.contains("unreachable"), "{}", err);
+    }
+
+    #[test]
+    fn rotate_moves_values_of_any_size() {
+        // an i32 1, a u8 3 and an i32 2 are brought up one at a time and combined into 1 * 10 + 2 + 3
+        let body = [
+            Op1::Lit(1),
+            Op1::U8Lit(3),
+            Op1::Lit(2),
+            Op1::Rotate(2),
+            Op1::Lit(10),
+            Op1::Mul,
+            Op1::Add,
+            Op1::Rotate(1),
+            Op1::U8ToI32,
+            Op1::Add,
+            Op1::I32ToU8,
+            Op1::Halt,
+        ];
+        for padding in [0].into_iter().chain(1000..1030) {
+            let main = [vec![Op1::Lit(7); padding], body.to_vec()].concat();
+            assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main)]), 15, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn check_cast_compares_tags() {
+        assert_eq!(check_cast(0, &[Op1::I32]), 1);
+        // a one-field tuple has the same size as an i32 but a different tag
+        assert_eq!(check_cast(0, &[Op1::I32, Op1::Tuple(1)]), 2);
+    }
+
+    /// Run a main function that pushes `padding` i32s and then runs `body`.
+    /// Paddings from 1000 to 1030 put the values `body` pushes on either side of the end of the first stack chunk.
+    fn run_padded(padding: usize, body: &[Op1]) -> u8 {
+        run_funcs(vec![(vec![Op1::Func(0)], [vec![Op1::Lit(7); padding], body.to_vec()].concat())])
+    }
+
+    #[test]
+    fn tagged_values_stay_in_one_chunk() {
+        // some of these put the tag right at the end of a stack chunk,
+        // so the whole tagged value has to move to the next chunk for `pop` and `check_cast` to find it
+        let body = [
+            Op1::Size(4),
+            Op1::Some,
+            Op1::CTGet(0),
+            Op1::End,
+            Op1::Tagged,
+            Op1::I32,
+            Op1::Lit(42),
+            Op1::PackTagged,
+            Op1::Pop,
+            Op1::I32ToU8,
+            Op1::Halt,
+        ];
+        for padding in 1000..1030 {
+            assert_eq!(run_padded(padding, &body), 7, "with {} i32s below", padding);
+        }
+        for padding in [0].into_iter().chain(1000..1030) {
+            assert_eq!(check_cast(padding, &[Op1::I32]), 1, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn values_are_found_across_chunk_edges() {
+        // an i64 that doesn't fit after the 42 starts a new chunk, leaving the end of the old one unused
+        let get = vec![Op1::Lit(42), Op1::LitI64(0), Op1::Get(1), Op1::I32ToU8, Op1::Halt];
+        // the i64 can land in a new chunk while the tuple it's written into stays in the old one
+        let init = vec![
+            Op1::Lit(42),
+            Op1::I64,
+            Op1::I32,
+            Op1::Tuple(2),
+            Op1::Malloc,
+            Op1::Lit(1),
+            Op1::Init(0),
+            Op1::LitI64(2),
+            Op1::Init(1),
+            Op1::Pop,
+            Op1::I32ToU8,
+            Op1::Halt,
+        ];
+        // the same goes for a value written through a pointer, or into an array
+        let to_status = [Op1::I64ToI32, Op1::I32ToU8, Op1::Rotate(1), Op1::FreeRgn, Op1::Halt];
+        let init_ip = [
+            vec![Op1::NewRgn(64), Op1::Get(0), Op1::CTGet(0), Op1::I64, Op1::Tuple(1), Op1::Ptr, Op1::Malloc],
+            vec![Op1::LitI64(42), Op1::Init(0), Op1::Proj(0)],
+            to_status.to_vec(),
+        ];
+        let arr_mut = [
+            vec![Op1::NewRgn(64), Op1::Get(0), Op1::Lit(1), Op1::CTGet(0), Op1::I64, Op1::Arr, Op1::Malloc],
+            vec![Op1::LitI64(42), Op1::Lit(0), Op1::ArrMut, Op1::Lit(0), Op1::ArrProj],
+            to_status.to_vec(),
+        ];
+        for body in [get, init, init_ip.concat(), arr_mut.concat()] {
+            for padding in 1000..1030 {
+                assert_eq!(run_padded(padding, &body), 42, "with {} i32s below: {:?}", padding, body);
+            }
+        }
+    }
+
+    /// Verify and run `main` with the given data section.
+    fn run_main(data_section: Vec<u8>, main: Vec<Op1>) -> Result<u8, RuntimeError> {
+        let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        let program = verify::go_default(data_section, &forward_decs, vec![Stmt1::Func(0, 0, main)]).unwrap();
+        go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default())
+    }
+
+    #[test]
+    fn arrays_know_their_length() {
+        // an array of i32s in a new region, with the status left under the region's handle to free it
+        let alloc = |len| vec![Op1::NewRgn(1024), Op1::Get(0), Op1::Lit(len), Op1::I32, Op1::ArrAlloc];
+        let free = [Op1::I32ToU8, Op1::Swap, Op1::FreeRgn, Op1::Halt];
+        for len in [0, 3, 200] {
+            assert_eq!(run_main(vec![], [alloc(len), vec![Op1::ArrLen], free.to_vec()].concat()), Ok(len as u8));
+        }
+        // an array in the data section runs from where it starts to the end of the data section
+        let data = vec![Op1::DataSec, Op1::U8, Op1::Arr, Op1::Data(2), Op1::ArrLen, Op1::I32ToU8, Op1::Halt];
+        assert_eq!(run_main(vec![0; 10], data), Ok(8));
+        let data = vec![Op1::DataSec, Op1::I32, Op1::Arr, Op1::Data(2), Op1::ArrLen, Op1::I32ToU8, Op1::Halt];
+        assert_eq!(run_main(vec![0; 10], data), Ok(2));
+    }
+
+    #[test]
+    fn array_accesses_are_bounds_checked() {
+        let alloc = |len| vec![Op1::NewRgn(1024), Op1::Get(0), Op1::Lit(len), Op1::I32, Op1::ArrAlloc];
+        let free = [Op1::I32ToU8, Op1::Swap, Op1::FreeRgn, Op1::Halt];
+        let proj = |len, i| run_main(vec![], [alloc(len), vec![Op1::Lit(i), Op1::ArrProj], free.to_vec()].concat());
+        let mutate = |len, i| {
+            let mutate = vec![Op1::Lit(7), Op1::Lit(i), Op1::ArrMut, Op1::Lit(i), Op1::ArrProj];
+            run_main(vec![], [alloc(len), mutate, free.to_vec()].concat())
+        };
+        assert_eq!(proj(3, 2), Ok(0));
+        assert_eq!(mutate(3, 2), Ok(7));
+        for i in [3, -1, i32::MIN] {
+            assert_eq!(proj(3, i), Err(RuntimeError::Trapped), "projecting {}", i);
+            assert_eq!(mutate(3, i), Err(RuntimeError::Trapped), "mutating {}", i);
+        }
+        assert_eq!(proj(-1, 0), Err(RuntimeError::Trapped));
+        // the data section array starts two bytes in, so it has eight of them
+        let data_proj = |i| {
+            let data = vec![Op1::DataSec, Op1::U8, Op1::Arr, Op1::Data(2), Op1::Lit(i), Op1::ArrProj, Op1::Halt];
+            run_main((0..10).collect(), data)
+        };
+        assert_eq!(data_proj(7), Ok(9));
+        assert_eq!(data_proj(8), Err(RuntimeError::Trapped));
+        assert_eq!(data_proj(-1), Err(RuntimeError::Trapped));
+    }
+
+    #[test]
+    fn pop_discards_a_value() {
+        // a 12-byte tuple, then an existential hiding an i32, are popped off the 42 below them
+        let tuple = [Op1::I64, Op1::I32, Op1::Tuple(2), Op1::Malloc, Op1::Lit(1), Op1::Init(0), Op1::LitI64(2), Op1::Init(1), Op1::Pop];
+        let package = [Op1::Size(4), Op1::Some, Op1::CTGet(0), Op1::End, Op1::I32, Op1::Lit(5), Op1::Pack, Op1::Pop];
+        let body = [&[Op1::Lit(42)][..], &tuple, &package, &[Op1::I32ToU8, Op1::Halt]].concat();
+        for padding in [0].into_iter().chain(1000..1030) {
+            assert_eq!(run_padded(padding, &body), 42, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn halt_exits_with_an_integers_low_byte() {
+        let halt_on = |status: Vec<Op1>| run_funcs(vec![(vec![Op1::Func(0)], [status, vec![Op1::Halt]].concat())]);
+        assert_eq!(halt_on(vec![Op1::U8Lit(7)]), 7);
+        assert_eq!(halt_on(vec![Op1::Lit(300)]), 44);
+        assert_eq!(halt_on(vec![Op1::LitI64(-1)]), 255);
+        assert_eq!(halt_on(vec![Op1::Lit(7), Op1::Tuple(0), Op1::Malloc]), 0);
+    }
+
+    #[test]
+    fn dropped_handles_are_popped_across_chunk_edges() {
+        // forall r. (i32, handle r) -> 0, which drops the shared handle off the top and halts with the i32
+        let decl = vec![Op1::Rgn, Op1::I32, Op1::CTGet(1), Op1::Handle, Op1::Func(2), Op1::End];
+        let drop = (decl, vec![Op1::DropHandle, Op1::I32ToU8, Op1::Halt]);
+        for padding in 1000..1030 {
+            let main = [vec![Op1::Lit(7); padding], vec![Op1::NewRgn(64), Op1::CTGet(0), Op1::GlobalFunc(1), Op1::Call]].concat();
+            assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main), drop.clone()]), 7, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn clean_compacts_the_stack_before_a_call() {
+        // the u8, i64 and i32 between the 5 and the 37 are dropped, so the two meet for the add
+        let body = [
+            Op1::Lit(5),
+            Op1::U8Lit(1),
+            Op1::LitI64(2),
+            Op1::Lit(3),
+            Op1::Lit(37),
+            Op1::Clean(3),
+            Op1::Add,
+            Op1::GlobalFunc(1),
+            Op1::Call,
+        ];
+        let halt = (vec![Op1::I32, Op1::Func(1)], vec![Op1::I32ToU8, Op1::Halt]);
+        for padding in [0].into_iter().chain(1000..1030) {
+            let main = [vec![Op1::Lit(7); padding], body.to_vec()].concat();
+            assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main), halt.clone()]), 42, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn sums_stay_in_one_chunk() {
+        // a u8 injected into a sum with an i32 variant gets 3 bytes of padding and two words on top,
+        // which some of these paddings push past the end of a stack chunk
+        for padding in 1000..1030 {
+            let main = [
+                vec![Op1::Lit(7); padding],
+                vec![Op1::U8Lit(42), Op1::I32, Op1::U8, Op1::Sum(2), Op1::Inj(0)],
+                vec![Op1::GlobalFunc(2), Op1::GlobalFunc(1), Op1::Case(2)],
+            ];
+            let status = run_funcs(vec![
+                (vec![Op1::Func(0)], main.concat()),
+                (vec![Op1::U8, Op1::Func(1)], vec![Op1::Halt]),
+                (vec![Op1::I32, Op1::Func(1)], vec![Op1::U8Lit(0), Op1::Halt]),
+            ]);
+            assert_eq!(status, 42, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn dup_and_swap_values_of_different_sizes() {
+        // 5 and 3 swap places, the 5 is doubled to 10, and they swap back to be multiplied
+        let body = [Op1::Lit(5), Op1::LitI64(3), Op1::Swap, Op1::Dup, Op1::Add, Op1::Swap, Op1::I64ToI32, Op1::Mul, Op1::I32ToU8, Op1::Halt];
+        for padding in [0].into_iter().chain(1000..1030) {
+            let main = [vec![Op1::Lit(7); padding], body.to_vec()].concat();
+            assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], main)]), 30, "with {} i32s below", padding);
+        }
+    }
+
+    #[test]
+    fn tuples_on_the_heap_need_no_handle() {
+        // (30, 12) is allocated on the heap, read back through a projection and a deref, and summed
+        let pair = [Op1::Heap, Op1::U8, Op1::I32, Op1::Tuple(2), Op1::Ptr, Op1::Malloc];
+        let inits = [Op1::Lit(30), Op1::Init(0), Op1::U8Lit(12), Op1::Init(1)];
+        let read = [Op1::Get(0), Op1::Proj(0), Op1::Swap, Op1::Deref, Op1::Proj(1), Op1::U8ToI32, Op1::Add, Op1::I32ToU8, Op1::Halt];
+        assert_eq!(run_funcs(vec![(vec![Op1::Func(0)], [&pair[..], &inits, &read].concat())]), 42);
+    }
+
+    #[test]
+    fn empty_tuples_take_no_space() {
+        // an empty tuple on the stack, copied and stored as the first field of (), i32 on the heap before a 42
+        let empty = [Op1::Tuple(0), Op1::Malloc, Op1::Get(0), Op1::Pop];
+        let pair = [Op1::Heap, Op1::I32, Op1::Tuple(0), Op1::Tuple(2), Op1::Ptr, Op1::Malloc];
+        let inits = [Op1::Swap, Op1::Init(0), Op1::Lit(42), Op1::Init(1)];
+        let read = [Op1::Get(0), Op1::Proj(0), Op1::Pop, Op1::Proj(1), Op1::I32ToU8, Op1::Halt];
+        let body = [&empty[..], &pair, &inits, &read].concat();
+        let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        // the peephole pass drops the zero-size ops, so the VM also has to handle them when they're kept
+        for peephole in [false, true] {
+            let opts = verify::VerifyOptions { peephole, ..verify::VerifyOptions::default() };
+            let program = verify::go(vec![], &forward_decs, vec![Stmt1::Func(0, 0, body.clone())], &opts).unwrap();
+            let status = go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default());
+            assert_eq!(status, Ok(42), "peephole: {}", peephole);
+        }
+    }
+
+    #[test]
+    fn peephole_shrinks_functions_without_changing_them() {
+        // a dup undone by a pop, a nop, and a swap undone by a swap, around 5 * 8
+        let body = vec![Op1::Lit(5), Op1::Dup, Op1::Pop, Op1::Nop, Op1::Lit(8), Op1::Swap, Op1::Swap, Op1::Mul, Op1::I32ToU8, Op1::Halt];
+        let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        let verify_with = |peephole| {
+            let opts = verify::VerifyOptions { peephole, ..verify::VerifyOptions::default() };
+            verify::go(vec![], &forward_decs, vec![Stmt1::Func(0, 0, body.clone())], &opts).unwrap()
+        };
+        let len = |program: &IRProgram| {
+            let Stmt2::Func(_, _, ops) = &program.funcs[0];
+            ops.len()
+        };
+        let (plain, optimized) = (verify_with(false), verify_with(true));
+        assert_eq!(len(&optimized), len(&plain) - 4);
+        for program in [plain, optimized] {
+            let status = go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default());
+            assert_eq!(status, Ok(40));
+        }
+    }
+
+    #[test]
+    fn peephole_renumbers_jumps_into_removed_ops() {
+        // the count-to-5 loop with a 9 under the count, where the loop header and the break target
+        // are each a dup undone by a pop, a swap undone by a swap, or a unit made and dropped
+        let removable = [
+            vec![Op1::Dup, Op1::Pop],
+            vec![Op1::Swap, Op1::Swap],
+            vec![Op1::Tuple(0), Op1::Malloc, Op1::Pop],
+        ];
+        let forward_decs = [ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])];
+        for header in &removable {
+            for after in &removable {
+                let count = vec![Op1::Lit(1), Op1::Add, Op1::Get(0), Op1::Lit(-5), Op1::Add, Op1::Continue];
+                let body = [
+                    vec![Op1::Lit(9), Op1::Lit(0), Op1::Loop],
+                    header.clone(),
+                    count,
+                    vec![Op1::Lit(1), Op1::Break, Op1::LoopEnd],
+                    after.clone(),
+                    vec![Op1::I32ToU8, Op1::Halt],
+                ]
+                .concat();
+                let verify_with = |peephole| {
+                    let opts = verify::VerifyOptions { peephole, ..verify::VerifyOptions::default() };
+                    verify::go(vec![], &forward_decs, vec![Stmt1::Func(0, 0, body.clone())], &opts).unwrap()
+                };
+                let len = |program: &IRProgram| {
+                    let Stmt2::Func(_, _, ops) = &program.funcs[0];
+                    ops.len()
+                };
+                let (plain, optimized) = (verify_with(false), verify_with(true));
+                assert!(len(&optimized) < len(&plain), "header {:?}, after {:?}", header, after);
+                for program in [plain, optimized] {
+                    let status = go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default());
+                    assert_eq!(status, Ok(5), "header {:?}, after {:?}", header, after);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn each_run_gets_a_fresh_heap() {
+        // more than half of the 1 MiB heap, so a heap kept from the first run couldn't fit the second allocation
+        let run = || run_ops(vec![Op2::HeapHandle, Op2::Malloc(600_000), Op2::Pop(16), Op2::U8Lit(0), Op2::Halt]);
+        assert_eq!(run(), Ok(0));
+        assert_eq!(run(), Ok(0));
+    }
+
+    /// Apply an i32 op to `b` and `a` (`a` on top) and halt with the low byte of the result.
+    fn i32_op(b: i32, a: i32, op: Op2) -> Result<u8, RuntimeError> {
+        run_ops(vec![Op2::Lit(b), Op2::Lit(a), op, Op2::I32ToU8, Op2::Halt])
+    }
+
+    #[test]
+    fn i32_arithmetic() {
+        let res = run_ops(vec![
+            Op2::Lit(2),
+            Op2::Lit(3),
+            Op2::AddI32,
+            Op2::Lit(4),
+            Op2::MulI32,
+            Op2::I32ToU8,
+            Op2::Halt,
+        ]);
+        assert_eq!(res, Ok(20));
+        assert_eq!(i32_op(7, 2, Op2::SubI32), Ok(5));
+        assert_eq!(i32_op(7, 2, Op2::DivI32), Ok(3));
+        assert_eq!(i32_op(7, 2, Op2::ModuloI32), Ok(1));
+        assert_eq!(i32_op(7, 0, Op2::DivI32), Err(RuntimeError::DivByZero));
+        assert_eq!(i32_op(7, 0, Op2::ModuloI32), Err(RuntimeError::DivByZero));
+    }
+
+    #[test]
+    fn wide_arithmetic() {
+        let halt = |ops: Vec<Op1>, to_i64: Vec<Op1>| {
+            let main = [ops, to_i64, vec![Op1::I64ToI32, Op1::I32ToU8, Op1::Halt]].concat();
+            let program = verify::go_default(vec![], &[ForwardDec::Func(0, 0, Visibility::Local, vec![Op1::Func(0)])], vec![Stmt1::Func(0, 0, main)]).unwrap();
+            go(vec![program], &mut Output { out: &mut std::io::sink(), err: &mut std::io::sink() }, &Limits::default())
+        };
+        // 3e9 * 2 / 1e9 only fits in 64 bits along the way
+        let i64s = vec![Op1::LitI64(3000000000), Op1::LitI64(2), Op1::Mul, Op1::LitI64(1000000000), Op1::Div];
+        assert_eq!(halt(i64s, vec![]), Ok(6));
+        let f64 = |x: f64| Op1::LitF64(x.to_bits());
+        let f64s = vec![f64(7.5), f64(2.0), Op1::Mul, f64(0.5), Op1::Sub];
+        assert_eq!(halt(f64s, vec![Op1::F64ToI64]), Ok(14));
+        assert_eq!(halt(vec![Op1::LitI64(1), Op1::LitI64(0), Op1::Div], vec![]), Err(RuntimeError::DivByZero));
+        assert_eq!(halt(vec![Op1::LitI64(1), Op1::LitI64(0), Op1::Modulo], vec![]), Err(RuntimeError::DivByZero));
+    }
+
+    #[test]
+    fn i32_overflow_wraps() {
+        // i32::MIN's low byte is 0 and i32::MAX's is 255
+        assert_eq!(i32_op(i32::MIN, -1, Op2::DivI32), Ok(0));
+        assert_eq!(i32_op(i32::MIN, -1, Op2::ModuloI32), Ok(0));
+        assert_eq!(i32_op(i32::MIN, 1, Op2::SubI32), Ok(255));
+        assert_eq!(i32_op(i32::MAX, 1, Op2::AddI32), Ok(0));
+        assert_eq!(i32_op(i32::MAX, 2, Op2::MulI32), Ok(254));
+    }
+}